@@ -0,0 +1,19 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use std::path::Path;
+use voi_synth::Context;
+use vstlisp::lisp;
+
+fuzz_target!(|data: &[u8]| {
+    let source = match std::str::from_utf8(data) {
+        Ok(source) => source,
+        Err(_) => return,
+    };
+
+    let mut ctx = Context::new(3, 256).unwrap();
+
+    if let Ok((id, _)) = lisp::create_synth(&mut ctx, source, Path::new("")) {
+        ctx.remove_synth(id);
+    }
+});
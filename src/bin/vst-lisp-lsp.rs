@@ -0,0 +1,364 @@
+use vstlisp::VstResult;
+use vstlisp::lisp::{self, Span, analysis::{self, Analysis, CompletionKind, DefinitionKind}};
+
+use failure::format_err;
+use lsp_server::{Connection, ErrorCode, Message, Notification, Request, Response};
+use lsp_types::*;
+use lsp_types::notification::{Notification as _, DidOpenTextDocument, DidChangeTextDocument, DidCloseTextDocument, PublishDiagnostics};
+use lsp_types::request::{Request as _, Completion, HoverRequest, GotoDefinition, DocumentSymbolRequest};
+
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::process;
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+// Evaluation needs a sample rate, but nothing it reports depends on it
+const SAMPLE_RATE: f32 = 44100.0;
+
+// Editors send a change for every key typed, so evaluation waits for a pause this long
+const EVALUATION_DELAY: Duration = Duration::from_millis(300);
+
+type Documents = HashMap<Url, Document>;
+
+/// An open document. Parsing and checking are cheap, so requests redo them, and only
+/// evaluation happens away from the message loop
+#[derive(Clone)]
+struct Document {
+    source: String,
+    dir: PathBuf,
+    version: u64,
+}
+
+impl Document {
+    fn analysis(&self) -> Analysis<'_> { analysis::analyse(&self.source) }
+}
+
+// The latest version of each open document. It's locked while diagnostics are published, so
+// the evaluator can't publish over diagnostics for a newer version
+type Versions = Arc<Mutex<HashMap<Url, u64>>>;
+
+struct Evaluation {
+    uri: Url,
+    document: Document,
+}
+
+fn main() {
+    if let Err(err) = run() {
+        eprintln!("error: {}", err);
+        process::exit(1);
+    }
+}
+
+fn run() -> VstResult<()> {
+    let (connection, io_threads) = Connection::stdio();
+
+    let capabilities = ServerCapabilities {
+        text_document_sync: Some(TextDocumentSyncCapability::Kind(TextDocumentSyncKind::FULL)),
+        completion_provider: Some(CompletionOptions::default()),
+        hover_provider: Some(HoverProviderCapability::Simple(true)),
+        definition_provider: Some(OneOf::Left(true)),
+        document_symbol_provider: Some(OneOf::Left(true)),
+        ..ServerCapabilities::default()
+    };
+
+    connection.initialize(serde_json::to_value(capabilities)?)?;
+
+    let mut documents = Documents::new();
+    let versions = Versions::default();
+
+    let sender = connection.sender.clone();
+    let (evaluations, evaluator) = spawn_evaluator(versions.clone(), move |message| { let _ = sender.send(message); });
+    let mut server = Server { connection: &connection, versions, evaluations, next_version: 0 };
+
+    for message in &connection.receiver {
+        match message {
+            Message::Request(request) => {
+                if connection.handle_shutdown(&request)? {
+                    break
+                }
+
+                let response = handle_request(&documents, request);
+                connection.sender.send(Message::Response(response))?;
+            }
+
+            Message::Notification(notification) => server.handle_notification(&mut documents, notification)?,
+            Message::Response(_) => {}
+        }
+    }
+
+    // The writer thread only finishes once every sender is gone, including the evaluator's
+    drop(server);
+    evaluator.join().map_err(|_| format_err!("The evaluator thread panicked"))?;
+    drop(connection);
+    io_threads.join()?;
+    Ok(())
+}
+
+
+struct Server<'a> {
+    connection: &'a Connection,
+    versions: Versions,
+    evaluations: mpsc::Sender<Evaluation>,
+    next_version: u64,
+}
+
+impl Server<'_> {
+    fn handle_notification(&mut self, documents: &mut Documents, notification: Notification) -> VstResult<()> {
+        let (uri, text) = match notification.method.as_str() {
+            DidOpenTextDocument::METHOD => {
+                let params: DidOpenTextDocumentParams = serde_json::from_value(notification.params)?;
+                (params.text_document.uri, Some(params.text_document.text))
+            }
+
+            DidChangeTextDocument::METHOD => {
+                // Changes always hold the whole document, since that's the only sync we offer
+                let params: DidChangeTextDocumentParams = serde_json::from_value(notification.params)?;
+
+                match params.content_changes.into_iter().last() {
+                    Some(change) => (params.text_document.uri, Some(change.text)),
+                    None => return Ok(()),
+                }
+            }
+
+            DidCloseTextDocument::METHOD => {
+                let params: DidCloseTextDocumentParams = serde_json::from_value(notification.params)?;
+                (params.text_document.uri, None)
+            }
+
+            _ => return Ok(()),
+        };
+
+        let mut versions = self.versions.lock().unwrap();
+
+        let text = match text {
+            Some(text) => text,
+            None => {
+                // Closed documents shouldn't leave errors behind
+                documents.remove(&uri);
+                versions.remove(&uri);
+                self.connection.sender.send(publish_diagnostics(uri, "", &[]))?;
+                return Ok(())
+            }
+        };
+
+        self.next_version += 1;
+        let document = Document { source: text, dir: document_dir(&uri), version: self.next_version };
+        versions.insert(uri.clone(), document.version);
+
+        let diagnostics = document.analysis().diagnostics;
+        self.connection.sender.send(publish_diagnostics(uri.clone(), &document.source, &diagnostics))?;
+
+        if diagnostics.is_empty() {
+            self.evaluations.send(Evaluation { uri: uri.clone(), document: document.clone() })?;
+        }
+
+        documents.insert(uri, document);
+        Ok(())
+    }
+}
+
+fn publish_diagnostics(uri: Url, source: &str, diagnostics: &[lisp::Diagnostic]) -> Message {
+    let diagnostics = diagnostics.iter()
+        .map(|d| Diagnostic {
+            range: range(source, d.span),
+            severity: Some(DiagnosticSeverity::ERROR),
+            source: Some("vst-lisp".into()),
+            message: d.message.clone(),
+            ..Diagnostic::default()
+        })
+        .collect();
+
+    let params = PublishDiagnosticsParams { uri, diagnostics, version: None };
+    Message::Notification(Notification::new(PublishDiagnostics::METHOD.into(), params))
+}
+
+// Evaluates documents that check, which can mean loading files and building large graphs, so
+// it happens here instead of holding up the message loop. Once typing pauses, only the latest
+// version of each document is evaluated, and its errors are only published if it's still the
+// latest. Stops once the sender is dropped
+fn spawn_evaluator(versions: Versions, publish: impl Fn(Message) + Send + 'static) -> (mpsc::Sender<Evaluation>, thread::JoinHandle<()>) {
+    let (evaluations, received) = mpsc::channel::<Evaluation>();
+
+    let evaluator = thread::spawn(move || {
+        while let Ok(first) = received.recv() {
+            let mut pending = HashMap::new();
+            pending.insert(first.uri.clone(), first.document);
+
+            loop {
+                match received.recv_timeout(EVALUATION_DELAY) {
+                    Ok(evaluation) => { pending.insert(evaluation.uri, evaluation.document); }
+                    Err(mpsc::RecvTimeoutError::Timeout) => break,
+                    Err(mpsc::RecvTimeoutError::Disconnected) => return,
+                }
+            }
+
+            for (uri, document) in pending {
+                let diagnostics = document.analysis().evaluate(SAMPLE_RATE, &document.dir);
+
+                let versions = versions.lock().unwrap();
+                if versions.get(&uri) == Some(&document.version) {
+                    publish(publish_diagnostics(uri, &document.source, &diagnostics));
+                }
+            }
+        }
+    });
+
+    (evaluations, evaluator)
+}
+
+
+fn handle_request(documents: &Documents, request: Request) -> Response {
+    let result = match request.method.as_str() {
+        Completion::METHOD => handle::<Completion>(&request, |params| {
+            let position = params.text_document_position;
+
+            with_analysis(documents, &position.text_document.uri, |source, analysis| {
+                let items = analysis.completions(offset(source, position.position)).into_iter()
+                    .map(|c| CompletionItem {
+                        label: c.label,
+                        kind: Some(completion_kind(c.kind)),
+                        detail: Some(c.detail),
+                        ..CompletionItem::default()
+                    })
+                    .collect();
+
+                Some(CompletionResponse::Array(items))
+            })
+        }),
+
+        HoverRequest::METHOD => handle::<HoverRequest>(&request, |params| {
+            let position = params.text_document_position_params;
+
+            with_analysis(documents, &position.text_document.uri, |source, analysis| {
+                let hover = analysis.hover(offset(source, position.position))?;
+
+                let mut value = format!("```lisp\n{}\n```", hover.code);
+                if let Some(doc) = hover.doc {
+                    value = format!("{}\n\n{}", value, doc);
+                }
+
+                Some(Hover {
+                    contents: HoverContents::Markup(MarkupContent { kind: MarkupKind::Markdown, value }),
+                    range: Some(range(source, hover.span)),
+                })
+            })
+        }),
+
+        GotoDefinition::METHOD => handle::<GotoDefinition>(&request, |params| {
+            let TextDocumentPositionParams { text_document, position } = params.text_document_position_params;
+            let uri = text_document.uri;
+
+            with_analysis(documents, &uri, |source, analysis| {
+                let definition = analysis.definition(offset(source, position))?;
+                let location = Location::new(uri.clone(), range(source, definition.name_span));
+
+                Some(GotoDefinitionResponse::Scalar(location))
+            })
+        }),
+
+        DocumentSymbolRequest::METHOD => handle::<DocumentSymbolRequest>(&request, |params| {
+            with_analysis(documents, &params.text_document.uri, |source, analysis| {
+                let symbols = analysis.symbols().into_iter()
+                    .map(|d| {
+                        #[allow(deprecated)]
+                        DocumentSymbol {
+                            name: d.name.to_owned(),
+                            detail: None,
+                            kind: symbol_kind(d.kind),
+                            tags: None,
+                            deprecated: None,
+                            range: range(source, d.span),
+                            selection_range: range(source, d.name_span),
+                            children: None,
+                        }
+                    })
+                    .collect();
+
+                Some(DocumentSymbolResponse::Nested(symbols))
+            })
+        }),
+
+        _ => return Response::new_err(request.id, ErrorCode::MethodNotFound as i32,
+            format!("Unsupported request {}", request.method)),
+    };
+
+    match result {
+        Ok(value) => Response::new_ok(request.id, value),
+        Err(err) => Response::new_err(request.id, ErrorCode::InvalidParams as i32, err.to_string()),
+    }
+}
+
+fn handle<R: lsp_types::request::Request>(request: &Request, f: impl FnOnce(R::Params) -> R::Result) -> VstResult<serde_json::Value> {
+    let params = serde_json::from_value(request.params.clone())?;
+    Ok(serde_json::to_value(f(params))?)
+}
+
+// Requests about documents we haven't been sent get an empty result
+fn with_analysis<T>(documents: &Documents, uri: &Url, f: impl FnOnce(&str, &Analysis) -> Option<T>) -> Option<T> {
+    let document = documents.get(uri)?;
+    f(&document.source, &document.analysis())
+}
+
+// Samples are loaded relative to the patch, when it's a file
+fn document_dir(uri: &Url) -> PathBuf {
+    uri.to_file_path().ok()
+        .and_then(|path| path.parent().map(PathBuf::from))
+        .unwrap_or_default()
+}
+
+
+fn completion_kind(kind: CompletionKind) -> CompletionItemKind {
+    match kind {
+        CompletionKind::Builtin => CompletionItemKind::FUNCTION,
+        CompletionKind::SpecialForm => CompletionItemKind::KEYWORD,
+        CompletionKind::Definition(DefinitionKind::Function) => CompletionItemKind::FUNCTION,
+        CompletionKind::Definition(_) => CompletionItemKind::VARIABLE,
+    }
+}
+
+fn symbol_kind(kind: DefinitionKind) -> SymbolKind {
+    match kind {
+        DefinitionKind::Function => SymbolKind::FUNCTION,
+        DefinitionKind::Store => SymbolKind::FIELD,
+        DefinitionKind::Value | DefinitionKind::Parameter => SymbolKind::VARIABLE,
+    }
+}
+
+
+// LSP positions count lines from zero and characters in UTF-16 code units
+
+fn position(source: &str, offset: usize) -> Position {
+    let before = &source[..offset.min(source.len())];
+    let line_start = before.rfind('\n').map_or(0, |p| p + 1);
+
+    Position::new(before.matches('\n').count() as u32, before[line_start..].encode_utf16().count() as u32)
+}
+
+fn offset(source: &str, position: Position) -> usize {
+    let mut line_start = 0;
+
+    for _ in 0..position.line {
+        match source[line_start..].find('\n') {
+            Some(end) => line_start += end + 1,
+            None => return source.len(),
+        }
+    }
+
+    let mut character = 0;
+
+    for (index, c) in source[line_start..].char_indices() {
+        if character >= position.character || c == '\n' {
+            return line_start + index
+        }
+
+        character += c.len_utf16() as u32;
+    }
+
+    source.len()
+}
+
+fn range(source: &str, span: Span) -> Range {
+    Range::new(position(source, span.start), position(source, span.end))
+}
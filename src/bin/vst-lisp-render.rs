@@ -0,0 +1,112 @@
+use vstlisp::VstResult;
+use vstlisp::lisp;
+use vstlisp::render::{self, NoteEvent, RenderOptions};
+
+use failure::{bail, format_err};
+
+use std::path::Path;
+use std::process;
+
+const USAGE: &str = "\
+Usage: vst-lisp-render <patch.lisp> <events> -o <out.wav> [options]
+
+Renders a patch to a WAV file, playing the notes from a Standard MIDI File
+or a text event list (lines of `<seconds> on <key> [velocity]` or
+`<seconds> off <key>`).
+
+Options:
+    -o, --output <path>      The WAV file to write
+    --sample-rate <hz>       Defaults to 44100
+    --length <seconds>       Defaults to one second after the last event
+    --block-size <samples>   Events are quantised to this. Defaults to 64
+    --bits <16|24|32>        32 bit output is floating point. Defaults to 32
+";
+
+struct Args {
+    patch: String,
+    events: String,
+    output: String,
+    bits: u16,
+    length: Option<f32>,
+    options: RenderOptions,
+}
+
+fn main() {
+    let args = std::env::args().skip(1).collect::<Vec<_>>();
+
+    if args.is_empty() || args.iter().any(|a| a == "-h" || a == "--help") {
+        eprint!("{}", USAGE);
+        process::exit(2);
+    }
+
+    if let Err(err) = parse_args(&args).and_then(|args| run(&args)) {
+        eprintln!("error: {}", err);
+        process::exit(1);
+    }
+}
+
+fn parse_args(args: &[String]) -> VstResult<Args> {
+    let mut positional = Vec::new();
+    let mut output = None;
+    let mut bits = 32;
+    let mut length = None;
+    let mut options = RenderOptions::default();
+
+    let mut args = args.iter();
+
+    while let Some(arg) = args.next() {
+        let mut value = || args.next()
+            .ok_or_else(|| format_err!("{} requires a value", arg));
+
+        match arg.as_str() {
+            "-o" | "--output" => output = Some(value()?.clone()),
+            "--sample-rate" => options.sample_rate = value()?.parse()?,
+            "--length" => length = Some(value()?.parse()?),
+            "--block-size" => options.block_size = value()?.parse()?,
+            "--bits" => bits = value()?.parse()?,
+            _ if arg.starts_with('-') => bail!("Unknown option: {}", arg),
+            _ => positional.push(arg.clone()),
+        }
+    }
+
+    if positional.len() != 2 {
+        bail!("Expected a patch and an event file\n\n{}", USAGE);
+    }
+
+    let events = positional.pop().unwrap();
+    let patch = positional.pop().unwrap();
+    let output = output.ok_or_else(|| format_err!("No output file given, use -o <out.wav>"))?;
+
+    Ok(Args { patch, events, output, bits, length, options })
+}
+
+fn run(args: &Args) -> VstResult<()> {
+    let patch = std::fs::read_to_string(&args.patch)?;
+    let events = read_events(Path::new(&args.events))?;
+
+    let length = args.length.unwrap_or_else(|| {
+        events.iter()
+            .map(|e| e.time)
+            .fold(0.0, f32::max) + 1.0
+    });
+
+    let options = RenderOptions { length, ..args.options };
+
+    // Samples the patch loads are found next to it
+    let dir = Path::new(&args.patch).parent().unwrap_or_else(|| Path::new(""));
+    let graph = lisp::compile_in(&patch, options.sample_rate, dir)?;
+
+    let samples = render::render_graph(&graph, &events, &options)?;
+    render::write_wav(Path::new(&args.output), &samples, options.sample_rate as u32, args.bits)
+}
+
+fn read_events(path: &Path) -> VstResult<Vec<NoteEvent>> {
+    let data = std::fs::read(path)?;
+
+    // Standard MIDI Files start with a header chunk, anything else is an event list
+    if data.starts_with(b"MThd") {
+        render::read_midi_file(&data)
+    } else {
+        render::parse_event_list(&String::from_utf8(data)?)
+    }
+}
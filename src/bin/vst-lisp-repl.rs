@@ -0,0 +1,169 @@
+use vstlisp::VstResult;
+use vstlisp::lisp::{self, Session};
+use vstlisp::render::{self, NoteEvent, NoteEventKind, RenderOptions};
+
+use failure::{bail, format_err};
+
+use std::io::{self, BufRead, Write};
+use std::path::Path;
+use std::process;
+
+const USAGE: &str = "\
+Usage: vst-lisp-repl [--sample-rate <hz>]
+
+Evaluates patch forms interactively. Bindings and synth nodes persist
+between inputs, and forms spanning several lines are read until their
+brackets balance.
+";
+
+const HELP: &str = "\
+Commands:
+    :help                       Shows this message
+    :render <seconds> [path]    Renders the current patch holding middle C,
+                                to repl.wav by default
+    :graph [dot|json]           Prints the current synth graph
+    :reset                      Forgets everything evaluated so far
+    :quit, :q                   Exits
+";
+
+fn main() {
+    let mut sample_rate = 44100.0;
+    let mut args = std::env::args().skip(1);
+
+    while let Some(arg) = args.next() {
+        let result = match arg.as_str() {
+            "--sample-rate" => args.next()
+                .ok_or_else(|| format_err!("--sample-rate requires a value"))
+                .and_then(|v| Ok(v.parse::<f32>()?)),
+
+            _ => {
+                eprint!("{}", USAGE);
+                process::exit(2);
+            }
+        };
+
+        match result {
+            Ok(rate) => sample_rate = rate,
+            Err(err) => {
+                eprintln!("error: {}", err);
+                process::exit(1);
+            }
+        }
+    }
+
+    if let Err(err) = repl(sample_rate) {
+        eprintln!("error: {}", err);
+        process::exit(1);
+    }
+}
+
+fn repl(sample_rate: f32) -> VstResult<()> {
+    let mut session = Session::new(sample_rate);
+    let mut input = String::new();
+
+    let stdin = io::stdin();
+    let mut lines = stdin.lock().lines();
+
+    loop {
+        print!("{}", if input.is_empty() { "> " } else { ". " });
+        io::stdout().flush()?;
+
+        let line = match lines.next() {
+            Some(line) => line?,
+            None => break,
+        };
+
+        if input.is_empty() && line.trim_start().starts_with(':') {
+            let mut words = line.split_whitespace();
+            let command = words.next().unwrap();
+            let args = words.collect::<Vec<_>>();
+
+            match command {
+                ":quit" | ":q" => break,
+                ":help" => print!("{}", HELP),
+                ":reset" => session = Session::new(sample_rate),
+
+                _ => if let Err(err) = run_command(&session, sample_rate, command, &args) {
+                    println!("error: {}", err);
+                }
+            }
+
+            continue
+        }
+
+        input.push_str(&line);
+        input.push('\n');
+
+        if bracket_depth(&input) > 0 {
+            continue
+        }
+
+        match session.evaluate(input.trim_end()) {
+            Ok(results) => for result in results {
+                println!("{}", result);
+            }
+
+            Err(err) => println!("error: {}", err),
+        }
+
+        input.clear();
+    }
+
+    Ok(())
+}
+
+fn run_command(session: &Session, sample_rate: f32, command: &str, args: &[&str]) -> VstResult<()> {
+    match command {
+        ":render" => {
+            let seconds = args.get(0)
+                .ok_or_else(|| format_err!(":render requires a length in seconds"))?
+                .parse::<f32>()?;
+
+            let path = args.get(1).cloned().unwrap_or("repl.wav");
+
+            let options = RenderOptions { sample_rate, length: seconds, ..RenderOptions::default() };
+            let events = [NoteEvent { time: 0.0, kind: NoteEventKind::On { key: 60, velocity: 1.0 } }];
+
+            let samples = render::render_graph(&session.graph(), &events, &options)?;
+            render::write_wav(Path::new(path), &samples, options.sample_rate as u32, 32)?;
+
+            println!("wrote {} samples to {}", samples.len(), path);
+        }
+
+        ":graph" => {
+            let graph = session.graph();
+
+            match args.get(0).cloned().unwrap_or("dot") {
+                "dot" => print!("{}", lisp::export::to_dot(&graph, session.source())),
+                "json" => println!("{}", serde_json::to_string_pretty(&lisp::export::to_json(&graph, session.source()))?),
+                format => bail!("Unknown format '{}', expected 'dot' or 'json'", format),
+            }
+        }
+
+        _ => bail!("Unknown command '{}', try :help", command),
+    }
+
+    Ok(())
+}
+
+/// How many brackets are left open, ignoring comments and strings the way the parser does
+fn bracket_depth(input: &str) -> i32 {
+    let mut depth = 0;
+    let mut in_comment = false;
+    let mut in_string = false;
+
+    for c in input.chars() {
+        match c {
+            '"' if !in_comment => in_string = !in_string,
+            _ if in_string => {}
+            '\n' => in_comment = false,
+            _ if in_comment => {}
+            ';' => in_comment = true,
+            '(' | '[' => depth += 1,
+            ')' | ']' => depth -= 1,
+            _ => {}
+        }
+    }
+
+    depth
+}
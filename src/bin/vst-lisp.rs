@@ -0,0 +1,181 @@
+use vstlisp::VstResult;
+use vstlisp::lisp;
+use vstlisp::render::{self, NoteEvent, NoteEventKind, RenderOptions};
+
+use failure::{bail, format_err};
+
+use std::io::{self, Read};
+use std::path::Path;
+use std::process;
+
+const USAGE: &str = "\
+Usage: vst-lisp <command> [options]
+
+Commands:
+    graph [--format dot|json] [--sample-rate <hz>] <patch.lisp>
+        Prints the compiled synth graph of a patch
+
+    docs [-o <reference.md>] [--audio <dir>]
+        Writes the Markdown language reference. With --audio, each example is
+        rendered to a WAV file in dir, holding a note for half a second
+
+    fmt [--check] [<patch.lisp>...]
+        Reformats patches in place, or stdin to stdout when no files are given.
+        With --check, lists the files that would change instead
+";
+
+fn main() {
+    let args = std::env::args().skip(1).collect::<Vec<_>>();
+
+    let result = match args.first().map(String::as_str) {
+        Some("graph") => graph(&args[1..]),
+        Some("docs") => docs(&args[1..]),
+        Some("fmt") => fmt(&args[1..]),
+
+        _ => {
+            eprint!("{}", USAGE);
+            process::exit(2);
+        }
+    };
+
+    if let Err(err) = result {
+        eprintln!("error: {}", err);
+        process::exit(1);
+    }
+}
+
+fn graph(args: &[String]) -> VstResult<()> {
+    let mut format = "dot";
+    let mut sample_rate = 44100.0;
+    let mut path = None;
+
+    let mut args = args.iter();
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--format" => {
+                format = args.next()
+                    .map(String::as_str)
+                    .ok_or_else(|| format_err!("--format requires a value"))?;
+            }
+
+            "--sample-rate" => {
+                sample_rate = args.next()
+                    .ok_or_else(|| format_err!("--sample-rate requires a value"))?
+                    .parse()?;
+            }
+
+            _ if path.is_none() => path = Some(arg),
+            _ => bail!("Unexpected argument: {}", arg),
+        }
+    }
+
+    let path = path.ok_or_else(|| format_err!("No patch file given\n\n{}", USAGE))?;
+    let source = std::fs::read_to_string(path)?;
+    let dir = Path::new(path).parent().unwrap_or_else(|| Path::new(""));
+    let graph = lisp::compile_in(&source, sample_rate, dir)?;
+
+    match format {
+        "dot" => print!("{}", lisp::export::to_dot(&graph, &source)),
+        "json" => println!("{}", serde_json::to_string_pretty(&lisp::export::to_json(&graph, &source))?),
+        _ => bail!("Unknown format '{}', expected 'dot' or 'json'", format),
+    }
+
+    Ok(())
+}
+
+fn docs(args: &[String]) -> VstResult<()> {
+    let mut output = None;
+    let mut audio_dir = None;
+
+    let mut args = args.iter();
+
+    while let Some(arg) = args.next() {
+        let mut value = || args.next()
+            .ok_or_else(|| format_err!("{} requires a value", arg));
+
+        match arg.as_str() {
+            "-o" | "--output" => output = Some(value()?),
+            "--audio" => audio_dir = Some(value()?),
+            _ => bail!("Unexpected argument: {}", arg),
+        }
+    }
+
+    if let Some(dir) = audio_dir {
+        std::fs::create_dir_all(dir)?;
+
+        let options = RenderOptions::default();
+        let events = [
+            NoteEvent { time: 0.0, kind: NoteEventKind::On { key: 64, velocity: 1.0 } },
+            NoteEvent { time: 0.5, kind: NoteEventKind::Off { key: 64 } },
+        ];
+
+        for (slug, example) in lisp::reference::examples() {
+            let samples = render::render(example, &events, &options)
+                .map_err(|e| format_err!("Couldn't render the example for {}: {}", slug, e))?;
+
+            let path = Path::new(dir).join(format!("{}.wav", slug));
+            render::write_wav(&path, &samples, options.sample_rate as u32, 16)?;
+        }
+    }
+
+    let reference = lisp::reference::markdown(audio_dir.map(String::as_str));
+
+    match output {
+        Some(path) => std::fs::write(path, reference)?,
+        None => print!("{}", reference),
+    }
+
+    Ok(())
+}
+
+fn fmt(args: &[String]) -> VstResult<()> {
+    let check = args.iter().any(|a| a == "--check");
+    let paths = args.iter()
+        .filter(|a| *a != "--check")
+        .collect::<Vec<_>>();
+
+    if let Some(arg) = paths.iter().find(|a| a.starts_with('-')) {
+        bail!("Unknown option: {}", arg);
+    }
+
+    if paths.is_empty() {
+        let mut source = String::new();
+        io::stdin().read_to_string(&mut source)?;
+
+        let formatted = lisp::format(&source)?;
+
+        if check && formatted != source {
+            bail!("stdin isn't formatted");
+        } else if !check {
+            print!("{}", formatted);
+        }
+
+        return Ok(())
+    }
+
+    let mut unformatted = 0;
+
+    for path in paths {
+        let source = std::fs::read_to_string(path)?;
+        let formatted = lisp::format(&source)
+            .map_err(|e| format_err!("{}: {}", path, e))?;
+
+        if formatted == source {
+            continue
+        }
+
+        if check {
+            println!("{}", path);
+            unformatted += 1;
+        } else {
+            std::fs::write(path, formatted)?;
+        }
+    }
+
+    if unformatted > 0 {
+        bail!("{} file(s) aren't formatted", unformatted);
+    }
+
+    Ok(())
+}
@@ -0,0 +1,239 @@
+//! Drives `BasicPlugin` through the `Plugin` trait the way a host would, minus the editor
+
+use vst::plugin::Plugin;
+use vst::buffer::AudioBuffer as VstAudioBuffer;
+use vst::api::{Events as VstEvents, Event as VstEvent, EventType, MidiEvent as VstMidiEvent};
+
+use crate::BasicPlugin;
+
+use std::{mem, ptr};
+
+// Buffers queued before the block size changed are still in flight after setup
+const SETTLE_BLOCKS: usize = 4;
+
+pub struct Harness {
+    plugin: BasicPlugin,
+    pub sample_rate: f32,
+    pub block_size: usize,
+}
+
+impl Harness {
+    pub fn new(patch: &str) -> Harness {
+        Harness::with_settings(patch, 44100.0, 256)
+    }
+
+    pub fn with_settings(patch: &str, sample_rate: f32, block_size: usize) -> Harness {
+        let mut plugin = BasicPlugin::new();
+        plugin.set_sample_rate(sample_rate);
+        plugin.set_block_size(block_size as i64);
+        plugin.load_bank_data(patch.as_bytes());
+
+        let mut harness = Harness { plugin, sample_rate, block_size };
+        harness.process_blocks(SETTLE_BLOCKS);
+        harness
+    }
+
+    pub fn is_loaded(&self) -> bool { self.plugin.model.is_some() }
+
+    pub fn load(&mut self, patch: &str) {
+        self.plugin.load_bank_data(patch.as_bytes());
+    }
+
+    /// Sends a single raw MIDI message, the same way a host would
+    pub fn midi(&mut self, data: [u8; 3]) {
+        let mut event = VstMidiEvent {
+            event_type: EventType::Midi,
+            byte_size: mem::size_of::<VstMidiEvent>() as i32,
+            delta_frames: 0,
+            flags: 0,
+            note_length: 0,
+            note_offset: 0,
+            midi_data: data,
+            _midi_reserved: 0,
+            detune: 0,
+            note_off_velocity: 0,
+            _reserved1: 0,
+            _reserved2: 0,
+        };
+
+        let events = VstEvents {
+            num_events: 1,
+            _reserved: 0,
+            events: [&mut event as *mut VstMidiEvent as *mut VstEvent, ptr::null_mut()],
+        };
+
+        self.plugin.process_events(&events);
+    }
+
+    pub fn note_on(&mut self, key: u8, velocity: u8) { self.midi([0x90, key, velocity]) }
+    pub fn note_off(&mut self, key: u8) { self.midi([0x80, key, 0]) }
+
+    pub fn process_blocks(&mut self, blocks: usize) -> Vec<f32> {
+        let mut output = Vec::with_capacity(blocks * self.block_size);
+        let mut block = vec![0.0f32; self.block_size];
+
+        for _ in 0..blocks {
+            // Anything the plugin doesn't write should show up as silence
+            for sample in block.iter_mut() { *sample = 0.0 }
+
+            {
+                let inputs: [*const f32; 0] = [];
+                let mut outputs = [block.as_mut_ptr()];
+
+                let mut buffer = unsafe {
+                    VstAudioBuffer::from_raw(0, 1, inputs.as_ptr(), outputs.as_mut_ptr(), self.block_size)
+                };
+
+                self.plugin.process(&mut buffer);
+            }
+
+            output.extend_from_slice(&block);
+        }
+
+        output
+    }
+
+    pub fn process_seconds(&mut self, seconds: f32) -> Vec<f32> {
+        let samples = (seconds * self.sample_rate).ceil() as usize;
+        self.process_blocks((samples + self.block_size - 1) / self.block_size)
+    }
+}
+
+
+pub fn rms(samples: &[f32]) -> f32 {
+    if samples.is_empty() {
+        return 0.0
+    }
+
+    let sum: f32 = samples.iter().map(|s| s * s).sum();
+    (sum / samples.len() as f32).sqrt()
+}
+
+/// Estimates the fundamental of a simple periodic signal from its rising zero crossings
+pub fn zero_crossing_pitch(samples: &[f32], sample_rate: f32) -> f32 {
+    let crossings = samples.windows(2)
+        .filter(|w| w[0] < 0.0 && w[1] >= 0.0)
+        .count();
+
+    crossings as f32 * sample_rate / samples.len() as f32
+}
+
+pub fn is_silent(samples: &[f32]) -> bool {
+    samples.iter().all(|s| s.abs() < 1.0e-4)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PLUCK: &str = "
+        (let env (env-ar 0.005 0.05 (key-vel)))
+        (output (* env (sin (key-freq))))
+    ";
+
+    #[test]
+    fn silent_without_notes() {
+        let mut harness = Harness::new(PLUCK);
+        assert!(harness.is_loaded());
+        assert!(is_silent(&harness.process_seconds(0.5)));
+    }
+
+    #[test]
+    fn note_on_plays_at_key_pitch() {
+        let mut harness = Harness::new(PLUCK);
+
+        // The voice allocator tunes key 64 to 440hz
+        harness.note_on(64, 127);
+        harness.process_seconds(0.05);
+        let held = harness.process_seconds(0.5);
+
+        let level = rms(&held);
+        assert!(level > 0.5 && level < 0.8, "rms of a full scale sine should be ~0.707, got {}", level);
+
+        let pitch = zero_crossing_pitch(&held, harness.sample_rate);
+        assert!((pitch - 440.0).abs() < 5.0, "expected 440hz, got {}", pitch);
+    }
+
+    #[test]
+    fn velocity_zero_note_on_releases() {
+        let mut harness = Harness::new(PLUCK);
+
+        harness.note_on(64, 127);
+        assert!(rms(&harness.process_seconds(0.2)) > 0.1);
+
+        harness.midi([0x90, 64, 0]);
+        harness.process_seconds(0.2);
+        assert!(is_silent(&harness.process_seconds(0.2)));
+    }
+
+    #[test]
+    fn silent_after_release() {
+        let mut harness = Harness::new(PLUCK);
+
+        harness.note_on(64, 127);
+        assert!(rms(&harness.process_seconds(0.2)) > 0.1);
+
+        harness.note_off(64);
+        harness.process_seconds(0.2);
+        assert!(is_silent(&harness.process_seconds(0.2)));
+    }
+
+    #[test]
+    fn other_channels_and_messages() {
+        let mut harness = Harness::new(PLUCK);
+
+        // Note on, channel 16
+        harness.midi([0x9F, 64, 100]);
+        assert!(rms(&harness.process_seconds(0.2)) > 0.1);
+
+        // Control change and pitch bend are ignored
+        harness.midi([0xB0, 1, 127]);
+        harness.midi([0xE0, 0, 64]);
+        assert!(rms(&harness.process_seconds(0.2)) > 0.1);
+
+        // Note off, channel 16
+        harness.midi([0x8F, 64, 0]);
+        harness.process_seconds(0.2);
+        assert!(is_silent(&harness.process_seconds(0.2)));
+    }
+
+    #[test]
+    fn invalid_patches_dont_load() {
+        let mut harness = Harness::new("(output (sin");
+        assert!(!harness.is_loaded());
+
+        // Notes without a model are dropped, rather than panicking
+        harness.note_on(64, 127);
+        assert!(is_silent(&harness.process_seconds(0.1)));
+
+        harness.load(PLUCK);
+        assert!(harness.is_loaded());
+    }
+
+    #[test]
+    fn reloading_replaces_the_synth() {
+        let mut harness = Harness::new(PLUCK);
+        harness.load("(gain 0.5) (output (sqr 110))");
+
+        let output = harness.process_seconds(0.5);
+        assert!((rms(&output) - 0.5).abs() < 0.05, "rms was {}", rms(&output));
+        assert!((zero_crossing_pitch(&output, harness.sample_rate) - 110.0).abs() < 5.0);
+
+        // Bank data round trips the source
+        assert_eq!(harness.plugin.get_bank_data(), b"(gain 0.5) (output (sqr 110))".to_vec());
+    }
+
+    #[test]
+    fn default_patch_plays() {
+        let mut harness = Harness::new(include_str!("../assets/default.lisp"));
+
+        harness.note_on(64, 127);
+        harness.process_seconds(0.2);
+        assert!(rms(&harness.process_seconds(0.2)) > 0.05);
+
+        harness.note_off(64);
+        harness.process_seconds(1.0);
+        assert!(is_silent(&harness.process_seconds(0.2)));
+    }
+}
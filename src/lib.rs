@@ -8,12 +8,17 @@ use vst::api::Supported as VstSupported;
 use vst::api::Events as VstEvents;
 
 use std::sync::mpsc;
+use std::path::PathBuf;
 
 mod model;
 mod view;
-mod lisp;
+pub mod lisp;
+pub mod render;
 mod voice_allocator;
 
+#[cfg(test)]
+mod harness;
+
 use self::view::View;
 use self::model::Model;
 
@@ -26,7 +31,8 @@ use std::sync::Once;
 static LOGGER_INIT: Once = Once::new();
 
 pub enum AudioCommand {
-    SetModel(String),
+    // The patch source, and the directory files it loads are relative to
+    SetModel(String, PathBuf),
 }
 
 struct BasicPlugin {
@@ -91,7 +97,11 @@ impl Plugin for BasicPlugin {
     // fn load_preset_data(&mut self, data: &[u8]) { log::info!("load_preset_data"); }
     fn load_bank_data(&mut self, data: &[u8]) {
         match String::from_utf8(data.to_owned()) {
-            Ok(source) => self.load_model(source),
+            Ok(source) => {
+                // Bank data is only the source, so files stay relative to the current patch
+                let dir = self.model.as_ref().map(|m| m.dir.clone()).unwrap_or_default();
+                self.load_model(source, dir)
+            }
             Err(_) => log::error!("load_bank_data got invalid data")
         }
     }
@@ -177,31 +187,39 @@ impl Plugin for BasicPlugin {
 
 impl Default for BasicPlugin {
     fn default() -> Self {
-        use std::fs;
-        
-        LOGGER_INIT.call_once(|| {
-            use simplelog::*;
-            use std::fs::File;
+        LOGGER_INIT.call_once(init_logging);
+        BasicPlugin::new()
+    }
+}
 
-            std::env::set_var("RUST_BACKTRACE", "1");
 
-            let log_dir = dirs::data_dir().unwrap().join("_manpat");
+fn init_logging() {
+    use simplelog::*;
+    use std::fs::{self, File};
 
-            fs::create_dir_all(&log_dir).unwrap();
+    std::env::set_var("RUST_BACKTRACE", "1");
 
-            if let Ok(file) = File::create(log_dir.join("vst-lisp.log")) {
-                WriteLogger::init(
-                    LevelFilter::Info,
-                    Config::default(),
-                    file
-                ).unwrap();
+    let log_dir = dirs::data_dir().unwrap().join("_manpat");
 
-                log_panics::init();
-            }
+    fs::create_dir_all(&log_dir).unwrap();
+
+    if let Ok(file) = File::create(log_dir.join("vst-lisp.log")) {
+        WriteLogger::init(
+            LevelFilter::Info,
+            Config::default(),
+            file
+        ).unwrap();
+
+        log_panics::init();
+    }
+
+    log::info!("Logging enabled");
+}
 
-            log::info!("Logging enabled");
-        });
 
+impl BasicPlugin {
+    // Doesn't touch logging, so tests can build as many as they like
+    fn new() -> Self {
         let (audio_cmd_tx, audio_cmd_rx) = mpsc::channel();
 
         let synth_ctx = voi_synth::Context::new(3, 256).unwrap();
@@ -218,10 +236,7 @@ impl Default for BasicPlugin {
             // voice_allocator: VoiceAllocator::new(synth_info.key_input),
         }
     }
-}
-
 
-impl BasicPlugin {
     fn process_midi_event(&mut self, evt: vst::event::MidiEvent) {
         let packet = evt.data;
 
@@ -257,17 +272,17 @@ impl BasicPlugin {
     fn process_audio_commands(&mut self) {
         while let Ok(audio_cmd) = self.audio_cmd_rx.try_recv() {
             match audio_cmd {
-                AudioCommand::SetModel(src) => self.load_model(src),
+                AudioCommand::SetModel(src, dir) => self.load_model(src, dir),
             }
         }
     }
 
-    fn load_model(&mut self, src: String) {
+    fn load_model(&mut self, src: String, dir: PathBuf) {
         if let Some(model) = self.model.take() {
             self.synth_ctx.remove_synth(model.synth_id);
         }
 
-        match Model::from_string(&mut self.synth_ctx, src) {
+        match Model::from_string(&mut self.synth_ctx, src, dir) {
             Ok(m) => {
                 self.model = Some(m);
                 log::info!("model loaded!");
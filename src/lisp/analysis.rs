@@ -0,0 +1,325 @@
+//! What editors want to know about a patch: diagnostics, the bindings it defines and
+//! what's under the cursor. Nothing here stops at the first error
+
+use super::sexpression::SExpression;
+use super::parser::ExprReader;
+use super::span::Span;
+use super::typecheck::{self, Diagnostic};
+use super::evaluation::EvaluationContext;
+use super::builtins::{self, BUILTINS, SPECIAL_FORMS};
+
+use std::path::Path;
+
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum DefinitionKind {
+	Value,
+	Function,
+	Store,
+	Parameter,
+}
+
+/// A name bound by `let`, `defn`, `def-store` or a function parameter list
+#[derive(Clone, Debug)]
+pub struct Definition<'a> {
+	pub name: &'a str,
+	pub kind: DefinitionKind,
+
+	// The whole defining form, and just the name within it
+	pub span: Span,
+	pub name_span: Span,
+
+	// For functions, the names of their parameters
+	pub params: Vec<&'a str>,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum CompletionKind {
+	Builtin,
+	SpecialForm,
+	Definition(DefinitionKind),
+}
+
+#[derive(Clone, Debug)]
+pub struct Completion {
+	pub label: String,
+	pub kind: CompletionKind,
+	pub detail: String,
+}
+
+
+/// What to show when hovering over an identifier
+#[derive(Clone, Debug)]
+pub struct HoverInfo {
+	// How it's used, or the form that defined it
+	pub code: String,
+	pub doc: Option<&'static str>,
+	pub span: Span,
+}
+
+
+pub struct Analysis<'a> {
+	source: &'a str,
+	forms: Vec<SExpression<'a>>,
+	pub diagnostics: Vec<Diagnostic>,
+}
+
+/// Parses and checks as much of a patch as possible. Forms after a parse error are
+/// skipped. Nothing is evaluated, so this is cheap enough to do on every change
+pub fn analyse<'a>(source: &'a str) -> Analysis<'a> {
+	let mut reader = ExprReader::new(source);
+	let mut forms = Vec::new();
+	let mut diagnostics = Vec::new();
+
+	reader.skip_whitespace();
+
+	while !reader.is_empty() {
+		let start = reader.offset();
+
+		match reader.parse_sexpression() {
+			Ok(sexpr) => forms.push(sexpr),
+			Err(err) => {
+				let len = source[start..].chars().next().map_or(0, char::len_utf8);
+				diagnostics.push(Diagnostic {
+					span: Span::new(start, start + len),
+					message: err.to_string(),
+				});
+				break;
+			}
+		}
+
+		reader.skip_whitespace();
+	}
+
+	diagnostics.extend(typecheck::check(&forms));
+
+	Analysis {source, forms, diagnostics}
+}
+
+
+impl<'a> Analysis<'a> {
+	/// Evaluates the patch, with files it loads relative to `dir`, returning the first error.
+	/// Only patches without diagnostics are evaluated. This can load files and build large
+	/// graphs, so it's slow compared to everything else here
+	pub fn evaluate(&self, sample_rate: f32, dir: &Path) -> Vec<Diagnostic> {
+		if !self.diagnostics.is_empty() {
+			return Vec::new()
+		}
+
+		let mut ctx = EvaluationContext::new(sample_rate);
+		ctx.dir = dir.to_owned();
+
+		for sexpr in self.forms.iter().cloned() {
+			let span = sexpr.span();
+
+			if let Err(err) = ctx.evaluate_top_level_form(sexpr) {
+				// Everything after this is likely to fail for the same reason
+				return vec![Diagnostic {span, message: err.to_string()}]
+			}
+		}
+
+		Vec::new()
+	}
+
+	/// Every binding made at the top level of the patch
+	pub fn symbols(&self) -> Vec<Definition<'a>> {
+		self.forms.iter()
+			.filter_map(definition)
+			.collect()
+	}
+
+	/// Where the identifier at `offset` was bound
+	pub fn definition(&self, offset: usize) -> Option<Definition<'a>> {
+		let (name, _) = self.identifier_at(offset)?;
+
+		self.definitions_in_scope(offset).into_iter()
+			.rev()
+			.find(|d| d.name == name)
+	}
+
+	/// Documentation for the builtin, special form or binding at `offset`
+	pub fn hover(&self, offset: usize) -> Option<HoverInfo> {
+		let (name, span) = self.identifier_at(offset)?;
+
+		if let Some(builtin) = builtins::lookup(name) {
+			let usage = builtin.signatures.iter()
+				.map(|s| s.usage(builtin.name))
+				.collect::<Vec<_>>();
+
+			return Some(HoverInfo {code: usage.join("\n"), doc: Some(builtin.doc), span});
+		}
+
+		if let Some(form) = SPECIAL_FORMS.iter().find(|f| f.name == name) {
+			return Some(HoverInfo {code: form.usage.to_owned(), doc: Some(form.doc), span});
+		}
+
+		let definition = self.definition(offset)?;
+		let code = self.source[definition.span.start..definition.span.end].to_owned();
+
+		Some(HoverInfo {code, doc: None, span})
+	}
+
+	/// Everything that could be typed at `offset`, innermost bindings first
+	pub fn completions(&self, offset: usize) -> Vec<Completion> {
+		let mut completions: Vec<Completion> = Vec::new();
+
+		for definition in self.definitions_in_scope(offset).into_iter().rev() {
+			if completions.iter().any(|c| c.label == definition.name) {
+				continue
+			}
+
+			let detail = match definition.kind {
+				DefinitionKind::Function => format!("(defn {} ({}))", definition.name, definition.params.join(" ")),
+				DefinitionKind::Store => "store".into(),
+				DefinitionKind::Parameter => "parameter".into(),
+				DefinitionKind::Value => "let".into(),
+			};
+
+			completions.push(Completion {
+				label: definition.name.to_owned(),
+				kind: CompletionKind::Definition(definition.kind),
+				detail,
+			});
+		}
+
+		for form in SPECIAL_FORMS {
+			completions.push(Completion {
+				label: form.name.to_owned(),
+				kind: CompletionKind::SpecialForm,
+				detail: form.usage.to_owned(),
+			});
+		}
+
+		for builtin in BUILTINS {
+			let names = std::iter::once(&builtin.name).chain(builtin.aliases.iter());
+
+			for &name in names {
+				completions.push(Completion {
+					label: name.to_owned(),
+					kind: CompletionKind::Builtin,
+					detail: builtin.signatures[0].usage(name),
+				});
+			}
+		}
+
+		completions
+	}
+
+	fn identifier_at(&self, offset: usize) -> Option<(&'a str, Span)> {
+		fn find<'a>(sexpr: &SExpression<'a>, offset: usize) -> Option<(&'a str, Span)> {
+			match sexpr {
+				SExpression::Identifier(name, span) if span.start <= offset && offset <= span.end =>
+					Some((*name, *span)),
+
+				SExpression::List(list, span) | SExpression::Array(list, span) if contains(*span, offset) =>
+					list.iter().find_map(|e| find(e, offset)),
+
+				_ => None,
+			}
+		}
+
+		self.forms.iter().find_map(|f| find(f, offset))
+	}
+
+	/// Bindings visible at `offset`, outermost first. Top level bindings are only
+	/// visible after the form that makes them
+	fn definitions_in_scope(&self, offset: usize) -> Vec<Definition<'a>> {
+		let mut scope = Vec::new();
+		top_level_scope(&self.forms, offset, &mut scope);
+		scope
+	}
+}
+
+
+fn contains(span: Span, offset: usize) -> bool {
+	span.start <= offset && offset < span.end
+}
+
+fn top_level_scope<'a>(forms: &[SExpression<'a>], offset: usize, scope: &mut Vec<Definition<'a>>) {
+	for form in forms {
+		if form.span().end <= offset {
+			scope.extend(definition(form));
+		} else if contains(form.span(), offset) {
+			nested_scope(form, offset, scope);
+		}
+	}
+}
+
+fn nested_scope<'a>(sexpr: &SExpression<'a>, offset: usize, scope: &mut Vec<Definition<'a>>) {
+	let list = match sexpr {
+		SExpression::List(list, _) | SExpression::Array(list, _) => list,
+		_ => return,
+	};
+
+	let params_and_body = match list.first() {
+		Some(SExpression::Identifier("fn", _)) => list.get(1).zip(list.get(2)),
+		Some(SExpression::Identifier("defn", _)) => list.get(2).zip(list.get(3)),
+
+		// Baked synths are evaluated in their own scope
+		Some(SExpression::Identifier("bake", _)) => {
+			scope.clear();
+			top_level_scope(&list[2.min(list.len())..], offset, scope);
+			return;
+		}
+
+		_ => None,
+	};
+
+	if let Some((SExpression::List(params, _), body)) = params_and_body {
+		if contains(body.span(), offset) {
+			for param in params {
+				if let SExpression::Identifier(name, span) = *param {
+					scope.push(Definition {
+						name, kind: DefinitionKind::Parameter,
+						span, name_span: span,
+						params: Vec::new(),
+					});
+				}
+			}
+		}
+	}
+
+	for element in list {
+		if contains(element.span(), offset) {
+			nested_scope(element, offset, scope);
+		}
+	}
+}
+
+/// The binding a top level form makes, if any
+fn definition<'a>(sexpr: &SExpression<'a>) -> Option<Definition<'a>> {
+	let list = match sexpr {
+		SExpression::List(list, _) => list,
+		_ => return None,
+	};
+
+	let (form, name, name_span) = match (list.first(), list.get(1)) {
+		(Some(SExpression::Identifier(form, _)), Some(SExpression::Identifier(name, span))) => (*form, *name, *span),
+		_ => return None,
+	};
+
+	let (kind, params) = match form {
+		"let" => (DefinitionKind::Value, Vec::new()),
+		"def-store" => (DefinitionKind::Store, Vec::new()),
+
+		"defn" => {
+			let params = match list.get(2) {
+				Some(SExpression::List(params, _)) => params.iter()
+					.filter_map(|p| match *p { SExpression::Identifier(name, _) => Some(name), _ => None })
+					.collect(),
+				_ => Vec::new(),
+			};
+
+			(DefinitionKind::Function, params)
+		}
+
+		_ => return None,
+	};
+
+	Some(Definition {
+		name, kind,
+		span: sexpr.span(),
+		name_span,
+		params,
+	})
+}
@@ -0,0 +1,2424 @@
+use super::LispResult;
+use super::evaluation::{EvaluationContext, EvalResult, Function};
+use super::ir::{Graph, Input, NodeRef, StoreRef, Op};
+use super::typecheck::Type::{self, *};
+use super::{wav, wavetable};
+use voi_synth::failure::{format_err, bail, ensure};
+
+use std::f32::consts::PI;
+use std::rc::Rc;
+
+
+pub type BuiltinFn = for<'a> fn(&mut EvaluationContext<'a>, Args<'a>) -> LispResult<EvalResult<'a>>;
+
+pub struct Param {
+	pub name: &'static str,
+	pub kind: Type,
+
+	// Value used when the argument is omitted
+	pub default: Option<f32>,
+}
+
+pub struct Signature {
+	pub params: &'static [Param],
+
+	// The last parameter may be repeated. Arrays passed to a variadic
+	// signal parameter are spliced into the argument list
+	pub variadic: bool,
+
+	pub returns: Type,
+}
+
+pub struct Builtin {
+	pub name: &'static str,
+	pub aliases: &'static [&'static str],
+
+	// Overloads, chosen by the number of arguments
+	pub signatures: &'static [Signature],
+
+	// Computes the result at compile time when every argument is constant
+	pub fold: Option<fn(&[f32]) -> LispResult<f32>>,
+
+	pub doc: &'static str,
+
+	// A whole patch showing it in use, for the reference
+	pub example: &'static str,
+
+	pub func: BuiltinFn,
+}
+
+const fn req(name: &'static str, kind: Type) -> Param {
+	Param {name, kind, default: None}
+}
+
+const fn opt(name: &'static str, kind: Type, default: f32) -> Param {
+	Param {name, kind, default: Some(default)}
+}
+
+
+pub static BUILTINS: &[Builtin] = &[
+	Builtin {
+		name: "+", aliases: &[],
+		signatures: &[Signature { params: &[req("inputs", Signal)], variadic: true, returns: Signal }],
+		fold: Some(fold_add),
+		doc: "Sums its inputs.",
+		example: "(output (+ (sin 220) (sin 330)))",
+		func: add,
+	},
+
+	Builtin {
+		name: "-", aliases: &[],
+		signatures: &[Signature { params: &[req("input", Signal), req("subtrahends", Signal)], variadic: true, returns: Signal }],
+		fold: Some(fold_sub),
+		doc: "Subtracts every following input from the first.",
+		example: "(output (- (saw 110) (sqr 110)))",
+		func: sub,
+	},
+
+	Builtin {
+		name: "*", aliases: &[],
+		signatures: &[Signature { params: &[req("inputs", Signal)], variadic: true, returns: Signal }],
+		fold: Some(fold_multiply),
+		doc: "Multiplies its inputs together.",
+		example: "(output (* 0.5 (sin (key-freq))))",
+		func: multiply,
+	},
+
+	Builtin {
+		name: "/", aliases: &[],
+		signatures: &[Signature { params: &[req("dividend", Signal), req("divisor", Constant)], variadic: false, returns: Signal }],
+		fold: Some(fold_divide),
+		doc: "Divides an input by a constant.",
+		example: "(output (/ (saw 110) 4))",
+		func: divide,
+	},
+
+	Builtin {
+		name: "<", aliases: &[],
+		signatures: &[Signature { params: &[req("a", Constant), req("b", Constant)], variadic: false, returns: Constant }],
+		fold: Some(fold_less),
+		doc: "1 if a is less than b, otherwise 0.",
+		example: "(output (sin (if (< 1 2) 220 440)))",
+		func: compare,
+	},
+
+	Builtin {
+		name: ">", aliases: &[],
+		signatures: &[Signature { params: &[req("a", Constant), req("b", Constant)], variadic: false, returns: Constant }],
+		fold: Some(fold_greater),
+		doc: "1 if a is greater than b, otherwise 0.",
+		example: "(output (sin (if (> 1 2) 220 440)))",
+		func: compare,
+	},
+
+	Builtin {
+		name: "<=", aliases: &[],
+		signatures: &[Signature { params: &[req("a", Constant), req("b", Constant)], variadic: false, returns: Constant }],
+		fold: Some(fold_less_equal),
+		doc: "1 if a is less than or equal to b, otherwise 0.",
+		example: "(output (sin (if (<= 2 2) 220 440)))",
+		func: compare,
+	},
+
+	Builtin {
+		name: ">=", aliases: &[],
+		signatures: &[Signature { params: &[req("a", Constant), req("b", Constant)], variadic: false, returns: Constant }],
+		fold: Some(fold_greater_equal),
+		doc: "1 if a is greater than or equal to b, otherwise 0.",
+		example: "(output (sin (if (>= 1 2) 220 440)))",
+		func: compare,
+	},
+
+	Builtin {
+		name: "=", aliases: &[],
+		signatures: &[Signature { params: &[req("a", Constant), req("b", Constant)], variadic: false, returns: Constant }],
+		fold: Some(fold_equal),
+		doc: "1 if a and b are equal, otherwise 0.",
+		example: "(output (sin (if (= 1 1) 220 440)))",
+		func: compare,
+	},
+
+	Builtin {
+		name: "mix", aliases: &[],
+		signatures: &[
+			Signature { params: &[req("inputs", Array)], variadic: false, returns: Signal },
+			Signature { params: &[req("a", Signal), req("b", Signal), req("mix", Signal)], variadic: false, returns: Signal },
+		],
+		fold: Some(fold_mix),
+		doc: "Crossfades from a to b as mix goes from 0 to 1, or averages an array of inputs.",
+		example: "(output (mix (sin 220) (saw 220) (tri 0.5)))",
+		func: mix,
+	},
+
+	Builtin {
+		name: "sin", aliases: &["sine"],
+		signatures: &[Signature { params: &[req("freq", Signal), opt("phase", Signal, 0.0)], variadic: false, returns: Signal }],
+		fold: None,
+		doc: "Sine oscillator. Phase is added on in cycles, and can be modulated, for phase modulation that keeps \
+			its pitch. Phases beyond 16 cycles either way are clamped.",
+		example: "(output (sin (key-freq) (* 0.3 (sin (* 2 (key-freq))))))",
+		func: sine,
+	},
+
+	Builtin {
+		name: "tri", aliases: &["triangle"],
+		signatures: &[Signature { params: &[req("freq", Signal), opt("phase", Signal, 0.0)], variadic: false, returns: Signal }],
+		fold: None,
+		doc: "Triangle oscillator, with a phase in cycles like sin's.",
+		example: "(output (tri (key-freq)))",
+		func: triangle,
+	},
+
+	Builtin {
+		name: "sqr", aliases: &["square"],
+		signatures: &[Signature { params: &[req("freq", Signal)], variadic: false, returns: Signal }],
+		fold: None,
+		doc: "Square oscillator.",
+		example: "(output (sqr (key-freq)))",
+		func: square,
+	},
+
+	Builtin {
+		name: "saw", aliases: &["sawtooth"],
+		signatures: &[Signature { params: &[req("freq", Signal)], variadic: false, returns: Signal }],
+		fold: None,
+		doc: "Sawtooth oscillator.",
+		example: "(output (saw (key-freq)))",
+		func: saw,
+	},
+
+	Builtin {
+		name: "saw-bl", aliases: &[],
+		signatures: &[Signature { params: &[req("freq", Signal)], variadic: false, returns: Signal }],
+		fold: None,
+		doc: "Sawtooth oscillator with PolyBLEP anti-aliasing, for high notes where saw aliases.",
+		example: "(output (saw-bl (* 4 (key-freq))))",
+		func: saw_bl,
+	},
+
+	Builtin {
+		name: "sqr-bl", aliases: &[],
+		signatures: &[Signature { params: &[req("freq", Signal), opt("width", Signal, 0.5)], variadic: false, returns: Signal }],
+		fold: None,
+		doc: "Square oscillator with PolyBLEP anti-aliasing. Width is the part of each cycle spent high, from 0 to 1.",
+		example: "(output (sqr-bl (key-freq) (+ 0.5 (* 0.4 (sin 0.3)))))",
+		func: square_bl,
+	},
+
+	Builtin {
+		name: "wavetable", aliases: &[],
+		signatures: &[
+			Signature {
+				params: &[req("table", Unknown), req("freq", Signal), opt("position", Signal, 0.0)],
+				variadic: false, returns: Signal
+			},
+			Signature {
+				params: &[req("table", Unknown), req("mode", Str), req("phase-or-freq", Signal), req("position", Signal)],
+				variadic: false, returns: Signal
+			},
+		],
+		fold: None,
+		doc: "Wavetable oscillator. The table is a single cycle array, an array of cycles, a WAV file or a baked \
+			buffer, with files and buffers split into 2048 sample cycles. Position sweeps from the first cycle at 0 \
+			to the last at 1, crossfading between them. Mode is \"freq\" or \"phase\", and with \"phase\" the next \
+			argument is the phase in cycles. Cycles are played back band limited, with fewer harmonics the higher \
+			the pitch. Only the first 64 harmonics of each cycle are played, which puts the top of a 55hz note at \
+			about 3.5khz, and tables of more than 8 cycles are resampled to 8, evenly spaced and crossfaded from the \
+			cycles either side. A sine costs about 30 nodes per voice, a single bright cycle about 570, and each \
+			further cycle up to about 270 more, for up to about 2450.",
+		example: "(output (wavetable [[0 0.7 1 0.7 0 -0.7 -1 -0.7] [1 1 1 1 -1 -1 -1 -1]] (key-freq) (* 0.5 (+ 1 (sin 0.5)))))",
+		func: wavetable,
+	},
+
+	Builtin {
+		name: "fm-op", aliases: &[],
+		signatures: &[Signature {
+			params: &[req("ratio", Signal), req("index", Signal), req("env", Signal), opt("mod-input", Signal, 0.0)],
+			variadic: false, returns: Signal
+		}],
+		fold: None,
+		doc: "An FM operator, a sine at ratio times the key's frequency with its level set by env. Its phase is \
+			modulated by mod-input, scaled by index, in radians, so a modulator with a level of 1 and an index \
+			of 2 gives a modulation index of 2.",
+		example: "(let env (env-adsr 0.001 1.5 0 0.3 (key-vel)))\n(output (fm-op 1 3 env (fm-op 3.5 0 env)))",
+		func: fm_op,
+	},
+
+	Builtin {
+		name: "fm-stack", aliases: &[],
+		signatures: &[Signature {
+			params: &[req("ratios", Array), req("indexes", Array), req("env", Signal)],
+			variadic: false, returns: Signal
+		}],
+		fold: None,
+		doc: "A stack of FM operators sharing env, each modulating the one before it. Ratios run from the carrier \
+			up, and each index is how hard the next operator up modulates the one below, so there's one index \
+			fewer than there are ratios.",
+		example: "(output (fm-stack [1 2 7] [2.5 1] (env-adsr 0.001 1 0.2 0.3 (key-vel))))",
+		func: fm_stack,
+	},
+
+	Builtin {
+		name: "fm-parallel", aliases: &[],
+		signatures: &[Signature {
+			params: &[req("ratios", Array), req("index", Signal), req("env", Signal), req("mod-input", Signal)],
+			variadic: false, returns: Signal
+		}],
+		fold: None,
+		doc: "FM carriers at each of ratios, all modulated by mod-input, mixed together.",
+		example: "(let env (env-adsr 0.001 2 0 0.5 (key-vel)))\n(output (fm-parallel [1 2.76 5.4] 1.5 env (fm-op 1 0 env)))",
+		func: fm_parallel,
+	},
+
+	Builtin {
+		name: "fm-feedback", aliases: &[],
+		signatures: &[Signature {
+			params: &[req("ratio", Signal), req("feedback", Signal), req("env", Signal)],
+			variadic: false, returns: Signal
+		}],
+		fold: None,
+		doc: "An FM operator modulating itself, by the average of its last two samples scaled by feedback. It \
+			goes from a sine towards a saw as feedback rises, and turns to noise somewhere past 1.5.",
+		example: "(output (fm-op 1 2 (env-ar 0.001 0.8 (key-vel)) (fm-feedback 1 1.2 1)))",
+		func: fm_feedback,
+	},
+
+	Builtin {
+		name: "noise", aliases: &[],
+		signatures: &[
+			Signature { params: &[req("colour", Str)], variadic: false, returns: Signal },
+			Signature { params: &[req("colour", Str), req("seed", Constant)], variadic: false, returns: Signal },
+		],
+		fold: None,
+		doc: "Noise, either 'white, 'pink or 'brown. The same seed always gives the same noise, and unseeded \
+			noise sources are numbered in the order they appear, apart from any seed, so a patch renders the \
+			same every time. White noise fills -1 to 1, and pink and brown peak around there.",
+		example: "(output (* (env-ar 0.001 0.08 (key-vel)) (hp 7000 (noise 'white))))",
+		func: noise,
+	},
+
+	Builtin {
+		name: "random-step", aliases: &[],
+		signatures: &[
+			Signature { params: &[req("rate", Signal)], variadic: false, returns: Signal },
+			Signature { params: &[req("rate", Signal), req("seed", Constant)], variadic: false, returns: Signal },
+		],
+		fold: None,
+		doc: "Sample and hold: a random value from -1 to 1, replaced rate times a second. Seeded like noise.",
+		example: "(output (sqr (+ (key-freq) (* 100 (random-step 8 1)))))",
+		func: random_step,
+	},
+
+	Builtin {
+		name: "lp", aliases: &["lowpass"],
+		signatures: &[Signature { params: &[req("cutoff", Signal), req("input", Signal)], variadic: false, returns: Signal }],
+		fold: None,
+		doc: "Lowpass filter.",
+		example: "(output (lp 800 (saw (key-freq))))",
+		func: lowpass,
+	},
+
+	Builtin {
+		name: "hp", aliases: &["highpass"],
+		signatures: &[Signature { params: &[req("cutoff", Signal), req("input", Signal)], variadic: false, returns: Signal }],
+		fold: None,
+		doc: "Highpass filter.",
+		example: "(output (hp 2000 (saw (key-freq))))",
+		func: highpass,
+	},
+
+	Builtin {
+		name: "svf", aliases: &[],
+		signatures: &[Signature {
+			params: &[req("mode", Str), req("cutoff", Signal), req("q", Signal), req("input", Signal)],
+			variadic: false, returns: Signal
+		}],
+		fold: None,
+		doc: "Resonant state variable filter. Mode is 'lp, 'hp, 'bp, 'notch or 'peak, and q runs from 0.5 for \
+			no resonance up to 100. The band pass has unity gain at the cutoff. Cutoff and q can be swept at \
+			audio rate, though sudden jumps in q take a few samples to catch up.",
+		example: "(output (svf 'lp (+ 1200 (* 900 (sin 0.5))) 8 (saw (key-freq))))",
+		func: svf,
+	},
+
+	Builtin {
+		name: "ladder", aliases: &[],
+		signatures: &[Signature {
+			params: &[req("cutoff", Signal), req("resonance", Signal), req("drive", Signal), req("input", Signal)],
+			variadic: false, returns: Signal
+		}],
+		fold: None,
+		doc: "Four pole lowpass ladder filter. Resonance runs from 0 to 1, where it starts to self oscillate, \
+			and thins out the bass as it rises, like the original. Drive is the gain into the filter, which \
+			saturates its input and feedback, and keeps it from running away.",
+		example: "(output (ladder (* (key-freq) (+ 2 (* 6 (env-ar 0.01 0.3 (key-vel))))) 0.8 1.5 (saw (key-freq))))",
+		func: ladder,
+	},
+
+	Builtin {
+		name: "delay", aliases: &[],
+		signatures: &[Signature {
+			params: &[req("time", Signal), req("feedback", Signal), req("input", Signal), opt("max-time", Constant, 0.0)],
+			variadic: false, returns: Signal
+		}],
+		fold: None,
+		doc: "Delays input by time seconds, feeding the delayed signal back into it scaled by feedback, which is \
+			kept within 0.99 either way. Times between samples are interpolated, so time can be modulated, but it \
+			then needs a max-time, and only reaches up to 2ms short of it. Every sample of delay costs a node per \
+			voice, about 44 a millisecond at 44.1khz, and a modulated time about 450 more, so delays are limited \
+			to 20ms, and all of a patch's together to 40ms. That's enough for combs and plucked strings, but not \
+			for echoes.",
+		example: "(output (+ (saw (key-freq)) (delay 0.01 0.6 (saw (key-freq)))))",
+		func: delay,
+	},
+
+	Builtin {
+		name: "delay-line", aliases: &[],
+		signatures: &[Signature { params: &[req("max-time", Constant), req("input", Signal)], variadic: false, returns: DelayLine }],
+		fold: None,
+		doc: "A delay line up to max-time seconds long, for reading from with delay-tap. Like delay, every \
+			sample of it costs a node per voice, about 44 a millisecond at 44.1khz, up to 20ms.",
+		example: "(let line (delay-line 0.02 (saw (key-freq))))\n\
+			(output (mix [(delay-tap line 0) (delay-tap line 0.007) (delay-tap line (+ 0.019 (* 0.001 (sin 0.8))))]))",
+		func: delay_line,
+	},
+
+	Builtin {
+		name: "delay-tap", aliases: &[],
+		signatures: &[Signature { params: &[req("line", DelayLine), req("time", Signal)], variadic: false, returns: Signal }],
+		fold: None,
+		doc: "Reads a delay line time seconds back, interpolating between samples. A fixed time costs a node at \
+			most, but modulated times only reach over the last 2ms of the line, and cost about 450 nodes per \
+			voice. Taps read the line before it moves on, so feeding a tap back into its own line through a store \
+			delays it a sample more.",
+		example: "(def-store echo)\n(let line (delay-line 0.01 (+ (saw (key-freq)) (* 0.5 echo))))\n\
+			(store echo (delay-tap line 0.01))\n(output (+ (saw (key-freq)) echo))",
+		func: delay_tap,
+	},
+
+	Builtin {
+		name: "chorus", aliases: &[],
+		signatures: &[Signature {
+			params: &[req("rate", Signal), req("depth", Signal), req("mix", Signal), req("input", Signal)],
+			variadic: false, returns: Signal
+		}],
+		fold: None,
+		doc: "Chorus, reading the input back around 3.5ms, give or take up to 1ms swung by a sine at rate hz. \
+			Depth scales the swing from 0 to 1, and mix crossfades from the dry input to only the delayed copy. \
+			It's built on a delay, and costs about 650 nodes per voice.",
+		example: "(output (chorus 0.8 0.7 0.5 (saw (key-freq))))",
+		func: chorus,
+	},
+
+	Builtin {
+		name: "chorus-stereo", aliases: &[],
+		signatures: &[Signature {
+			params: &[req("rate", Signal), req("depth", Signal), req("mix", Signal), req("input", Signal)],
+			variadic: false, returns: Array
+		}],
+		fold: None,
+		doc: "Chorus with the sides swung in opposite directions, as a [left right] array. It costs twice as much \
+			as chorus.",
+		example: "(output (mix (chorus-stereo 0.8 0.7 0.5 (saw (key-freq)))))",
+		func: chorus_stereo,
+	},
+
+	Builtin {
+		name: "flanger", aliases: &[],
+		signatures: &[Signature {
+			params: &[req("rate", Signal), req("depth", Signal), req("feedback", Signal), req("input", Signal)],
+			variadic: false, returns: Signal
+		}],
+		fold: None,
+		doc: "Flanger, mixing the input with a copy delayed between 0.2ms and up to 2.2ms, swept by a sine at rate \
+			hz. Depth scales the sweep from 0 to 1, and feedback runs from -0.95 to 0.95. It's built on a delay, \
+			and costs about 550 nodes per voice.",
+		example: "(output (flanger 0.2 0.8 0.7 (saw (key-freq))))",
+		func: flanger,
+	},
+
+	Builtin {
+		name: "flanger-stereo", aliases: &[],
+		signatures: &[Signature {
+			params: &[req("rate", Signal), req("depth", Signal), req("feedback", Signal), req("input", Signal)],
+			variadic: false, returns: Array
+		}],
+		fold: None,
+		doc: "Flanger with the sides swept in opposite directions, as a [left right] array. It costs twice as much \
+			as flanger.",
+		example: "(output (mix (flanger-stereo 0.2 0.8 0.7 (saw (key-freq)))))",
+		func: flanger_stereo,
+	},
+
+	Builtin {
+		name: "phaser", aliases: &[],
+		signatures: &[Signature {
+			params: &[req("stages", Constant), req("rate", Signal), req("depth", Signal), req("input", Signal)],
+			variadic: false, returns: Signal
+		}],
+		fold: None,
+		doc: "Phaser, mixing the input with itself through up to 12 allpass stages, swept from 200hz towards 4khz \
+			by a sine at rate hz. Depth scales the sweep from 0 to 1. Every two stages add a notch.",
+		example: "(output (phaser 6 0.3 0.8 (saw (key-freq))))",
+		func: phaser,
+	},
+
+	Builtin {
+		name: "phaser-stereo", aliases: &[],
+		signatures: &[Signature {
+			params: &[req("stages", Constant), req("rate", Signal), req("depth", Signal), req("input", Signal)],
+			variadic: false, returns: Array
+		}],
+		fold: None,
+		doc: "Phaser with the sides swept in opposite directions, as a [left right] array.",
+		example: "(output (mix (phaser-stereo 6 0.3 0.8 (saw (key-freq)))))",
+		func: phaser_stereo,
+	},
+
+	Builtin {
+		name: "env-ar", aliases: &["ar"],
+		signatures: &[Signature {
+			params: &[req("attack", Constant), req("release", Constant), req("gate", Signal)],
+			variadic: false, returns: Signal
+		}],
+		fold: None,
+		doc: "Attack/release envelope, triggered while gate is high. Times are in seconds.",
+		example: "(output (* (env-ar 0.01 0.3 (key-vel)) (sin (key-freq))))",
+		func: env_ar,
+	},
+
+	Builtin {
+		name: "env-adsr", aliases: &["adsr"],
+		signatures: &[Signature {
+			params: &[req("attack", Constant), req("decay", Constant), req("sustain", Constant),
+				req("release", Constant), req("gate", Signal)],
+			variadic: false, returns: Signal
+		}],
+		fold: None,
+		doc: "Attack/decay/sustain/release envelope, triggered while gate is high. Times are in seconds.",
+		example: "(output (* (env-adsr 0.01 0.1 0.6 0.3 (key-vel)) (saw (key-freq))))",
+		func: env_adsr,
+	},
+
+	Builtin {
+		name: "clamp", aliases: &[],
+		signatures: &[Signature {
+			params: &[req("input", Signal), req("lower", Signal), req("upper", Signal)],
+			variadic: false, returns: Signal
+		}],
+		fold: Some(fold_clamp),
+		doc: "Limits input to the range [lower, upper].",
+		example: "(output (clamp (* 4 (sin (key-freq))) -0.5 0.5))",
+		func: clamp,
+	},
+
+	Builtin {
+		name: "tanh", aliases: &[],
+		signatures: &[Signature { params: &[req("input", Signal)], variadic: false, returns: Signal }],
+		fold: Some(fold_tanh),
+		doc: "Saturates input smoothly towards -1 and 1. Like the other waveshapers, it's oversampled to keep \
+			aliasing down.",
+		example: "(output (tanh (* 4 (saw (key-freq)))))",
+		func: tanh,
+	},
+
+	Builtin {
+		name: "softclip", aliases: &[],
+		signatures: &[Signature { params: &[req("drive", Signal), req("input", Signal)], variadic: false, returns: Signal }],
+		fold: Some(fold_softclip),
+		doc: "Boosts input by drive, then rounds it off with a cubic that reaches -1 and 1 at 1.5, and clips past that.",
+		example: "(output (softclip (+ 1 (* 3 (env-ar 0.01 0.5 (key-vel)))) (saw (key-freq))))",
+		func: softclip,
+	},
+
+	Builtin {
+		name: "fold", aliases: &[],
+		signatures: &[Signature { params: &[req("input", Signal)], variadic: false, returns: Signal }],
+		fold: Some(fold_foldback),
+		doc: "Wavefolder, which reflects input back inside -1 and 1 as many times as it takes.",
+		example: "(output (fold (* 3 (sin (key-freq)))))",
+		func: foldback,
+	},
+
+	Builtin {
+		name: "crush", aliases: &[],
+		signatures: &[Signature { params: &[req("bits", Constant), req("input", Signal)], variadic: false, returns: Signal }],
+		fold: Some(fold_crush),
+		doc: "Bitcrusher, rounding input to 2^bits levels from -1 to 1, including both. Bits runs from 1 to 16, and doesn't have \
+			to be whole.",
+		example: "(output (crush 4 (sin (key-freq))))",
+		func: crush,
+	},
+
+	Builtin {
+		name: "decimate", aliases: &[],
+		signatures: &[Signature { params: &[req("rate", Signal), req("input", Signal)], variadic: false, returns: Signal }],
+		fold: None,
+		doc: "Sample rate reduction, holding input for a sample rate of rate hz. It isn't filtered, so it aliases.",
+		example: "(output (decimate 3000 (saw (key-freq))))",
+		func: decimate,
+	},
+
+	Builtin {
+		name: "shaper", aliases: &[],
+		signatures: &[Signature { params: &[req("table", Array), req("input", Signal)], variadic: false, returns: Signal }],
+		fold: None,
+		doc: "Applies a transfer curve to input, given as up to 256 points spread evenly from -1 to 1, with input \
+			clamped to that range and interpolated between them.",
+		example: "(output (shaper [-1 -0.2 0 0.2 1] (sin (key-freq))))",
+		func: shaper,
+	},
+
+	Builtin {
+		name: "sequencer", aliases: &[],
+		signatures: &[Signature {
+			params: &[req("sequence", Array), req("advance", Signal), opt("reset", Signal, 1.0)],
+			variadic: false, returns: Signal
+		}],
+		fold: None,
+		doc: "Steps through a sequence of constants each time advance is triggered.",
+		example: "(output (sin (sequencer [220 330 440 330] (sqr 4))))",
+		func: sequencer,
+	},
+
+	Builtin {
+		name: "sample", aliases: &[],
+		signatures: &[Signature {
+			params: &[req("path", Str), opt("start", Constant, 0.0), opt("rate", Constant, 1.0),
+				opt("loop-start", Constant, 0.0), opt("loop-end", Constant, 0.0)],
+			variadic: false, returns: Signal
+		}],
+		fold: None,
+		doc: "Plays a WAV file of up to 60 seconds, found relative to the patch. Playback begins start \
+			seconds in, at rate times the original speed, up to 8 times. The file is resampled to the rate \
+			when the patch loads, so it can't follow key-freq. If loop-end is after loop-start, the part \
+			between them repeats once it's reached, for up to 10 seconds of playback. Playback is a single \
+			node, but every voice holds its own copy of what it plays.",
+		example: "(output (sample \"assets/kick.wav\"))",
+		func: sample,
+	},
+
+	Builtin {
+		name: "key-freq", aliases: &[],
+		signatures: &[Signature { params: &[], variadic: false, returns: Signal }],
+		fold: None,
+		doc: "Frequency of the currently held key.",
+		example: "(output (sin (key-freq)))",
+		func: key_freq,
+	},
+
+	Builtin {
+		name: "key-vel", aliases: &[],
+		signatures: &[Signature { params: &[], variadic: false, returns: Signal }],
+		fold: None,
+		doc: "Velocity of the currently held key, or 0 when no key is held.",
+		example: "(output (* (key-vel) (sin (key-freq))))",
+		func: key_vel,
+	},
+
+	Builtin {
+		name: "repeat", aliases: &[],
+		signatures: &[Signature { params: &[req("count", Constant), req("function", Function)], variadic: false, returns: Array }],
+		fold: None,
+		doc: "Calls function with each index from 0 to count, collecting the results in an array.",
+		example: "(output (mix (repeat 3 (fn (i) (saw (* (key-freq) (+ 1 (* i 0.01))))))))",
+		func: repeat,
+	},
+
+	Builtin {
+		name: "map", aliases: &[],
+		signatures: &[Signature { params: &[req("function", Function), req("array", Array)], variadic: false, returns: Array }],
+		fold: None,
+		doc: "Calls function with each element of an array, collecting the results in an array.",
+		example: "(output (mix (map (fn (f) (sin f)) [220 275 330])))",
+		func: map,
+	},
+];
+
+
+/// Forms the evaluator handles itself, rather than through the builtin table
+pub struct SpecialForm {
+	pub name: &'static str,
+	pub usage: &'static str,
+	pub doc: &'static str,
+	pub example: &'static str,
+}
+
+pub static SPECIAL_FORMS: &[SpecialForm] = &[
+	SpecialForm {
+		name: "let", usage: "(let name value)",
+		doc: "Binds value to name for the rest of the patch.",
+		example: "(let freq (key-freq))\n(output (sin freq))",
+	},
+	SpecialForm {
+		name: "defn", usage: "(defn name (params...) body)",
+		doc: "Defines a function.",
+		example: "(defn detuned (f) (mix (saw f) (saw (* f 1.01)) 0.5))\n(output (detuned (key-freq)))",
+	},
+	SpecialForm {
+		name: "fn", usage: "(fn (params...) body)",
+		doc: "An anonymous function.",
+		example: "(output (mix (map (fn (x) (sin (* x (key-freq)))) [1 2 3])))",
+	},
+	SpecialForm {
+		name: "gain", usage: "(gain value)",
+		doc: "Sets the constant gain applied to the output.",
+		example: "(gain 0.3)\n(output (saw (key-freq)))",
+	},
+	SpecialForm {
+		name: "output", usage: "(output signal)",
+		doc: "Sets the signal the synth plays.",
+		example: "(output (sin (key-freq)))",
+	},
+	SpecialForm {
+		name: "def-store", usage: "(def-store name)",
+		doc: "Defines a store, a value that holds between samples. Reading it gives the last value written.",
+		example: "(def-store phase)\n(store phase (+ phase 0.01))\n(output (sin (* 440 phase)))",
+	},
+	SpecialForm {
+		name: "store", usage: "(store name value)",
+		doc: "Writes value to a store every sample.",
+		example: "(def-store last)\n(store last (saw (key-freq)))\n(output (mix last (saw (key-freq)) 0.5))",
+	},
+	SpecialForm {
+		name: "if", usage: "(if condition then else)",
+		doc: "Chooses between two values. The condition must be constant.",
+		example: "(let bright 1)\n(output (if bright (saw (key-freq)) (sin (key-freq))))",
+	},
+	SpecialForm {
+		name: "cond", usage: "(cond (condition value)... (else value))",
+		doc: "Chooses the value of the first clause whose condition holds. Conditions must be constant.",
+		example: "(let shape 2)\n(output (cond ((= shape 1) (sin (key-freq))) ((= shape 2) (tri (key-freq))) (else (saw (key-freq)))))",
+	},
+	SpecialForm {
+		name: "bake", usage: "(bake seconds forms...)",
+		doc: "Renders forms as a separate synth into a buffer at compile time, and plays it back.",
+		example: "(output (bake 0.5 (output (* (env-ar 0.01 0.4 1) (saw 110)))))",
+	},
+];
+
+
+pub fn lookup(func_name: &str) -> Option<&'static Builtin> {
+	BUILTINS.iter()
+		.find(|b| b.name == func_name || b.aliases.contains(&func_name))
+}
+
+
+impl Signature {
+	fn accepts_count(&self, count: usize) -> bool {
+		let required = self.params.iter()
+			.take_while(|p| p.default.is_none())
+			.count();
+
+		count >= required && (self.variadic || count <= self.params.len())
+	}
+
+	fn describe_count(&self) -> String {
+		let required = self.params.iter()
+			.take_while(|p| p.default.is_none())
+			.count();
+
+		if self.variadic {
+			format!("at least {}", required)
+		} else if required == self.params.len() {
+			format!("{}", required)
+		} else {
+			format!("between {} and {}", required, self.params.len())
+		}
+	}
+
+	/// The parameter bound to the argument at `index`
+	pub fn param(&self, index: usize) -> Option<&Param> {
+		if self.variadic && index >= self.params.len() {
+			self.params.last()
+		} else {
+			self.params.get(index)
+		}
+	}
+
+	/// How a call with this signature looks, e.g., `(sequencer sequence advance [reset])`
+	pub fn usage(&self, func_name: &str) -> String {
+		let mut usage = format!("({}", func_name);
+
+		for (index, param) in self.params.iter().enumerate() {
+			let repeated = if self.variadic && index + 1 == self.params.len() { "..." } else { "" };
+
+			if param.default.is_some() {
+				usage += &format!(" [{}{}]", param.name, repeated);
+			} else {
+				usage += &format!(" {}{}", param.name, repeated);
+			}
+		}
+
+		usage + ")"
+	}
+
+	pub fn is_spliced(&self, index: usize) -> bool {
+		self.variadic && index + 1 >= self.params.len()
+			&& self.params.last().map_or(false, |p| p.kind == Signal)
+	}
+}
+
+impl Builtin {
+	/// Picks the overload accepting `count` arguments
+	pub fn signature(&self, count: usize) -> LispResult<&'static Signature> {
+		if let Some(sig) = self.signatures.iter().find(|s| s.accepts_count(count)) {
+			return Ok(sig);
+		}
+
+		let counts = self.signatures.iter()
+			.map(Signature::describe_count)
+			.collect::<Vec<_>>();
+
+		bail!("'{}' function requires {} arguments, {} received", self.name, counts.join(" or "), count)
+	}
+
+	pub fn call<'a>(&'static self, ctx: &mut EvaluationContext<'a>, values: Vec<EvalResult<'a>>) -> LispResult<EvalResult<'a>> {
+		let args = self.bind(values)?;
+
+		if let (Some(fold), Some(constants)) = (self.fold, args.as_constants()) {
+			return Ok(fold(&constants)?.into());
+		}
+
+		(self.func)(ctx, args)
+	}
+
+	pub fn bind<'a>(&'static self, values: Vec<EvalResult<'a>>) -> LispResult<Args<'a>> {
+		fn splice<'a>(value: EvalResult<'a>, bound: &mut Vec<EvalResult<'a>>) {
+			match value {
+				EvalResult::Array(elements) => {
+					for element in elements {
+						splice(element, bound);
+					}
+				}
+
+				value => bound.push(value),
+			}
+		}
+
+		let sig = self.signature(values.len())?;
+		let mut bound = Vec::with_capacity(values.len().max(sig.params.len()));
+		let count = values.len();
+
+		for (index, value) in values.into_iter().enumerate() {
+			if sig.is_spliced(index) {
+				splice(value, &mut bound);
+			} else {
+				bound.push(value);
+			}
+		}
+
+		for param in sig.params.iter().skip(count) {
+			if let Some(default) = param.default {
+				bound.push(default.into());
+			}
+		}
+
+		Ok(Args {
+			func_name: self.name,
+			values: bound.into_iter(),
+		})
+	}
+}
+
+
+/// Bound arguments, consumed in order by a builtin's implementation
+pub struct Args<'a> {
+	func_name: &'static str,
+	values: std::vec::IntoIter<EvalResult<'a>>,
+}
+
+impl<'a> Args<'a> {
+	pub fn len(&self) -> usize { self.values.len() }
+
+	/// The values of every remaining argument, if they're all constant
+	pub fn as_constants(&self) -> Option<Vec<f32>> {
+		self.values.as_slice().iter()
+			.map(|v| match *v { EvalResult::Constant(f) => Some(f), _ => None })
+			.collect()
+	}
+
+	fn next(&mut self) -> LispResult<EvalResult<'a>> {
+		self.values.next()
+			.ok_or_else(|| format_err!("'{}' function ran out of arguments", self.func_name))
+	}
+
+	pub fn value(&mut self) -> LispResult<EvalResult<'a>> { self.next() }
+	pub fn constant(&mut self) -> LispResult<f32> { self.next()?.expect_constant() }
+	pub fn input(&mut self) -> LispResult<Input> { self.next()?.to_input() }
+	pub fn array(&mut self) -> LispResult<Vec<f32>> { self.next()?.expect_array() }
+	pub fn elements(&mut self) -> LispResult<Vec<EvalResult<'a>>> { self.next()?.expect_elements() }
+	pub fn function(&mut self) -> LispResult<Rc<Function<'a>>> { self.next()?.expect_function() }
+	pub fn string(&mut self) -> LispResult<&'a str> { self.next()?.expect_string() }
+	pub fn delay_line(&mut self) -> LispResult<usize> { self.next()?.expect_delay_line() }
+
+	pub fn inputs(self) -> LispResult<Vec<Input>> {
+		self.values.map(EvalResult::to_input).collect()
+	}
+}
+
+
+fn fold_add(args: &[f32]) -> LispResult<f32> {
+	Ok(args.iter().sum())
+}
+
+fn fold_sub(args: &[f32]) -> LispResult<f32> {
+	Ok(args[0] - args[1..].iter().sum::<f32>())
+}
+
+fn fold_multiply(args: &[f32]) -> LispResult<f32> {
+	Ok(args.iter().product())
+}
+
+fn fold_divide(args: &[f32]) -> LispResult<f32> {
+	ensure!(args[1] != 0.0, "Division by zero");
+	Ok(args[0] / args[1])
+}
+
+fn fold_less(args: &[f32]) -> LispResult<f32> { Ok((args[0] < args[1]) as u32 as f32) }
+fn fold_greater(args: &[f32]) -> LispResult<f32> { Ok((args[0] > args[1]) as u32 as f32) }
+fn fold_less_equal(args: &[f32]) -> LispResult<f32> { Ok((args[0] <= args[1]) as u32 as f32) }
+fn fold_greater_equal(args: &[f32]) -> LispResult<f32> { Ok((args[0] >= args[1]) as u32 as f32) }
+fn fold_equal(args: &[f32]) -> LispResult<f32> { Ok((args[0] == args[1]) as u32 as f32) }
+
+fn fold_mix(args: &[f32]) -> LispResult<f32> {
+	Ok(args[0] + (args[1] - args[0]) * args[2])
+}
+
+fn fold_clamp(args: &[f32]) -> LispResult<f32> {
+	Ok(args[0].max(args[1]).min(args[2]))
+}
+
+
+fn fold_inputs<'a>(ctx: &mut EvaluationContext<'a>, args: Args<'a>,
+	op: fn(&mut Graph, Input, Input) -> NodeRef) -> LispResult<EvalResult<'a>>
+{
+	let func_name = args.func_name;
+	let mut inputs = args.inputs()?.into_iter();
+	let first = inputs.next()
+		.ok_or_else(|| format_err!("'{}' function received an empty array", func_name))?;
+
+	// TODO: take advantage of associativity
+	let res = inputs.fold(first, |a, e| op(&mut ctx.graph, a, e).into());
+	Ok(res.into())
+}
+
+fn add<'a>(ctx: &mut EvaluationContext<'a>, args: Args<'a>) -> LispResult<EvalResult<'a>> {
+	fold_inputs(ctx, args, |graph, a, e| graph.new_add(a, e))
+}
+
+fn sub<'a>(ctx: &mut EvaluationContext<'a>, args: Args<'a>) -> LispResult<EvalResult<'a>> {
+	fold_inputs(ctx, args, |graph, a, e| graph.new_sub(a, e))
+}
+
+fn multiply<'a>(ctx: &mut EvaluationContext<'a>, args: Args<'a>) -> LispResult<EvalResult<'a>> {
+	fold_inputs(ctx, args, |graph, a, e| graph.new_multiply(a, e))
+}
+
+fn divide<'a>(ctx: &mut EvaluationContext<'a>, mut args: Args<'a>) -> LispResult<EvalResult<'a>> {
+	let a = args.input()?;
+	let b = args.constant()?;
+
+	ensure!(b != 0.0, "Division by zero");
+	Ok(ctx.graph.new_multiply(a, 1.0 / b).into())
+}
+
+// Comparisons only make sense at compile time, and so are always folded
+fn compare<'a>(_: &mut EvaluationContext<'a>, _: Args<'a>) -> LispResult<EvalResult<'a>> {
+	bail!("Comparisons require constant arguments")
+}
+
+fn mix<'a>(ctx: &mut EvaluationContext<'a>, mut args: Args<'a>) -> LispResult<EvalResult<'a>> {
+	if args.len() == 1 {
+		let inputs = args.elements()?.into_iter()
+			.map(EvalResult::to_input)
+			.collect::<LispResult<Vec<_>>>()?;
+
+		ensure!(!inputs.is_empty(), "'mix' function received an empty array");
+
+		let scale = 1.0 / inputs.len() as f32;
+		let mut inputs = inputs.into_iter();
+		let first = inputs.next().unwrap();
+
+		let sum = inputs.fold(first, |a, e| ctx.graph.new_add(a, e).into());
+		return Ok(ctx.graph.new_multiply(sum, scale).into());
+	}
+
+	let a = args.input()?;
+	let b = args.input()?;
+	let mix = args.input()?;
+	Ok(ctx.graph.new_mix(a, b, mix).into())
+}
+
+fn sine<'a>(ctx: &mut EvaluationContext<'a>, mut args: Args<'a>) -> LispResult<EvalResult<'a>> {
+	let freq = args.input()?;
+	let phase = args.input()?;
+
+	if phase.literal() == Some(0.0) {
+		return Ok(ctx.graph.new_sine(freq).into())
+	}
+
+	Ok(phase_sine(ctx, freq, phase).into())
+}
+
+fn triangle<'a>(ctx: &mut EvaluationContext<'a>, mut args: Args<'a>) -> LispResult<EvalResult<'a>> {
+	let freq = args.input()?;
+	let phase = args.input()?;
+
+	if phase.literal() == Some(0.0) {
+		return Ok(ctx.graph.new_triangle(freq).into())
+	}
+
+	let phase = offset_phase(ctx, freq, phase, "tri");
+	let quarter = quarter_cycle(&mut ctx.graph, phase);
+	Ok(ctx.graph.new_multiply(quarter, 4.0).into())
+}
+
+fn square<'a>(ctx: &mut EvaluationContext<'a>, mut args: Args<'a>) -> LispResult<EvalResult<'a>> {
+	let freq = args.input()?;
+	Ok(ctx.graph.new_square(freq).into())
+}
+
+fn saw<'a>(ctx: &mut EvaluationContext<'a>, mut args: Args<'a>) -> LispResult<EvalResult<'a>> {
+	let freq = args.input()?;
+	Ok(ctx.graph.new_saw(freq).into())
+}
+
+// Band limited oscillators run on oscillator_phase, with their discontinuities smoothed over a
+// sample either side with PolyBLEP, which needs 1/dt, so it's kept up to date with a Newton
+// iteration. Per voice, a saw costs 15 nodes and a square 29, plus 9 when the frequency isn't
+// a literal
+
+fn saw_bl<'a>(ctx: &mut EvaluationContext<'a>, mut args: Args<'a>) -> LispResult<EvalResult<'a>> {
+	let freq = args.input()?;
+	let (phase, inv_dt) = blep_phase(ctx, freq, "saw-bl");
+	let graph = &mut ctx.graph;
+
+	// The phase is centred on 0, so like the sine, the saw starts halfway up
+	let naive = graph.new_multiply(phase, 2.0);
+	let blep = poly_blep(graph, phase, inv_dt);
+
+	Ok(graph.new_sub(naive, blep).into())
+}
+
+fn square_bl<'a>(ctx: &mut EvaluationContext<'a>, mut args: Args<'a>) -> LispResult<EvalResult<'a>> {
+	let freq = args.input()?;
+	let width = args.input()?;
+	let (phase, inv_dt) = blep_phase(ctx, freq, "sqr-bl");
+	let graph = &mut ctx.graph;
+
+	// Each cycle rises as the phase wraps, and falls width later. Any wider and the start of
+	// the cycle would round down a whole cycle too far
+	let width = graph.new_clamp(width, 0.0, 1.0 - f32::EPSILON);
+	let from_fall = graph.new_sub(phase, width);
+	let cycles = round(graph, from_fall.into(), 1.0);
+	let fall_phase = graph.new_sub(from_fall, cycles);
+
+	// High in the cycle before the fall, and low in the one it's in
+	let naive = graph.new_multiply(cycles, -2.0);
+	let naive = graph.new_add(naive, -1.0);
+
+	let rise = poly_blep(graph, phase, inv_dt);
+	let fall = poly_blep(graph, fall_phase.into(), inv_dt);
+
+	let square = graph.new_add(naive, rise);
+	Ok(graph.new_sub(square, fall).into())
+}
+
+// A phase advancing at freq, and the reciprocal of how far it moves each sample
+fn blep_phase(ctx: &mut EvaluationContext, freq: Input, name: &str) -> (Input, Input) {
+	let phase = oscillator_phase(ctx, freq, name);
+	let sample_rate = ctx.sample_rate;
+	let graph = &mut ctx.graph;
+
+	// Very low and negative frequencies get no smoothing, rather than dividing by zero
+	let period = reciprocal(graph, freq, 1.0 / sample_rate, 100.0, &format!("{} period", name));
+
+	let inv_dt = graph.new_multiply(period, sample_rate);
+	(phase, inv_dt.into())
+}
+
+// The phase of voi-synth's oscillators can't be read, so oscillators that need it keep their
+// own in a store, and build their waves from it with arithmetic nodes
+
+// A phase in cycles advancing at freq, wrapped to within half a cycle of 0. 5 nodes, or 6
+// when freq isn't a literal
+fn oscillator_phase(ctx: &mut EvaluationContext, freq: Input, name: &str) -> Input {
+	let sample_rate = ctx.sample_rate;
+	let graph = &mut ctx.graph;
+
+	let store = graph.new_value_store(&format!("{} phase", name));
+	let dt = graph.new_multiply(freq, 1.0 / sample_rate);
+	let next = graph.new_add(store, dt);
+	let phase = wrap(graph, next.into());
+	graph.new_store_write(store, phase);
+
+	phase
+}
+
+// Phase modulated oscillators can't use voi-synth's, so they wrap their phase plus the
+// modulation themselves, and fold it onto the quarter cycle either side of a zero crossing
+
+// sin(2 pi x) for x from -1/4 to 1/4, as a polynomial in x^2 times x, good to about 1e-6
+const QUARTER_SINE: [f32; 4] = [6.283_164, -41.337_143, 81.340_77, -70.993_43];
+
+fn phase_sine(ctx: &mut EvaluationContext, freq: Input, phase: Input) -> Input {
+	let phase = offset_phase(ctx, freq, phase, "sin");
+	sine_cycle(&mut ctx.graph, phase)
+}
+
+// The oscillator's phase plus phase, in cycles
+fn offset_phase(ctx: &mut EvaluationContext, freq: Input, phase: Input, name: &str) -> Input {
+	let oscillator = oscillator_phase(ctx, freq, name);
+	ctx.graph.new_add(oscillator, phase).into()
+}
+
+// sin(2 pi x). 13 nodes
+fn sine_cycle(graph: &mut Graph, x: Input) -> Input {
+	let quarter = quarter_cycle(graph, x);
+	let squared = graph.new_multiply(quarter, quarter);
+	let sine = polynomial(graph, squared.into(), &QUARTER_SINE);
+	graph.new_multiply(sine, quarter).into()
+}
+
+// x wrapped to within 1/2 of 0, and then folded back from the ends to within 1/4. Sines and
+// triangles are odd functions of it. 5 nodes
+fn quarter_cycle(graph: &mut Graph, x: Input) -> Input {
+	let wrapped = wrap(graph, x);
+
+	// Past 1/4, reflected about it, as twice the clamped value minus the wrapped one
+	let clamped = graph.new_clamp(wrapped, -0.25, 0.25);
+	graph.new_mix(wrapped, clamped, 2.0).into()
+}
+
+// 1/x, clamped between min and max. Signals are tracked with a Newton iteration, which
+// converges on 1/x from anywhere between 0 and 2/x, and so lags behind sudden jumps
+fn reciprocal(graph: &mut Graph, x: Input, min: f32, max: f32, name: &str) -> Input {
+	if let Some(x) = x.literal() {
+		return Input::Literal((1.0 / x).max(min).min(max))
+	}
+
+	// r' = r(2 - xr)
+	let estimate = graph.new_value_store(name);
+	let error = graph.new_multiply(x, estimate);
+	let correction = graph.new_sub(2.0, error);
+	let next = graph.new_multiply(estimate, correction);
+	let next = graph.new_clamp(next, min, max);
+	graph.new_store_write(estimate, next);
+	next.into()
+}
+
+// The correction for a falling discontinuity where the phase wraps from 1/2 to -1/2, zero
+// more than a sample away from it. t samples from the jump, it's (1 + t)^2 just before and
+// -(1 - t)^2 just after. 8 nodes, or 10 when inv_dt isn't a literal
+fn poly_blep(graph: &mut Graph, phase: Input, inv_dt: Input) -> Input {
+	let samples = graph.new_multiply(phase, inv_dt);
+	let edge = graph.new_multiply(inv_dt, 0.5);
+	let edge = graph.new_add(edge, -1.0);
+
+	let before = graph.new_sub(samples, edge);
+	let before = graph.new_clamp(before, 0.0, 1.0);
+	let before = graph.new_multiply(before, before);
+
+	let after = graph.new_add(samples, edge);
+	let after = graph.new_clamp(after, -1.0, 0.0);
+	let after = graph.new_multiply(after, after);
+
+	graph.new_sub(before, after).into()
+}
+
+// Rounding comes free with f32 arithmetic: once 1.5 * 2^23 is added to anything within 2^22
+// of 0, there are no bits left below the ones place. x is taken away from it instead, so the
+// optimiser can't fold the constants together
+pub(super) const ROUNDING_OFFSET: f32 = 12_582_912.0;
+
+// x to the nearest multiple of multiple, which has to be a power of two, ties to even. The
+// offset is scaled up to match, so it's exact for |x| under 2^22 times multiple, and rounds
+// to coarser steps past that. The optimiser has a test that this survives it. 2 nodes
+pub(super) fn round(graph: &mut Graph, x: Input, multiple: f32) -> Input {
+	let shifted = graph.new_sub(ROUNDING_OFFSET * multiple, x);
+	graph.new_sub(ROUNDING_OFFSET * multiple, shifted).into()
+}
+
+// x less the nearest whole number, so within 1/2 of 0. 3 nodes
+fn wrap(graph: &mut Graph, x: Input) -> Input {
+	let whole = round(graph, x, 1.0);
+	graph.new_sub(x, whole).into()
+}
+
+// 1 when x is at least 0, otherwise 0. The ramp between is as steep as f32 allows, so only
+// subnormal x land partway up it
+fn step(graph: &mut Graph, x: Input) -> NodeRef {
+	let scaled = graph.new_multiply(x, 2.0f32.powi(126));
+	let scaled = graph.new_add(scaled, 1.0);
+	graph.new_clamp(scaled, 0.0, 1.0)
+}
+
+// Cycles are played back as sums of their harmonics, so the more a table keeps, the more it
+// costs. Each harmonic takes 4 nodes to find, plus up to 4 for every cycle it's audible in, so
+// a single cycle costs up to about 570 nodes, and a full table up to about 2450
+const MAX_WAVETABLE_FRAMES: usize = 8;
+const MAX_WAVETABLE_HARMONICS: usize = 64;
+
+// Harmonics quieter than this are left out
+const QUIET_HARMONIC: f32 = 1.0e-4;
+
+fn wavetable<'a>(ctx: &mut EvaluationContext<'a>, mut args: Args<'a>) -> LispResult<EvalResult<'a>> {
+	let table = args.value()?;
+	let mode = if args.len() > 2 { args.string()? } else { "freq" };
+	let control = args.input()?;
+	let position = args.input()?;
+
+	let frames = match table {
+		EvalResult::Array(elements) => {
+			let nested = elements.iter().all(|e| match e { EvalResult::Array(_) => true, _ => false });
+
+			if nested && !elements.is_empty() {
+				elements.into_iter()
+					.map(EvalResult::expect_array)
+					.collect::<LispResult<Vec<_>>>()?
+			} else {
+				vec![EvalResult::Array(elements).expect_array()?]
+			}
+		}
+
+		EvalResult::Str(path) => wavetable::split_frames(&wav::read(&ctx.dir.join(path))?.samples),
+
+		EvalResult::SynthNode(Input::Node(node)) => match ctx.graph.node(node).op {
+			Op::Sampler(buffer) => wavetable::split_frames(&ctx.graph.buffers[buffer.0]),
+			_ => bail!("'wavetable' can only read the buffers of baked synths"),
+		},
+
+		_ => bail!("'wavetable' expects an array, a WAV file or a baked synth for its table"),
+	};
+
+	ensure!(frames.iter().all(|f| !f.is_empty()), "'wavetable' received an empty cycle");
+
+	let audible = |&(cosine, sine): &(f32, f32)| cosine.abs() > QUIET_HARMONIC || sine.abs() > QUIET_HARMONIC;
+
+	let harmonics = frames.iter()
+		.map(|frame| {
+			let mut harmonics = wavetable::harmonics(frame);
+			harmonics.truncate(MAX_WAVETABLE_HARMONICS + 1);
+			harmonics
+		})
+		.collect::<Vec<_>>();
+
+	let harmonics = wavetable::resample_frames(harmonics, MAX_WAVETABLE_FRAMES);
+
+	// Up to the highest harmonic any cycle has something in
+	let count = harmonics.iter()
+		.filter_map(|harmonics| harmonics.iter().skip(1).rposition(audible))
+		.max()
+		.map_or(0, |last| last + 1);
+
+	let (phase, freq) = match mode {
+		"freq" => (oscillator_phase(ctx, control, "wavetable"), control),
+		"phase" => wavetable_phase(ctx, control),
+		_ => bail!("'wavetable' mode can be \"freq\" or \"phase\", not \"{}\"", mode),
+	};
+
+	let sample_rate = ctx.sample_rate;
+	let graph = &mut ctx.graph;
+
+	// The cosines and sines of each harmonic, from the two below it, as
+	// cos(kx) = 2cos(x)cos((k - 1)x) - cos((k - 2)x), and the same for sines
+	let mut waves = vec![(Input::Literal(1.0), Input::Literal(0.0))];
+	if count > 0 {
+		let shifted = graph.new_add(phase, 0.25);
+		waves.push((sine_cycle(graph, shifted.into()), sine_cycle(graph, phase)));
+	}
+
+	if count > 1 {
+		let twice_cosine = graph.new_multiply(waves[1].0, 2.0);
+
+		for k in 2..=count {
+			let mut next = |this: Input, before: Input| -> Input {
+				let scaled = graph.new_multiply(twice_cosine, this);
+				graph.new_sub(scaled, before).into()
+			};
+
+			let ((cosine, sine), (cosine_before, sine_before)) = (waves[k - 1], waves[k - 2]);
+			waves.push((next(cosine, cosine_before), next(sine, sine_before)));
+		}
+	}
+
+	// Harmonics are faded out an octave band at a time, as the band's highest harmonic rises
+	// through the top fifth of the way to nyquist
+	let freq = absolute(graph, freq);
+	let bands = (0..)
+		.map(|band| 1 << band)
+		.take_while(|&start| start <= count)
+		.map(|start| {
+			let end = (2 * start).min(count + 1);
+			let fade = graph.new_multiply(freq, -10.0 * (end - 1) as f32 / sample_rate);
+			let fade = graph.new_add(fade, 5.0);
+			(start..end, graph.new_clamp(fade, 0.0, 1.0))
+		})
+		.collect::<Vec<_>>();
+
+	let cycles = harmonics.iter()
+		.map(|harmonics| {
+			let dc = harmonics[0].0;
+
+			bands.iter().fold(Input::Literal(dc), |sum, (range, fade)| {
+				let band = harmonics.iter().zip(&waves).take(range.end).skip(range.start)
+					.flat_map(|(&(cosine, sine), &(cosine_wave, sine_wave))| vec![(cosine, cosine_wave), (sine, sine_wave)])
+					.filter(|&(amplitude, _)| amplitude.abs() > QUIET_HARMONIC)
+					.fold(None, |band, (amplitude, wave)| {
+						let term = graph.new_multiply(wave, amplitude);
+						Some(match band {
+							Some(band) => graph.new_add(band, term).into(),
+							None => Input::from(term),
+						})
+					});
+
+				match band {
+					Some(band) => {
+						let faded = graph.new_multiply(band, *fade);
+						graph.new_add(sum, faded).into()
+					}
+					None => sum,
+				}
+			})
+		})
+		.collect::<Vec<_>>();
+
+	// Each cycle takes over from the one before as the position moves through the stretch
+	// between them
+	let clamped_position = graph.new_clamp(position, 0.0, 1.0);
+	let frame_position = graph.new_multiply(clamped_position, (cycles.len() - 1) as f32);
+
+	let first = cycles[0];
+	Ok(cycles.into_iter().enumerate().skip(1).fold(first, |sum, (i, cycle)| {
+		let amount = graph.new_add(frame_position, -((i - 1) as f32));
+		let amount = graph.new_clamp(amount, 0.0, 1.0);
+		graph.new_mix(sum, cycle, amount).into()
+	}).into())
+}
+
+// A wavetable driven by its phase in cycles, along with its frequency, found from how far the
+// phase moves each sample. The step is wrapped to within half a cycle, so that ramps wrapping
+// from 1 to 0 don't look like sudden jumps in pitch
+fn wavetable_phase(ctx: &mut EvaluationContext, phase: Input) -> (Input, Input) {
+	let sample_rate = ctx.sample_rate;
+	let graph = &mut ctx.graph;
+
+	let previous = graph.new_value_store("wavetable phase");
+	let step = graph.new_sub(phase, previous);
+	graph.new_store_write(previous, phase);
+
+	let step = wrap(graph, step.into());
+	let freq = graph.new_multiply(step, sample_rate);
+
+	(phase, freq.into())
+}
+
+// |x|, as twice the positive part minus x
+fn absolute(graph: &mut Graph, x: Input) -> Input {
+	let positive = graph.new_clamp(x, 0.0, f32::MAX);
+	let doubled = graph.new_multiply(positive, 2.0);
+	graph.new_sub(doubled, x).into()
+}
+
+// Evaluates the polynomial with coefficients from the constant term up
+fn polynomial(graph: &mut Graph, x: Input, coefficients: &[f32]) -> Input {
+	let (&last, rest) = coefficients.split_last().unwrap();
+
+	rest.iter().rev().fold(Input::Literal(last), |sum, &coefficient| {
+		let sum = graph.new_multiply(sum, x);
+		graph.new_add(sum, coefficient).into()
+	})
+}
+
+// polynomial for constants
+fn polynomial_value(x: f32, coefficients: &[f32]) -> f32 {
+	coefficients.iter().rev().fold(0.0, |sum, &coefficient| sum * x + coefficient)
+}
+
+// White noise is generated while the patch loads, into two tables of coprime lengths that
+// are played back together. Sequencers only step on rising edges, so the tables take turns
+// stepping every other sample, and the noise only repeats once every 54 hours or so at 44.1khz
+const NOISE_TABLE_LENGTHS: [usize; 2] = [65521, 65536];
+
+// Pink and brown noise are scaled to this RMS, which keeps their peaks around 1
+const COLOURED_NOISE_RMS: f32 = 0.25;
+
+// Paul Kellet's economy pink noise filter at 44.1khz, as (pole, gain)
+const PINK_POLES: [(f32, f32); 3] = [(0.99765, 0.0990460), (0.96300, 0.2965164), (0.57000, 1.0526913)];
+const PINK_DIRECT: f32 = 0.1848;
+
+// Below this, brown noise flattens out instead of wandering off
+const BROWN_CORNER: f32 = 20.0;
+
+fn noise<'a>(ctx: &mut EvaluationContext<'a>, mut args: Args<'a>) -> LispResult<EvalResult<'a>> {
+	let colour = args.string()?;
+	let seed = if args.len() > 0 { Some(args.constant()?) } else { None };
+
+	let (poles, direct) = match colour {
+		"white" => (Vec::new(), 1.0),
+
+		"pink" => {
+			// Each pole keeps its gain at DC as the sample rate changes
+			let poles = PINK_POLES.iter()
+				.map(|&(pole, gain)| {
+					let scaled = pole.powf(44100.0 / ctx.sample_rate);
+					(scaled, gain * (1.0 - scaled) / (1.0 - pole))
+				})
+				.collect::<Vec<_>>();
+
+			(poles, PINK_DIRECT)
+		}
+
+		"brown" => {
+			let pole = (-2.0 * PI * BROWN_CORNER / ctx.sample_rate).exp();
+			(vec![(pole, 1.0 - pole)], 0.0)
+		}
+
+		_ => bail!("'noise' can be 'white, 'pink or 'brown, not '{}", colour),
+	};
+
+	let white = white_noise(ctx, seed);
+	if poles.is_empty() {
+		return Ok(white.into())
+	}
+
+	// The sum of the squares of the filter's impulse response, which white noise's
+	// variance is scaled by
+	let energy = direct * direct
+		+ poles.iter().map(|&(_, g)| 2.0 * direct * g).sum::<f32>()
+		+ poles.iter()
+			.flat_map(|&(pi, gi)| poles.iter().map(move |&(pj, gj)| gi * gj / (1.0 - pi * pj)))
+			.sum::<f32>();
+
+	let white_rms = 1.0 / 3.0f32.sqrt();
+	let scale = COLOURED_NOISE_RMS / (white_rms * energy.sqrt());
+
+	let graph = &mut ctx.graph;
+	let mut sum: Input = graph.new_multiply(white, direct * scale).into();
+
+	for (pole, gain) in poles {
+		let store = graph.new_value_store("noise filter");
+		let decayed = graph.new_multiply(store, pole);
+		let fed = graph.new_multiply(white, gain * scale);
+		let filtered = graph.new_add(decayed, fed);
+		graph.new_store_write(store, filtered);
+
+		sum = graph.new_add(sum, filtered).into();
+	}
+
+	Ok(sum.into())
+}
+
+fn random_step<'a>(ctx: &mut EvaluationContext<'a>, mut args: Args<'a>) -> LispResult<EvalResult<'a>> {
+	let rate = args.input()?;
+	let seed = if args.len() > 0 { Some(args.constant()?) } else { None };
+
+	let white = white_noise(ctx, seed);
+	Ok(sample_and_hold(ctx, "random-step", rate, white).into())
+}
+
+// Takes a new value from input rate times a second, holding it in between. It counts down
+// from 1 to the next one, and starts out empty, so the first value is taken straight away
+fn sample_and_hold(ctx: &mut EvaluationContext, func_name: &str, rate: Input, input: Input) -> NodeRef {
+	let sample_rate = ctx.sample_rate;
+	let graph = &mut ctx.graph;
+
+	let remaining = graph.new_value_store(&format!("{} countdown", func_name));
+	let dt = graph.new_multiply(rate, 1.0 / sample_rate);
+	let dt = graph.new_clamp(dt, 0.0, 1.0);
+	let next = graph.new_sub(remaining, dt);
+	let overdue = graph.new_multiply(next, -1.0);
+	let expired = step(graph, overdue.into());
+	let refilled = graph.new_add(next, expired);
+	graph.new_store_write(remaining, refilled);
+
+	let held = graph.new_value_store(&format!("{} value", func_name));
+	let value = graph.new_mix(held, input, expired);
+	graph.new_store_write(held, value);
+
+	value
+}
+
+// Uniform noise from -1 to 1, from the sum of a sample from each table wrapped to within 1/2
+// of 0. One of them is new each sample, which makes each sum a new one. Unseeded sources are
+// seeded with how many came before them, kept apart from every seed
+fn white_noise(ctx: &mut EvaluationContext, seed: Option<f32>) -> Input {
+	let state = match seed {
+		Some(seed) => hash(u64::from(seed.to_bits())),
+		None => {
+			ctx.noise_sources += 1;
+			hash(ctx.noise_sources as u64 | 1 << 32)
+		}
+	};
+
+	let [first_len, second_len] = NOISE_TABLE_LENGTHS;
+	let mut first = uniform_noise(state, first_len + second_len);
+	let second = first.split_off(first_len);
+
+	let graph = &mut ctx.graph;
+	let first = graph.new_buffer(first);
+	let second = graph.new_buffer(second);
+
+	// Flips between 0 and 1 every sample, and is read before it's written. Reset is left
+	// high, as 'sequencer' leaves it
+	let toggle = graph.new_value_store("noise toggle");
+	let flipped = graph.new_sub(1.0, toggle);
+	let first = graph.new_sequencer(first, flipped, 1.0);
+	let second = graph.new_sequencer(second, toggle, 1.0);
+	graph.new_store_write(toggle, flipped);
+
+	let sum = graph.new_add(first, second);
+	let wrapped = wrap(graph, sum.into());
+	graph.new_multiply(wrapped, 2.0).into()
+}
+
+// Values from 0 to 1 on a grid of 2^-24, from SplitMix64 starting at state
+fn uniform_noise(state: u64, len: usize) -> Vec<f32> {
+	let scale = (-24.0f32).exp2();
+
+	(0..len as u64)
+		.map(|i| (hash(state.wrapping_add(i.wrapping_mul(SPLITMIX_INCREMENT))) >> 40) as f32 * scale)
+		.collect()
+}
+
+const SPLITMIX_INCREMENT: u64 = 0x9E37_79B9_7F4A_7C15;
+
+// SplitMix64's finaliser, so that nearby seeds give unrelated noise
+fn hash(x: u64) -> u64 {
+	let mut x = x.wrapping_add(SPLITMIX_INCREMENT);
+	x = (x ^ (x >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+	x = (x ^ (x >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+	x ^ (x >> 31)
+}
+
+// FM operators are phase modulated sines, as on the DX7, which keeps their pitch steady
+// however hard they're modulated. Indexes are in radians, and phases in cycles
+fn fm_op<'a>(ctx: &mut EvaluationContext<'a>, mut args: Args<'a>) -> LispResult<EvalResult<'a>> {
+	let ratio = args.input()?;
+	let index = args.input()?;
+	let env = args.input()?;
+	let modulation = args.input()?;
+	Ok(operator(ctx, ratio, index, env, modulation).into())
+}
+
+fn fm_stack<'a>(ctx: &mut EvaluationContext<'a>, mut args: Args<'a>) -> LispResult<EvalResult<'a>> {
+	let ratios = args.array()?;
+	let indexes = args.array()?;
+	let env = args.input()?;
+
+	ensure!(!ratios.is_empty(), "'fm-stack' needs at least one ratio");
+	ensure!(indexes.len() + 1 == ratios.len(),
+		"'fm-stack' needs an index for each ratio but the first, got {} ratios and {} indexes", ratios.len(), indexes.len());
+
+	// Built from the top down, so each operator's modulation is ready for the one below
+	let top = operator(ctx, ratios[ratios.len() - 1].into(), Input::Literal(0.0), env, Input::Literal(0.0));
+	let output = ratios.iter().rev().skip(1).zip(indexes.iter().rev())
+		.fold(top, |modulation, (&ratio, &index)| operator(ctx, ratio.into(), index.into(), env, modulation));
+
+	Ok(output.into())
+}
+
+fn fm_parallel<'a>(ctx: &mut EvaluationContext<'a>, mut args: Args<'a>) -> LispResult<EvalResult<'a>> {
+	let ratios = args.array()?;
+	let index = args.input()?;
+	let env = args.input()?;
+	let modulation = args.input()?;
+
+	ensure!(!ratios.is_empty(), "'fm-parallel' needs at least one ratio");
+
+	let sum = ratios.iter().fold(Input::Literal(0.0), |sum, &ratio| {
+		let carrier = operator(ctx, ratio.into(), index, env, modulation);
+		ctx.graph.new_add(sum, carrier).into()
+	});
+
+	Ok(ctx.graph.new_multiply(sum, 1.0 / ratios.len() as f32).into())
+}
+
+fn fm_feedback<'a>(ctx: &mut EvaluationContext<'a>, mut args: Args<'a>) -> LispResult<EvalResult<'a>> {
+	let ratio = args.input()?;
+	let feedback = args.input()?;
+	let env = args.input()?;
+
+	// Averaging over two samples keeps the loop from flipping between extremes each sample
+	let last = ctx.graph.new_value_store("fm-feedback output");
+	let before_last = ctx.graph.new_value_store("fm-feedback output");
+	let average = ctx.graph.new_add(last, before_last);
+	let average = ctx.graph.new_multiply(average, 0.5);
+
+	let output = operator(ctx, ratio, feedback, env, average.into());
+	ctx.graph.new_store_write(before_last, last);
+	ctx.graph.new_store_write(last, output);
+
+	Ok(output.into())
+}
+
+// env * sin(ratio * key-freq, index * modulation / 2 pi)
+fn operator(ctx: &mut EvaluationContext, ratio: Input, index: Input, env: Input, modulation: Input) -> Input {
+	let phase = operator_phase(ctx, ratio);
+
+	let offset = ctx.graph.new_multiply(modulation, index);
+	let offset = ctx.graph.new_multiply(offset, 0.5 / PI);
+	let phase = ctx.graph.new_add(phase, offset);
+
+	let sine = sine_cycle(&mut ctx.graph, phase.into());
+	ctx.graph.new_multiply(sine, env).into()
+}
+
+// Operators share a phase accumulator at key-freq, which wraps every KEY_PHASE_CYCLES cycles.
+// Any ratio that's a whole number of 1/KEY_PHASE_CYCLES still moves on a whole number of
+// cycles when it wraps, so it can scale the shared phase. Other ratios need their own
+const KEY_PHASE_CYCLES: f32 = 16.0;
+
+fn operator_phase(ctx: &mut EvaluationContext, ratio: Input) -> Input {
+	match ratio.literal() {
+		Some(ratio) if (ratio * KEY_PHASE_CYCLES).fract() == 0.0 => {
+			let key_phase = key_phase(ctx);
+			ctx.graph.new_multiply(key_phase, ratio).into()
+		}
+
+		_ => {
+			let key_freq = key_parameter(&mut ctx.graph, "key-freq");
+			let freq = ctx.graph.new_multiply(key_freq, ratio);
+			oscillator_phase(ctx, freq.into(), "fm-op")
+		}
+	}
+}
+
+// The key's phase in cycles, made for the first operator that needs it. 6 nodes
+fn key_phase(ctx: &mut EvaluationContext) -> Input {
+	if let Some(phase) = ctx.key_phase {
+		return phase
+	}
+
+	let key_freq = key_parameter(&mut ctx.graph, "key-freq");
+	let sample_rate = ctx.sample_rate;
+	let graph = &mut ctx.graph;
+
+	let store = graph.new_value_store("key phase");
+	let dt = graph.new_multiply(key_freq, 1.0 / sample_rate);
+	let next = graph.new_add(store, dt);
+	let cycles = round(graph, next.into(), KEY_PHASE_CYCLES);
+	let phase = graph.new_sub(next, cycles);
+	graph.new_store_write(store, phase);
+
+	ctx.key_phase = Some(phase.into());
+	phase.into()
+}
+
+fn lowpass<'a>(ctx: &mut EvaluationContext<'a>, mut args: Args<'a>) -> LispResult<EvalResult<'a>> {
+	let cutoff = args.input()?;
+	let input = args.input()?;
+	Ok(ctx.graph.new_lowpass(input, cutoff).into())
+}
+
+fn highpass<'a>(ctx: &mut EvaluationContext<'a>, mut args: Args<'a>) -> LispResult<EvalResult<'a>> {
+	let cutoff = args.input()?;
+	let input = args.input()?;
+	Ok(ctx.graph.new_highpass(input, cutoff).into())
+}
+
+// Resonant filters are built from trapezoidal integrators, which stay stable however fast
+// their coefficients change. Their coefficients would need tan and division, so they're
+// rewritten in terms of the sine and cosine of the cutoff, leaving only divisions by
+// numbers in a known range
+
+// Cutoffs are kept below nyquist, where the coefficients blow up
+const MIN_CUTOFF: f32 = 5.0;
+const MAX_CUTOFF_RATIO: f32 = 0.49;
+const MAX_Q: f32 = 100.0;
+
+// A ladder oscillates once its feedback passes 4, and this much more makes sure it does
+const MAX_LADDER_FEEDBACK: f32 = 4.4;
+
+fn svf<'a>(ctx: &mut EvaluationContext<'a>, mut args: Args<'a>) -> LispResult<EvalResult<'a>> {
+	let mode = args.string()?;
+	let cutoff = args.input()?;
+	let q = args.input()?;
+	let input = args.input()?;
+
+	ensure!(["lp", "hp", "bp", "notch", "peak"].contains(&mode),
+		"'svf' mode can be 'lp, 'hp, 'bp, 'notch or 'peak, not '{}", mode);
+
+	let (sin, cos) = cutoff_sin_cos(ctx, cutoff);
+	let graph = &mut ctx.graph;
+
+	// Damping, 1/q
+	let k = reciprocal(graph, q, 1.0 / MAX_Q, 2.0, "svf damping");
+
+	// With g = tan(w), a1 = 1/(1 + g(g + k)), a2 = g a1 and a3 = g a2. Multiplying through
+	// by cos^2 leaves 1 + k sin cos underneath, which is at most 2
+	let sin_cos = graph.new_multiply(sin, cos);
+	let denominator = graph.new_multiply(k, sin_cos);
+	let denominator = graph.new_add(denominator, 1.0);
+	let inverse = reciprocal_between(graph, denominator.into(), 1.0, 2.0);
+
+	let cos_squared = graph.new_multiply(cos, cos);
+	let sin_squared = graph.new_multiply(sin, sin);
+	let a1 = graph.new_multiply(cos_squared, inverse);
+	let a2 = graph.new_multiply(sin_cos, inverse);
+	let a3 = graph.new_multiply(sin_squared, inverse);
+
+	let ic1 = graph.new_value_store("svf state");
+	let ic2 = graph.new_value_store("svf state");
+
+	let v3 = graph.new_sub(input, ic2);
+	let v1 = graph.new_multiply(a1, ic1);
+	let v1_input = graph.new_multiply(a2, v3);
+	let band = graph.new_add(v1, v1_input);
+
+	let v2 = graph.new_multiply(a2, ic1);
+	let v2 = graph.new_add(ic2, v2);
+	let v2_input = graph.new_multiply(a3, v3);
+	let low = graph.new_add(v2, v2_input);
+
+	let next_ic1 = graph.new_multiply(band, 2.0);
+	let next_ic1 = graph.new_sub(next_ic1, ic1);
+	graph.new_store_write(ic1, next_ic1);
+
+	let next_ic2 = graph.new_multiply(low, 2.0);
+	let next_ic2 = graph.new_sub(next_ic2, ic2);
+	graph.new_store_write(ic2, next_ic2);
+
+	// The input with the band removed
+	let damped_band = graph.new_multiply(k, band);
+	let notch = graph.new_sub(input, damped_band);
+
+	let output = match mode {
+		"lp" => low,
+		"bp" => damped_band,
+		"notch" => notch,
+		"hp" => graph.new_sub(notch, low),
+
+		_ => {
+			let twice_low = graph.new_multiply(low, 2.0);
+			graph.new_sub(notch, twice_low)
+		}
+	};
+
+	Ok(output.into())
+}
+
+fn ladder<'a>(ctx: &mut EvaluationContext<'a>, mut args: Args<'a>) -> LispResult<EvalResult<'a>> {
+	let cutoff = args.input()?;
+	let resonance = args.input()?;
+	let drive = args.input()?;
+	let input = args.input()?;
+
+	let (sin, cos) = cutoff_sin_cos(ctx, cutoff);
+	let graph = &mut ctx.graph;
+
+	// Each stage is y = G x + (1 - G) s, where G = g/(1 + g) with g = tan(w), or sin/(sin + cos)
+	let denominator = graph.new_add(sin, cos);
+	let inverse = reciprocal_between(graph, denominator.into(), 1.0, 2.0);
+	let gain = graph.new_multiply(sin, inverse);
+	let leak = graph.new_sub(1.0, gain);
+
+	let states = (0..4)
+		.map(|_| graph.new_value_store("ladder state"))
+		.collect::<Vec<_>>();
+
+	// Without saturation, the output is G^4 u + S, where S is what the states contribute.
+	// Solving for the feedback within the same sample keeps the resonance in tune
+	let mut from_states: Input = Input::Literal(0.0);
+	for &state in &states {
+		let scaled = graph.new_multiply(from_states, gain);
+		from_states = graph.new_add(scaled, state).into();
+	}
+	let from_states = graph.new_multiply(from_states, leak);
+
+	let gain_squared = graph.new_multiply(gain, gain);
+	let gain_fourth = graph.new_multiply(gain_squared, gain_squared);
+
+	let resonance = graph.new_clamp(resonance, 0.0, 1.0);
+	let k = graph.new_multiply(resonance, MAX_LADDER_FEEDBACK);
+
+	// y = (G^4 x + S)/(1 + k G^4)
+	let driven = graph.new_multiply(input, drive);
+	let estimate = graph.new_multiply(gain_fourth, driven);
+	let estimate = graph.new_add(estimate, from_states);
+	let loop_gain = graph.new_multiply(k, gain_fourth);
+	let loop_gain = graph.new_add(loop_gain, 1.0);
+	let inverse = reciprocal_between(graph, loop_gain.into(), 1.0, 1.0 + MAX_LADDER_FEEDBACK);
+	let estimate = graph.new_multiply(estimate, inverse);
+
+	let fed_back = graph.new_multiply(k, estimate);
+	let fed_back = graph.new_sub(driven, fed_back);
+	let mut stage = soft_clip(graph, fed_back.into());
+
+	for state in states {
+		let v = graph.new_sub(stage, state);
+		let v = graph.new_multiply(v, gain);
+		let low = graph.new_add(v, state);
+		let next = graph.new_add(low, v);
+		graph.new_store_write(state, next);
+		stage = low.into();
+	}
+
+	Ok(stage.into())
+}
+
+// The sine and cosine of pi * cutoff / sample rate, from polynomials good to about 1e-6
+fn cutoff_sin_cos(ctx: &mut EvaluationContext, cutoff: Input) -> (Input, Input) {
+	let sample_rate = ctx.sample_rate;
+	let graph = &mut ctx.graph;
+
+	let cutoff = graph.new_clamp(cutoff, MIN_CUTOFF, MAX_CUTOFF_RATIO * sample_rate);
+	let w = graph.new_multiply(cutoff, PI / sample_rate);
+	let w_squared = graph.new_multiply(w, w);
+
+	let sin = polynomial(graph, w_squared.into(), &[1.0, -1.0 / 6.0, 1.0 / 120.0, -1.0 / 5040.0, 1.0 / 362_880.0]);
+	let sin = graph.new_multiply(sin, w);
+	let cos = polynomial(graph, w_squared.into(), &[1.0, -0.5, 1.0 / 24.0, -1.0 / 720.0, 1.0 / 40320.0, -1.0 / 3_628_800.0]);
+
+	(sin.into(), cos)
+}
+
+// 1/x for x between low and high, as good as f32 gets
+fn reciprocal_between(graph: &mut Graph, x: Input, low: f32, high: f32) -> Input {
+	reciprocal_within(graph, x, low, high, 1.0e-7)
+}
+
+// 1/x for x between low and high, from the linear guess with the least relative error,
+// followed by Newton steps until the relative error is within tolerance
+fn reciprocal_within(graph: &mut Graph, x: Input, low: f32, high: f32, tolerance: f32) -> Input {
+	let slope = 2.0 / (low * high + (low + high).powi(2) / 4.0);
+	let mut error = 1.0 - slope * low * high;
+
+	let guess = graph.new_multiply(x, -slope);
+	let mut estimate: Input = graph.new_add(guess, slope * (low + high)).into();
+
+	while error > tolerance {
+		let product = graph.new_multiply(x, estimate);
+		let correction = graph.new_sub(2.0, product);
+		estimate = graph.new_multiply(estimate, correction).into();
+		error *= error;
+	}
+
+	estimate
+}
+
+// reciprocal_within for constants, one step at a time like the nodes
+fn reciprocal_within_value(x: f32, low: f32, high: f32, tolerance: f32) -> f32 {
+	let slope = 2.0 / (low * high + (low + high).powi(2) / 4.0);
+	let mut error = 1.0 - slope * low * high;
+	let mut estimate = x * -slope + slope * (low + high);
+
+	while error > tolerance {
+		estimate *= 2.0 - x * estimate;
+		error *= error;
+	}
+
+	estimate
+}
+
+// x - 4x^3/27, which passes small signals through untouched and flattens out at -1 and 1
+fn soft_clip(graph: &mut Graph, x: Input) -> Input {
+	let clipped = graph.new_clamp(x, -1.5, 1.5);
+	let cubed = graph.new_multiply(clipped, clipped);
+	let cubed = graph.new_multiply(cubed, clipped);
+	let cubic = graph.new_multiply(cubed, -4.0 / 27.0);
+	graph.new_add(clipped, cubic).into()
+}
+
+// Delay lines are chains of stores, one for each sample, shifted along every sample. That
+// costs a node per sample of delay in every voice, about 880 for a line of 20ms at 44.1khz,
+// so they're kept to what a handful of voices can run in real time
+const MAX_DELAY_SECONDS: f32 = 0.02;
+const MAX_TOTAL_DELAY_SECONDS: f32 = 0.04;
+
+// Reads at a signal time interpolate over every stage the time could reach, at 5 nodes a
+// stage, so they only reach this far back from the end of the line, for about 450 nodes
+const MAX_MODULATED_SECONDS: f32 = 0.002;
+
+// Anything more than this and the repeats never die away
+const MAX_DELAY_FEEDBACK: f32 = 0.99;
+
+fn delay<'a>(ctx: &mut EvaluationContext<'a>, mut args: Args<'a>) -> LispResult<EvalResult<'a>> {
+	let time = args.input()?;
+	let feedback = args.input()?;
+	let input = args.input()?;
+	let max_time = args.constant()?;
+
+	let modulated = time.literal().is_none();
+	let max_time = match time.literal() {
+		Some(time) => {
+			ensure!(time >= 0.0 && (max_time <= 0.0 || time <= max_time),
+				"'delay' can't delay by {} seconds with a max-time of {}", time, max_time);
+			max_time.max(time)
+		}
+
+		None if max_time > 0.0 => max_time,
+		None => bail!("'delay' requires a max-time when its time is a signal"),
+	};
+
+	let feedback = match feedback.literal() {
+		Some(feedback) => Input::Literal(feedback.max(-MAX_DELAY_FEEDBACK).min(MAX_DELAY_FEEDBACK)),
+		None => ctx.graph.new_clamp(feedback, -MAX_DELAY_FEEDBACK, MAX_DELAY_FEEDBACK).into(),
+	};
+
+	let position = delay_position(ctx, time);
+	let min_time = if modulated { max_time - MAX_MODULATED_SECONDS } else { 0.0 };
+	Ok(feedback_delay(ctx, "delay", position, min_time, max_time, feedback, input)?.into())
+}
+
+// Reads position samples back from a new delay line up to max_time seconds long, feeding
+// what it reads back in scaled by feedback. It never reads closer than min_time, so a
+// modulated position only needs interpolating over the stages past it
+fn feedback_delay(
+	ctx: &mut EvaluationContext, func_name: &str, position: Input, min_time: f32, max_time: f32,
+	feedback: Input, input: Input,
+) -> LispResult<Input> {
+	let stages = delay_stages(ctx, func_name, max_time)?;
+	let elements = stages.iter().map(|&s| s.into()).collect::<Vec<Input>>();
+
+	// The first stage is a sample back, since the input can't depend on itself
+	let first = ((min_time.max(0.0) * ctx.sample_rate).floor() as usize).max(1).min(elements.len()) - 1;
+	let offset = (first + 1) as f32;
+	let position = match position.literal() {
+		Some(position) => Input::Literal(position - offset),
+		None => ctx.graph.new_add(position, -offset).into(),
+	};
+
+	let graph = &mut ctx.graph;
+	let delayed = tap(graph, &elements[first..], position);
+	let fed_back = graph.new_multiply(delayed, feedback);
+	let input = graph.new_add(input, fed_back);
+
+	ctx.delay_lines.push((stages, input.into()));
+	Ok(delayed)
+}
+
+fn delay_line<'a>(ctx: &mut EvaluationContext<'a>, mut args: Args<'a>) -> LispResult<EvalResult<'a>> {
+	let max_time = args.constant()?;
+	let input = args.input()?;
+
+	let stages = delay_stages(ctx, "delay-line", max_time)?;
+
+	ctx.delay_lines.push((stages, input));
+	Ok(EvalResult::DelayLine(ctx.delay_lines.len() - 1))
+}
+
+fn delay_tap<'a>(ctx: &mut EvaluationContext<'a>, mut args: Args<'a>) -> LispResult<EvalResult<'a>> {
+	let line = args.delay_line()?;
+	let time = args.input()?;
+
+	let (stages, input) = &ctx.delay_lines[line];
+	let elements = std::iter::once(*input)
+		.chain(stages.iter().map(|&s| s.into()))
+		.collect::<Vec<Input>>();
+
+	let length = stages.len() as f32 / ctx.sample_rate;
+	let position = delay_position(ctx, time);
+
+	match time.literal() {
+		Some(time) => {
+			ensure!(time >= 0.0 && time <= length,
+				"'delay-tap' can't read {} seconds back from a delay line {} seconds long", time, length);
+			Ok(tap(&mut ctx.graph, &elements, position).into())
+		}
+
+		None => {
+			let first = elements.len().saturating_sub((MAX_MODULATED_SECONDS * ctx.sample_rate).ceil() as usize + 1);
+			let position = ctx.graph.new_add(position, -(first as f32));
+			Ok(tap(&mut ctx.graph, &elements[first..], position.into()).into())
+		}
+	}
+}
+
+fn delay_stages(ctx: &mut EvaluationContext, func_name: &str, seconds: f32) -> LispResult<Vec<StoreRef>> {
+	ensure!(seconds > 0.0 && seconds <= MAX_DELAY_SECONDS,
+		"'{}' can delay by up to {} seconds, not {}", func_name, MAX_DELAY_SECONDS, seconds);
+
+	let count = (seconds * ctx.sample_rate).ceil() as usize;
+	delay_stage_chain(ctx, func_name, count)
+}
+
+fn delay_stage_chain(ctx: &mut EvaluationContext, func_name: &str, count: usize) -> LispResult<Vec<StoreRef>> {
+	let total = ctx.delay_lines.iter().map(|(stages, _)| stages.len()).sum::<usize>() + count;
+
+	ensure!(total as f32 <= MAX_TOTAL_DELAY_SECONDS * ctx.sample_rate,
+		"A patch's delays can't add up to more than {} seconds", MAX_TOTAL_DELAY_SECONDS);
+
+	let name = format!("{} stage", func_name);
+
+	Ok((0..count).map(|_| ctx.graph.new_value_store(&name)).collect())
+}
+
+// How many samples back time seconds is
+fn delay_position(ctx: &mut EvaluationContext, time: Input) -> Input {
+	match time.literal() {
+		Some(time) => Input::Literal(time * ctx.sample_rate),
+		None => ctx.graph.new_multiply(time, ctx.sample_rate).into(),
+	}
+}
+
+// Linearly interpolates between elements, clamping position to the ends
+fn tap(graph: &mut Graph, elements: &[Input], position: Input) -> Input {
+	let last = elements.len() - 1;
+
+	if let Some(position) = position.literal() {
+		let position = position.max(0.0).min(last as f32);
+		let index = position.floor() as usize;
+		let fraction = position - index as f32;
+
+		if fraction == 0.0 {
+			return elements[index]
+		}
+
+		return graph.new_mix(elements[index], elements[index + 1], fraction).into()
+	}
+
+	// The first element, plus each step to the next for as far as position reaches
+	elements.windows(2).enumerate().fold(elements[0], |sum, (index, pair)| {
+		let reach = graph.new_add(position, -(index as f32));
+		let reach = graph.new_clamp(reach, 0.0, 1.0);
+		let step = graph.new_sub(pair[1], pair[0]);
+		let step = graph.new_multiply(step, reach);
+		graph.new_add(sum, step).into()
+	})
+}
+
+// Modulation effects sweep a delay or allpass chain with a sine LFO. Their stereo variants
+// run a second copy with the LFO upside down, so the sides sweep in opposite directions.
+// The delays only sweep as far as any other modulated read can, so chorus stays short, like
+// the bucket brigade choruses in old synths
+const CHORUS_DELAY: f32 = 0.0035;
+const CHORUS_SWING: f32 = MAX_MODULATED_SECONDS / 2.0;
+const FLANGER_MIN_DELAY: f32 = 0.0002;
+const FLANGER_SWEEP: f32 = MAX_MODULATED_SECONDS;
+const MAX_FLANGER_FEEDBACK: f32 = 0.95;
+const PHASER_MIN_FREQ: f32 = 200.0;
+const PHASER_MAX_FREQ: f32 = 4000.0;
+const MAX_PHASER_STAGES: f32 = 12.0;
+
+fn chorus<'a>(ctx: &mut EvaluationContext<'a>, args: Args<'a>) -> LispResult<EvalResult<'a>> {
+	chorus_sides(ctx, args, false)
+}
+
+fn chorus_stereo<'a>(ctx: &mut EvaluationContext<'a>, args: Args<'a>) -> LispResult<EvalResult<'a>> {
+	chorus_sides(ctx, args, true)
+}
+
+fn chorus_sides<'a>(ctx: &mut EvaluationContext<'a>, mut args: Args<'a>, stereo: bool) -> LispResult<EvalResult<'a>> {
+	let rate = args.input()?;
+	let depth = args.input()?;
+	let mix = args.input()?;
+	let input = args.input()?;
+
+	let depth = ctx.graph.new_clamp(depth, 0.0, 1.0);
+	let mix = ctx.graph.new_clamp(mix, 0.0, 1.0);
+
+	modulated(ctx, rate, stereo, |ctx, lfo| {
+		let swing = ctx.graph.new_multiply(lfo, depth);
+		let swing = ctx.graph.new_multiply(swing, CHORUS_SWING * ctx.sample_rate);
+		let position = ctx.graph.new_add(swing, CHORUS_DELAY * ctx.sample_rate);
+
+		let wet = feedback_delay(ctx, "chorus", position.into(), CHORUS_DELAY - CHORUS_SWING,
+			CHORUS_DELAY + CHORUS_SWING, Input::Literal(0.0), input)?;
+
+		Ok(ctx.graph.new_mix(input, wet, mix).into())
+	})
+}
+
+fn flanger<'a>(ctx: &mut EvaluationContext<'a>, args: Args<'a>) -> LispResult<EvalResult<'a>> {
+	flanger_sides(ctx, args, false)
+}
+
+fn flanger_stereo<'a>(ctx: &mut EvaluationContext<'a>, args: Args<'a>) -> LispResult<EvalResult<'a>> {
+	flanger_sides(ctx, args, true)
+}
+
+fn flanger_sides<'a>(ctx: &mut EvaluationContext<'a>, mut args: Args<'a>, stereo: bool) -> LispResult<EvalResult<'a>> {
+	let rate = args.input()?;
+	let depth = args.input()?;
+	let feedback = args.input()?;
+	let input = args.input()?;
+
+	let depth = ctx.graph.new_clamp(depth, 0.0, 1.0);
+	let feedback = ctx.graph.new_clamp(feedback, -MAX_FLANGER_FEEDBACK, MAX_FLANGER_FEEDBACK);
+
+	modulated(ctx, rate, stereo, |ctx, lfo| {
+		let sweep = lfo_sweep(&mut ctx.graph, lfo, depth.into());
+		let sweep = ctx.graph.new_multiply(sweep, FLANGER_SWEEP * ctx.sample_rate);
+		let position = ctx.graph.new_add(sweep, FLANGER_MIN_DELAY * ctx.sample_rate);
+
+		let wet = feedback_delay(ctx, "flanger", position.into(), FLANGER_MIN_DELAY,
+			FLANGER_MIN_DELAY + FLANGER_SWEEP, feedback.into(), input)?;
+
+		// Half and half, so the notches go all the way down
+		Ok(ctx.graph.new_mix(input, wet, 0.5).into())
+	})
+}
+
+fn phaser<'a>(ctx: &mut EvaluationContext<'a>, args: Args<'a>) -> LispResult<EvalResult<'a>> {
+	phaser_sides(ctx, args, false)
+}
+
+fn phaser_stereo<'a>(ctx: &mut EvaluationContext<'a>, args: Args<'a>) -> LispResult<EvalResult<'a>> {
+	phaser_sides(ctx, args, true)
+}
+
+fn phaser_sides<'a>(ctx: &mut EvaluationContext<'a>, mut args: Args<'a>, stereo: bool) -> LispResult<EvalResult<'a>> {
+	let stages = args.constant()?;
+	let rate = args.input()?;
+	let depth = args.input()?;
+	let input = args.input()?;
+
+	ensure!((1.0..=MAX_PHASER_STAGES).contains(&stages) && stages.fract() == 0.0,
+		"'phaser' takes a whole number of stages up to {}, not {}", MAX_PHASER_STAGES, stages);
+
+	let depth = ctx.graph.new_clamp(depth, 0.0, 1.0);
+
+	modulated(ctx, rate, stereo, |ctx, lfo| {
+		let sweep = lfo_sweep(&mut ctx.graph, lfo, depth.into());
+		let sweep = ctx.graph.new_multiply(sweep, PHASER_MAX_FREQ - PHASER_MIN_FREQ);
+		let cutoff = ctx.graph.new_add(sweep, PHASER_MIN_FREQ);
+
+		let (sin, cos) = cutoff_sin_cos(ctx, cutoff.into());
+		let graph = &mut ctx.graph;
+
+		// The allpasses' coefficient is (tan(w) - 1)/(tan(w) + 1)
+		let difference = graph.new_sub(sin, cos);
+		let sum = graph.new_add(sin, cos);
+		let inverse = reciprocal_between(graph, sum.into(), 1.0, 2.0);
+		let coefficient = graph.new_multiply(difference, inverse);
+
+		let shifted = (0..stages as usize)
+			.fold(input, |stage, _| allpass(graph, coefficient.into(), stage, "phaser state"));
+
+		Ok(graph.new_mix(input, shifted, 0.5).into())
+	})
+}
+
+// Runs effect with a sine LFO at rate, and for stereo, again with the LFO inverted
+fn modulated<'a, F>(ctx: &mut EvaluationContext<'a>, rate: Input, stereo: bool, mut effect: F) -> LispResult<EvalResult<'a>>
+	where F: FnMut(&mut EvaluationContext<'a>, Input) -> LispResult<Input>
+{
+	let lfo: Input = ctx.graph.new_sine(rate).into();
+	let left = effect(ctx, lfo)?;
+
+	if !stereo {
+		return Ok(left.into())
+	}
+
+	let inverted = ctx.graph.new_multiply(lfo, -1.0);
+	let right = effect(ctx, inverted.into())?;
+
+	Ok(EvalResult::Array(vec![left.into(), right.into()]))
+}
+
+// The LFO moved into 0 to 1, then scaled by depth
+fn lfo_sweep(graph: &mut Graph, lfo: Input, depth: Input) -> NodeRef {
+	let sweep = graph.new_multiply(lfo, 0.5);
+	let sweep = graph.new_add(sweep, 0.5);
+	graph.new_multiply(sweep, depth)
+}
+
+// Waveshapers run at twice the sample rate, between halfband filters built from two chains
+// of allpasses, which keeps down the aliasing from the harmonics they add. These are hiir's
+// polyphase coefficients for a transition band of 0.05, which reject about 105dB. That takes
+// four allpasses in each chain, so oversampling costs 82 nodes
+const HALFBAND: [f32; 8] = [
+	0.035_832_79, 0.134_090_14, 0.272_040_14, 0.424_324_87,
+	0.572_057_2, 0.706_292_14, 0.827_124_76, 0.941_503_09,
+];
+
+// tanh's 5/4 Pade approximant is within 1e-3 of it up to here, where it's all but flat. Its
+// coefficients are from the constant term up, in x^2
+const TANH_LIMIT: f32 = 3.5;
+const TANH_NUMERATOR: [f32; 3] = [1.0, 1.0 / 9.0, 1.0 / 945.0];
+const TANH_DENOMINATOR: [f32; 3] = [1.0, 4.0 / 9.0, 1.0 / 63.0];
+const TANH_TOLERANCE: f32 = 1.0e-4;
+
+const MAX_CRUSH_BITS: f32 = 16.0;
+const MAX_SHAPER_POINTS: usize = 256;
+
+fn tanh<'a>(ctx: &mut EvaluationContext<'a>, mut args: Args<'a>) -> LispResult<EvalResult<'a>> {
+	let input = args.input()?;
+	Ok(oversampled(&mut ctx.graph, input, tanh_shape).into())
+}
+
+fn softclip<'a>(ctx: &mut EvaluationContext<'a>, mut args: Args<'a>) -> LispResult<EvalResult<'a>> {
+	let drive = args.input()?;
+	let input = args.input()?;
+
+	let driven = ctx.graph.new_multiply(input, drive);
+	Ok(oversampled(&mut ctx.graph, driven.into(), soft_clip).into())
+}
+
+fn foldback<'a>(ctx: &mut EvaluationContext<'a>, mut args: Args<'a>) -> LispResult<EvalResult<'a>> {
+	let input = args.input()?;
+	Ok(oversampled(&mut ctx.graph, input, fold_shape).into())
+}
+
+fn crush<'a>(ctx: &mut EvaluationContext<'a>, mut args: Args<'a>) -> LispResult<EvalResult<'a>> {
+	let bits = args.constant()?;
+	let input = args.input()?;
+
+	let step_count = crush_steps(bits)?;
+	let graph = &mut ctx.graph;
+
+	let input = graph.new_clamp(input, -1.0, 1.0);
+	let steps = graph.new_add(input, 1.0);
+	let steps = graph.new_multiply(steps, step_count / 2.0);
+
+	let rounded = round(graph, steps.into(), 1.0);
+	let rounded = graph.new_multiply(rounded, 2.0 / step_count);
+	Ok(graph.new_add(rounded, -1.0).into())
+}
+
+fn decimate<'a>(ctx: &mut EvaluationContext<'a>, mut args: Args<'a>) -> LispResult<EvalResult<'a>> {
+	let rate = args.input()?;
+	let input = args.input()?;
+	Ok(sample_and_hold(ctx, "decimate", rate, input).into())
+}
+
+fn shaper<'a>(ctx: &mut EvaluationContext<'a>, mut args: Args<'a>) -> LispResult<EvalResult<'a>> {
+	let table = args.array()?;
+	let input = args.input()?;
+
+	ensure!(table.len() >= 2 && table.len() <= MAX_SHAPER_POINTS,
+		"'shaper' takes between 2 and {} points, not {}", MAX_SHAPER_POINTS, table.len());
+
+	let elements = table.into_iter().map(Input::Literal).collect::<Vec<_>>();
+	let last = (elements.len() - 1) as f32;
+
+	// The points are spread evenly from -1 to 1
+	Ok(oversampled(&mut ctx.graph, input, |graph, x| {
+		let x = graph.new_clamp(x, -1.0, 1.0);
+		let position = graph.new_multiply(x, last / 2.0);
+		let position = graph.new_add(position, last / 2.0);
+		tap(graph, &elements, position.into())
+	}).into())
+}
+
+// The same steps as tanh_shape, so constants fold to what the nodes would give
+fn fold_tanh(args: &[f32]) -> LispResult<f32> {
+	let x = args[0].max(-TANH_LIMIT).min(TANH_LIMIT);
+	let squared = x * x;
+
+	let numerator = polynomial_value(squared, &TANH_NUMERATOR) * x;
+	let denominator = polynomial_value(squared, &TANH_DENOMINATOR);
+
+	let inverse = reciprocal_within_value(denominator, 1.0, tanh_largest_denominator(), TANH_TOLERANCE);
+	Ok(numerator * inverse)
+}
+
+fn fold_softclip(args: &[f32]) -> LispResult<f32> {
+	let clipped = (args[0] * args[1]).max(-1.5).min(1.5);
+	Ok(clipped - 4.0 * clipped.powi(3) / 27.0)
+}
+
+fn fold_foldback(args: &[f32]) -> LispResult<f32> {
+	let phase = (args[0] + 1.0).rem_euclid(4.0);
+	Ok(if phase < 2.0 { phase - 1.0 } else { 3.0 - phase })
+}
+
+fn fold_crush(args: &[f32]) -> LispResult<f32> {
+	let step_count = crush_steps(args[0])?;
+	let steps = (args[1].max(-1.0).min(1.0) + 1.0) * (step_count / 2.0);
+
+	// Rounded the same way as the nodes, with ties to even
+	let rounded = (steps + ROUNDING_OFFSET) - ROUNDING_OFFSET;
+	Ok(rounded * (2.0 / step_count) - 1.0)
+}
+
+// How many steps there are from -1 to 1, so that there are 2^bits levels including both
+fn crush_steps(bits: f32) -> LispResult<f32> {
+	ensure!((1.0..=MAX_CRUSH_BITS).contains(&bits), "'crush' takes from 1 to {} bits, not {}", MAX_CRUSH_BITS, bits);
+	Ok(bits.exp2() - 1.0)
+}
+
+// Each sample becomes two, shaped, then filtered back down to one
+fn oversampled<F>(graph: &mut Graph, input: Input, mut shape: F) -> Input
+	where F: FnMut(&mut Graph, Input) -> Input
+{
+	let first = halfband_branch(graph, input, 0);
+	let second = halfband_branch(graph, input, 1);
+
+	let first = shape(graph, first);
+	let second = shape(graph, second);
+
+	// Going back down, each chain takes the other sample
+	let even = halfband_branch(graph, second, 0);
+	let odd = halfband_branch(graph, first, 1);
+	let sum = graph.new_add(even, odd);
+	graph.new_multiply(sum, 0.5).into()
+}
+
+// Runs x through the allpasses for every other coefficient, from the first or the second
+fn halfband_branch(graph: &mut Graph, x: Input, start: usize) -> Input {
+	HALFBAND.iter().skip(start).step_by(2)
+		.fold(x, |x, &coefficient| allpass(graph, coefficient.into(), x, "halfband state"))
+}
+
+// First order allpass, y = a x + s with s' = x - a y
+fn allpass(graph: &mut Graph, coefficient: Input, x: Input, name: &str) -> Input {
+	let state = graph.new_value_store(name);
+	let output = graph.new_multiply(coefficient, x);
+	let output = graph.new_add(output, state);
+	let fed_back = graph.new_multiply(coefficient, output);
+	let next = graph.new_sub(x, fed_back);
+	graph.new_store_write(state, next);
+	output.into()
+}
+
+// x (945 + 105x^2 + x^4) / (945 + 420x^2 + 15x^4), with the division only as good as the
+// approximation. 26 nodes
+fn tanh_shape(graph: &mut Graph, x: Input) -> Input {
+	let x = graph.new_clamp(x, -TANH_LIMIT, TANH_LIMIT);
+	let squared = graph.new_multiply(x, x);
+
+	let numerator = polynomial(graph, squared.into(), &TANH_NUMERATOR);
+	let numerator = graph.new_multiply(numerator, x);
+	let denominator = polynomial(graph, squared.into(), &TANH_DENOMINATOR);
+
+	let inverse = reciprocal_within(graph, denominator, 1.0, tanh_largest_denominator(), TANH_TOLERANCE);
+	graph.new_multiply(numerator, inverse).into()
+}
+
+// The denominator at TANH_LIMIT, the largest it gets
+fn tanh_largest_denominator() -> f32 {
+	polynomial_value(TANH_LIMIT * TANH_LIMIT, &TANH_DENOMINATOR)
+}
+
+// Reflects whatever's past 1 or -1 back inside, as often as it takes. That's a triangle wave
+// with a period of 4, so it's the quarter cycle fold scaled up. 7 nodes
+fn fold_shape(graph: &mut Graph, x: Input) -> Input {
+	let cycles = graph.new_multiply(x, 0.25);
+	let quarter = quarter_cycle(graph, cycles.into());
+	graph.new_multiply(quarter, 4.0).into()
+}
+
+fn env_ar<'a>(ctx: &mut EvaluationContext<'a>, mut args: Args<'a>) -> LispResult<EvalResult<'a>> {
+	let attack = args.constant()?;
+	let release = args.constant()?;
+	let gate = args.input()?;
+	Ok(ctx.graph.new_env_ar(attack, release, gate).into())
+}
+
+fn env_adsr<'a>(ctx: &mut EvaluationContext<'a>, mut args: Args<'a>) -> LispResult<EvalResult<'a>> {
+	let attack = args.constant()?;
+	let decay = args.constant()?;
+	let sustain = args.constant()?;
+	let release = args.constant()?;
+	let gate = args.input()?;
+	Ok(ctx.graph.new_env_adsr(attack, decay, sustain, release, gate).into())
+}
+
+fn clamp<'a>(ctx: &mut EvaluationContext<'a>, mut args: Args<'a>) -> LispResult<EvalResult<'a>> {
+	let input = args.input()?;
+	let lb = args.input()?;
+	let ub = args.input()?;
+	Ok(ctx.graph.new_clamp(input, lb, ub).into())
+}
+
+fn sequencer<'a>(ctx: &mut EvaluationContext<'a>, mut args: Args<'a>) -> LispResult<EvalResult<'a>> {
+	let sequence = args.array()?;
+	let advance = args.input()?;
+	let reset = args.input()?;
+
+	let buf = ctx.graph.new_buffer(sequence);
+	Ok(ctx.graph.new_sequencer(buf, advance, reset).into())
+}
+
+// Files are resampled while the patch loads, so they can't be too long
+const MAX_SAMPLE_SECONDS: f32 = 60.0;
+const MAX_PLAYBACK_RATE: f32 = 8.0;
+
+// voi-synth's samplers play a buffer through once, so loops are written out into it, over and
+// over for this long. Every voice keeps its own copy, so it's a few megabytes each
+const LOOPED_SECONDS: f32 = 10.0;
+
+fn sample<'a>(ctx: &mut EvaluationContext<'a>, mut args: Args<'a>) -> LispResult<EvalResult<'a>> {
+	let path = args.string()?;
+	let start_seconds = args.constant()?;
+	let rate = args.constant()?;
+	let loop_start = args.constant()?;
+	let loop_end = args.constant()?;
+
+	ensure!(start_seconds >= 0.0 && loop_start >= 0.0, "'sample' can't start before the beginning of the file");
+	ensure!(rate > 0.0 && rate <= MAX_PLAYBACK_RATE,
+		"'sample' requires a rate above 0 and up to {}, got {}", MAX_PLAYBACK_RATE, rate);
+
+	let file = wav::read(&ctx.dir.join(path))?;
+	let seconds = file.samples.len() as f32 / file.sample_rate;
+	ensure!(seconds <= MAX_SAMPLE_SECONDS,
+		"'{}' is {} seconds long, 'sample' can't play more than {}", path, seconds, MAX_SAMPLE_SECONDS);
+
+	// Loop points are found in the file's own samples, so they land exactly where they're put
+	let samples = &file.samples;
+	let frame = |seconds: f32| ((seconds * file.sample_rate).round() as usize).min(samples.len());
+	let (start, loop_start, loop_end) = (frame(start_seconds), frame(loop_start), frame(loop_end));
+
+	ensure!(start < samples.len(), "There's nothing to play in '{}' after {} seconds", path, start_seconds);
+
+	// Up to the end of the loop, and then the loop again until there's enough of it
+	let mut played = if loop_end > loop_start {
+		ensure!(start < loop_end, "'sample' starts after the end of its loop");
+
+		let looped_len = (LOOPED_SECONDS * file.sample_rate * rate).ceil() as usize;
+		let mut played = samples[start..loop_end].to_vec();
+		while played.len() < looped_len {
+			played.extend_from_slice(&samples[loop_start..loop_end]);
+		}
+
+		played.truncate(looped_len.max(loop_end - start));
+		played
+	} else {
+		samples[start..].to_vec()
+	};
+
+	// Playing faster is the same as the file having been recorded at a lower rate
+	played = wav::resample(&played, file.sample_rate * rate, ctx.sample_rate);
+
+	let buffer = ctx.graph.new_buffer(played);
+	Ok(ctx.graph.new_sampler(buffer).into())
+}
+
+// Key parameters are created on first use, and driven by the voice allocator
+fn key_parameter(graph: &mut Graph, name: &str) -> Input {
+	let param = match graph.find_parameter(name) {
+		Some(param) => param,
+		None => graph.new_parameter(name),
+	};
+
+	param.into()
+}
+
+fn key_freq<'a>(ctx: &mut EvaluationContext<'a>, _: Args<'a>) -> LispResult<EvalResult<'a>> {
+	Ok(key_parameter(&mut ctx.graph, "key-freq").into())
+}
+
+fn key_vel<'a>(ctx: &mut EvaluationContext<'a>, _: Args<'a>) -> LispResult<EvalResult<'a>> {
+	Ok(key_parameter(&mut ctx.graph, "key-vel").into())
+}
+
+const MAX_REPEAT: f32 = 4096.0;
+
+fn repeat<'a>(ctx: &mut EvaluationContext<'a>, mut args: Args<'a>) -> LispResult<EvalResult<'a>> {
+	let count = args.constant()?;
+	let function = args.function()?;
+
+	ensure!(count >= 0.0 && count.fract() == 0.0,
+		"'repeat' requires a whole, non-negative count, got {}", count);
+	ensure!(count <= MAX_REPEAT, "'repeat' can't repeat more than {} times", MAX_REPEAT);
+
+	let results = (0..count as usize)
+		.map(|i| ctx.call_function(&function, vec![(i as f32).into()]))
+		.collect::<LispResult<_>>()?;
+
+	Ok(EvalResult::Array(results))
+}
+
+fn map<'a>(ctx: &mut EvaluationContext<'a>, mut args: Args<'a>) -> LispResult<EvalResult<'a>> {
+	let function = args.function()?;
+	let elements = args.elements()?;
+
+	let results = elements.into_iter()
+		.map(|e| ctx.call_function(&function, vec![e]))
+		.collect::<LispResult<_>>()?;
+
+	Ok(EvalResult::Array(results))
+}
@@ -1,18 +1,14 @@
-use super::sexpression::{SExpression, ExpressionListExt};
-use super::{LispResult, SynthInfo, KeyInput};
+use super::sexpression::SExpression;
+use super::LispResult;
+use super::builtins;
+use super::ir::{Graph, Input, NodeRef, StoreRef, ParameterRef};
+use super::optimise::optimise;
+use super::lower;
 use voi_synth::failure::{format_err, bail, ensure};
 
-use voi_synth::{
-	Context as SynthContext,
-	context::EvaluationContext as SynthEvaluationContext,
-	node::Input as SynthInput,
-	Synth,
-	Buffer as SynthBuffer,
-	NodeContainer,
-	NodeID, synth::StoreID, ParameterID
-};
-
 use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
 
 macro_rules! ensure_args {
     ($func:expr, $list:ident == $count:expr) => {{
@@ -30,41 +26,109 @@ macro_rules! ensure_args {
 
 
 #[derive(Clone, Debug)]
-enum EvalResult {
+pub(super) enum EvalResult<'a> {
 	Constant(f32),
-	Array(Vec<f32>),
-	SynthNode(SynthInput),
-	// Function(),
+	Array(Vec<EvalResult<'a>>),
+	SynthNode(Input),
+	Function(Rc<Function<'a>>),
+	Str(&'a str),
+
+	// An index into the context's delay lines
+	DelayLine(usize),
+}
+
+#[derive(Debug)]
+pub(super) struct Function<'a> {
+	// Set for functions bound with 'defn', so errors can say which function went wrong
+	name: Option<&'a str>,
+
+	params: Vec<&'a str>,
+	body: SExpression<'a>,
+
+	// Bindings visible where the function was defined
+	captures: HashMap<&'a str, EvalResult<'a>>,
 }
 
-impl EvalResult {
-	fn expect_constant(self) -> LispResult<f32> {
+impl<'a> EvalResult<'a> {
+	pub(super) fn expect_constant(self) -> LispResult<f32> {
 		match self {
 			EvalResult::Constant(f) => Ok(f),
 			EvalResult::SynthNode(n) => bail!("Expected constant value, got node: {:?}", n),
 			EvalResult::Array(n) => bail!("Expected constant value, got array: [{:?}]", n),
+			EvalResult::Function(_) => bail!("Expected constant value, got function"),
+			EvalResult::Str(s) => bail!("Expected constant value, got string: \"{}\"", s),
+			EvalResult::DelayLine(_) => bail!("Expected constant value, got delay line"),
+		}
+	}
+	pub(super) fn expect_array(self) -> LispResult<Vec<f32>> {
+		match self {
+			EvalResult::Constant(f) => bail!("Expected array, got constant value: {:?}", f),
+			EvalResult::SynthNode(n) => bail!("Expected array, got node: {:?}", n),
+			EvalResult::Array(n) => n.into_iter().map(EvalResult::expect_constant).collect(),
+			EvalResult::Function(_) => bail!("Expected array, got function"),
+			EvalResult::Str(s) => bail!("Expected array, got string: \"{}\"", s),
+			EvalResult::DelayLine(_) => bail!("Expected array, got delay line"),
 		}
 	}
-	fn expect_array(self) -> LispResult<Vec<f32>> {
+	pub(super) fn expect_elements(self) -> LispResult<Vec<EvalResult<'a>>> {
 		match self {
 			EvalResult::Constant(f) => bail!("Expected array, got constant value: {:?}", f),
 			EvalResult::SynthNode(n) => bail!("Expected array, got node: {:?}", n),
 			EvalResult::Array(n) => Ok(n),
+			EvalResult::Function(_) => bail!("Expected array, got function"),
+			EvalResult::Str(s) => bail!("Expected array, got string: \"{}\"", s),
+			EvalResult::DelayLine(_) => bail!("Expected array, got delay line"),
 		}
 	}
-	fn to_input(self) -> LispResult<SynthInput> {
+	pub(super) fn expect_function(self) -> LispResult<Rc<Function<'a>>> {
+		match self {
+			EvalResult::Constant(f) => bail!("Expected function, got constant value: {:?}", f),
+			EvalResult::SynthNode(n) => bail!("Expected function, got node: {:?}", n),
+			EvalResult::Array(n) => bail!("Expected function, got array: [{:?}]", n),
+			EvalResult::Str(s) => bail!("Expected function, got string: \"{}\"", s),
+			EvalResult::DelayLine(_) => bail!("Expected function, got delay line"),
+			EvalResult::Function(f) => Ok(f),
+		}
+	}
+	pub(super) fn expect_string(self) -> LispResult<&'a str> {
+		match self {
+			EvalResult::Constant(f) => bail!("Expected string, got constant value: {:?}", f),
+			EvalResult::SynthNode(n) => bail!("Expected string, got node: {:?}", n),
+			EvalResult::Array(n) => bail!("Expected string, got array: [{:?}]", n),
+			EvalResult::Function(_) => bail!("Expected string, got function"),
+			EvalResult::Str(s) => Ok(s),
+			EvalResult::DelayLine(_) => bail!("Expected string, got delay line"),
+		}
+	}
+	pub(super) fn expect_delay_line(self) -> LispResult<usize> {
+		match self {
+			EvalResult::Constant(f) => bail!("Expected delay line, got constant value: {:?}", f),
+			EvalResult::SynthNode(n) => bail!("Expected delay line, got node: {:?}", n),
+			EvalResult::Array(n) => bail!("Expected delay line, got array: [{:?}]", n),
+			EvalResult::Function(_) => bail!("Expected delay line, got function"),
+			EvalResult::Str(s) => bail!("Expected delay line, got string: \"{}\"", s),
+			EvalResult::DelayLine(line) => Ok(line),
+		}
+	}
+	pub(super) fn to_input(self) -> LispResult<Input> {
 		match self {
 			EvalResult::Constant(f) => Ok(f.into()),
 			EvalResult::SynthNode(n) => Ok(n),
 			EvalResult::Array(n) => bail!("Expected constant value or synth node, got array: [{:?}]", n),
+			EvalResult::Function(_) => bail!("Expected constant value or synth node, got function"),
+			EvalResult::Str(s) => bail!("Expected constant value or synth node, got string: \"{}\"", s),
+			EvalResult::DelayLine(_) => bail!("Expected constant value or synth node, got delay line"),
 		}
 	}
-	fn expect_node_id(self) -> LispResult<NodeID> {
-		use self::SynthInput::*;
+	pub(super) fn expect_node_id(self) -> LispResult<NodeRef> {
+		use self::Input::*;
 
 		match self {
 			EvalResult::Constant(f) => bail!("Expected synth node, got constant: {}", f),
 			EvalResult::Array(n) => bail!("Expected synth node, got array: [{:?}]", n),
+			EvalResult::Function(_) => bail!("Expected synth node, got function"),
+			EvalResult::Str(s) => bail!("Expected synth node, got string: \"{}\"", s),
+			EvalResult::DelayLine(_) => bail!("Expected synth node, got delay line"),
 			EvalResult::SynthNode(n) => match n {
 				Literal(l) => bail!("Expected synth node, got Literal: {}", l),
 				Node(n_id) => Ok(n_id),
@@ -73,12 +137,15 @@ impl EvalResult {
 			},
 		}
 	}
-	fn expect_store_id(self) -> LispResult<StoreID> {
-		use self::SynthInput::*;
+	pub(super) fn expect_store_id(self) -> LispResult<StoreRef> {
+		use self::Input::*;
 
 		match self {
 			EvalResult::Constant(f) => bail!("Expected synth store, got constant: {}", f),
 			EvalResult::Array(n) => bail!("Expected synth store, got array: [{:?}]", n),
+			EvalResult::Function(_) => bail!("Expected synth store, got function"),
+			EvalResult::Str(s) => bail!("Expected synth store, got string: \"{}\"", s),
+			EvalResult::DelayLine(_) => bail!("Expected synth store, got delay line"),
 			EvalResult::SynthNode(n) => match n {
 				Literal(l) => bail!("Expected synth store, got Literal: {}", l),
 				Node(id) => bail!("Expected synth store, got Node: {:?}", id),
@@ -89,384 +156,476 @@ impl EvalResult {
 	}
 }
 
-impl Into<EvalResult> for f32 {
-	fn into(self) -> EvalResult { EvalResult::Constant(self) }
+impl<'a> Into<EvalResult<'a>> for f32 {
+	fn into(self) -> EvalResult<'a> { EvalResult::Constant(self) }
 }
 
-impl Into<EvalResult> for SynthInput {
-	fn into(self) -> EvalResult { EvalResult::SynthNode(self) }
+impl<'a> Into<EvalResult<'a>> for Input {
+	fn into(self) -> EvalResult<'a> { EvalResult::SynthNode(self) }
 }
 
-impl Into<EvalResult> for NodeID {
-	fn into(self) -> EvalResult { EvalResult::SynthNode(self.into()) }
+impl<'a> Into<EvalResult<'a>> for NodeRef {
+	fn into(self) -> EvalResult<'a> { EvalResult::SynthNode(self.into()) }
 }
 
-impl Into<EvalResult> for StoreID {
-	fn into(self) -> EvalResult { EvalResult::SynthNode(self.into()) }
+impl<'a> Into<EvalResult<'a>> for StoreRef {
+	fn into(self) -> EvalResult<'a> { EvalResult::SynthNode(self.into()) }
 }
 
-impl Into<EvalResult> for ParameterID {
-	fn into(self) -> EvalResult { EvalResult::SynthNode(self.into()) }
+impl<'a> Into<EvalResult<'a>> for ParameterRef {
+	fn into(self) -> EvalResult<'a> { EvalResult::SynthNode(self.into()) }
 }
 
 
-pub fn evaluate_top_level<'a>(ctx: &mut SynthContext, top_level: Vec<SExpression<'a>>) -> LispResult<(Synth, SynthInfo)> {
-	let mut ctx = EvaluationContext::new(ctx);
+pub fn evaluate_top_level<'a>(sample_rate: f32, dir: &Path, top_level: Vec<SExpression<'a>>) -> LispResult<Graph> {
+	let mut ctx = EvaluationContext::new(sample_rate);
+	ctx.dir = dir.to_owned();
 
 	for sexpr in top_level {
-		if let SExpression::List(mut list) = sexpr {
-			if list.is_empty() {
-				bail!("Tried to evaluate an empty list");
-			}
+		ctx.graph.set_span(Some(sexpr.span()));
+		ctx.evaluate_top_level_form(sexpr)?;
+	}
 
-			let func_name = list.remove(0).expect_ident()?;
+	ctx.graph.set_span(None);
 
-			match func_name {
-				"let" => {
-					ensure_args!(func_name, list == 2);
+	let mut graph = std::mem::replace(&mut ctx.graph, Graph::new());
+	ctx.shift_delay_lines(&mut graph);
+	Ok(graph)
+}
 
-					let ident = list.remove(0).expect_ident()?;
-					let value = ctx.evaluate_sexpr(list.remove(0))?;
 
-					ctx.let_bindings.insert(ident, value);
-				}
+// Baked buffers are rendered while the patch loads, so they can't be too long
+const MAX_BAKE_SECONDS: f32 = 60.0;
 
-				"gain" => {
-					ensure_args!(func_name, list == 1);
-					let gain = ctx.evaluate_sexpr(list.remove(0))?.expect_constant()?;
-					ctx.synth.set_gain(gain);
-				}
 
-				"output" => {
-					ensure_args!(func_name, list == 1);
+#[derive(Clone)]
+pub(super) struct EvaluationContext<'a> {
+	pub(super) sample_rate: f32,
+	pub(super) graph: Graph,
 
-					let node_id = ctx.evaluate_sexpr(list.remove(0))?.expect_node_id()?;
-					ctx.synth.set_output(node_id);
-				}
+	// Files the patch loads are relative to this
+	pub(super) dir: PathBuf,
 
-				"def-store" => {
-					ensure_args!(func_name, list == 1);
-					let ident = list.remove(0).expect_ident()?;
-					let store = ctx.synth.new_value_store();
-					ctx.let_bindings.insert(ident, store.into());
-				}
+	pub(super) let_bindings: HashMap<&'a str, EvalResult<'a>>,
 
-				"store" => {
-					ensure_args!(func_name, list == 2);
-					let ident = ctx.evaluate_sexpr(list.remove(0))?;
-					let value = ctx.evaluate_sexpr(list.remove(0))?;
-					ctx.synth.new_store_write(ident.expect_store_id()?, value.to_input()?);
-				}
+	// The stages of each delay line, and what's fed into them
+	pub(super) delay_lines: Vec<(Vec<StoreRef>, Input)>,
 
-				_ => {
-					list.insert(0, SExpression::Identifier(func_name));
-					ctx.execute_function(list)?;
-				}
-			}
+	// How many unseeded noise sources there have been, which seeds the next
+	pub(super) noise_sources: usize,
 
-		} else {
-			bail!("Unexpected item at top level of synth definition: {:?}", sexpr);
-		}
-	}
+	// The phase FM operators share, once one needs it
+	pub(super) key_phase: Option<Input>,
+}
 
-	let info = SynthInfo{
-		key_input: ctx.key_input,
-	};
 
-	Ok((ctx.synth, info))
-}
+impl<'a> EvaluationContext<'a> {
+	pub(super) fn new(sample_rate: f32) -> Self {
+		EvaluationContext {
+			sample_rate,
+			graph: Graph::new(),
 
+			dir: PathBuf::new(),
 
-struct EvaluationContext<'a> {
-	synth_context: &'a mut SynthContext,
-	synth: Synth,
+			let_bindings: HashMap::new(),
 
-	let_bindings: HashMap<&'a str, EvalResult>,
-	key_input: KeyInput,
-}
+			delay_lines: Vec::new(),
 
+			noise_sources: 0,
 
-impl<'a> EvaluationContext<'a> {
-	fn new(synth_context: &'a mut SynthContext) -> Self {
-		EvaluationContext {
-			synth_context,
-			synth: Synth::new(),
+			key_phase: None,
+		}
+	}
 
-			let_bindings: HashMap::new(),
-			key_input: KeyInput::None,
+	// Delay lines shift along once everything else is done, so that every tap reads them
+	// before they move
+	pub(super) fn shift_delay_lines(&self, graph: &mut Graph) {
+		for (stages, input) in self.delay_lines.iter() {
+			for pair in stages.windows(2).rev() {
+				graph.new_store_write(pair[1], pair[0]);
+			}
+
+			graph.new_store_write(stages[0], *input);
 		}
 	}
 
-	fn execute_function(&mut self, mut list: Vec<SExpression<'a>>) -> LispResult<EvalResult> {
-		use std::cell::RefCell;
+	// Evaluates a form at the top level of a synth definition, returning the value of
+	// any expression that isn't a definition
+	pub(super) fn evaluate_top_level_form(&mut self, sexpr: SExpression<'a>) -> LispResult<Option<EvalResult<'a>>> {
+		let mut list = match sexpr {
+			SExpression::List(list, _) => list,
+			sexpr => bail!("Unexpected item at top level of synth definition: {:?}", sexpr),
+		};
 
 		if list.is_empty() {
 			bail!("Tried to evaluate an empty list");
 		}
 
+		let func_span = list[0].span();
 		let func_name = list.remove(0).expect_ident()?;
 
 		match func_name {
-			"*" => {
-				ensure_args!(func_name, list >= 2);
-
-				let r_self = RefCell::new(self);
-
-				// TODO: make better
-				if list.is_constant() {
-					let res = list.into_iter()
-						.map(|expr| r_self.borrow_mut().evaluate_sexpr(expr)?.expect_constant())
-						.fold(Ok(1.0), |a: LispResult<f32>, e| Ok(a? * e?));
+			"let" => {
+				ensure_args!(func_name, list == 2);
 
-					Ok(res?.into())
-
-				} else {
-					let a = r_self.borrow_mut().evaluate_sexpr(list.remove(0))?.to_input();
-					// TODO: take advantage of associativity
-					let res = list.into_iter()
-						.map(|expr| r_self.borrow_mut().evaluate_sexpr(expr)?.to_input())
-						.fold(a, |a, e| {
-							Ok(r_self.borrow_mut().synth.new_multiply(a?, e?).into())
-						});
-					
-					Ok(res?.into())
-				}
+				let ident = list.remove(0).expect_ident()?;
+				let value = self.evaluate_sexpr(list.remove(0))?;
 
+				self.let_bindings.insert(ident, value);
 			}
 
-			"+" => {
-				ensure_args!(func_name, list >= 2);
-
-				let r_self = RefCell::new(self);
-
-				// TODO: make better
-				if list.is_constant() {
-					let res = list.into_iter()
-						.map(|expr| r_self.borrow_mut().evaluate_sexpr(expr)?.expect_constant())
-						.fold(Ok(1.0), |a: LispResult<f32>, e| Ok(a? + e?));
+			"defn" => {
+				ensure_args!(func_name, list == 3);
 
-					Ok(res?.into())
+				let ident = list.remove(0).expect_ident()?;
+				let function = self.new_function(Some(ident), list)?;
 
-				} else {
-					let a = r_self.borrow_mut().evaluate_sexpr(list.remove(0))?.to_input();
-					// TODO: take advantage of associativity
-					let res = list.into_iter()
-						.map(|expr| r_self.borrow_mut().evaluate_sexpr(expr)?.to_input())
-						.fold(a, |a, e| {
-							Ok(r_self.borrow_mut().synth.new_add(a?, e?).into())
-						});
-					
-					Ok(res?.into())
-				}
+				self.let_bindings.insert(ident, function);
 			}
 
-			"-" => {
-				ensure_args!(func_name, list >= 2);
-
-				let r_self = RefCell::new(self);
-
-				// TODO: make better
-				if list.is_constant() {
-					let res = list.into_iter()
-						.map(|expr| r_self.borrow_mut().evaluate_sexpr(expr)?.expect_constant())
-						.fold(Ok(1.0), |a: LispResult<f32>, e| Ok(a? - e?));
+			"gain" => {
+				ensure_args!(func_name, list == 1);
+				let gain = self.evaluate_sexpr(list.remove(0))?.expect_constant()?;
+				self.graph.set_gain(gain);
+			}
 
-					Ok(res?.into())
+			"output" => {
+				ensure_args!(func_name, list == 1);
 
-				} else {
-					let a = r_self.borrow_mut().evaluate_sexpr(list.remove(0))?.to_input();
-					let res = list.into_iter()
-						.map(|expr| r_self.borrow_mut().evaluate_sexpr(expr)?.to_input())
-						.fold(a, |a, e| {
-							Ok(r_self.borrow_mut().synth.new_sub(a?, e?).into())
-						});
-					
-					Ok(res?.into())
-				}
-			}
+				// The output has to be a node, so stores and parameters are passed through one
+				let node_id = match self.evaluate_sexpr(list.remove(0))? {
+					EvalResult::SynthNode(input @ Input::Store(_)) | EvalResult::SynthNode(input @ Input::Parameter(_)) =>
+						self.graph.new_add(input, 0.0),
+					value => value.expect_node_id()?,
+				};
 
-			"mix" => {
-				ensure_args!(func_name, list == 3);
-				let a = self.evaluate_sexpr(list.remove(0))?.to_input()?;
-				let b = self.evaluate_sexpr(list.remove(0))?.to_input()?;
-				let mix = self.evaluate_sexpr(list.remove(0))?.to_input()?;
-				Ok(self.synth.new_mix(a, b, mix).into())
+				self.graph.set_output(node_id);
 			}
 
-			"sin" | "sine" => {
+			"def-store" => {
 				ensure_args!(func_name, list == 1);
-				let freq = self.evaluate_sexpr(list.remove(0))?.to_input()?;
-				Ok(self.synth.new_sine(freq).into())
+				let ident = list.remove(0).expect_ident()?;
+				let store = self.graph.new_value_store(ident);
+				self.let_bindings.insert(ident, store.into());
 			}
 
-			"tri" | "triangle" => {
-				ensure_args!(func_name, list == 1);
-				let freq = self.evaluate_sexpr(list.remove(0))?.to_input()?;
-				Ok(self.synth.new_triangle(freq).into())
+			"store" => {
+				ensure_args!(func_name, list == 2);
+				let ident = self.evaluate_sexpr(list.remove(0))?;
+				let value = self.evaluate_sexpr(list.remove(0))?;
+				self.graph.new_store_write(ident.expect_store_id()?, value.to_input()?);
 			}
 
-			"sqr" | "square" => {
-				ensure_args!(func_name, list == 1);
-				let freq = self.evaluate_sexpr(list.remove(0))?.to_input()?;
-				Ok(self.synth.new_square(freq).into())
+			_ => {
+				list.insert(0, SExpression::Identifier(func_name, func_span));
+				return self.execute_function(list).map(Some);
 			}
+		}
 
-			"saw" | "sawtooth" => {
-				ensure_args!(func_name, list == 1);
-				let freq = self.evaluate_sexpr(list.remove(0))?.to_input()?;
-				Ok(self.synth.new_saw(freq).into())
-			}
+		Ok(None)
+	}
 
-			"lp" | "lowpass" => {
-				ensure_args!(func_name, list == 2);
-				let cutoff = self.evaluate_sexpr(list.remove(0))?.to_input()?;
-				let input = self.evaluate_sexpr(list.remove(0))?.to_input()?;
-				Ok(self.synth.new_lowpass(input, cutoff).into())
-			}
+	fn execute_function(&mut self, mut list: Vec<SExpression<'a>>) -> LispResult<EvalResult<'a>> {
+		if list.is_empty() {
+			bail!("Tried to evaluate an empty list");
+		}
 
-			"hp" | "highpass" => {
+		let func_name = list.remove(0).expect_ident()?;
+
+		match func_name {
+			"fn" => {
 				ensure_args!(func_name, list == 2);
-				let cutoff = self.evaluate_sexpr(list.remove(0))?.to_input()?;
-				let input = self.evaluate_sexpr(list.remove(0))?.to_input()?;
-				Ok(self.synth.new_highpass(input, cutoff).into())
+				self.new_function(None, list)
 			}
 
-			"ar" | "env-ar" => {
+			"if" => {
 				ensure_args!(func_name, list == 3);
-				let attack = self.evaluate_sexpr(list.remove(0))?.expect_constant()?;
-				let release = self.evaluate_sexpr(list.remove(0))?.expect_constant()?;
-				let gate = self.evaluate_sexpr(list.remove(0))?.to_input()?;
-				Ok(self.synth.new_env_ar(attack, release, gate).into())
-			}
+				let condition = self.evaluate_sexpr(list.remove(0))?.expect_constant()?;
 
-			"adsr" | "env-adsr" => {
-				ensure_args!(func_name, list == 5);
-				let attack = self.evaluate_sexpr(list.remove(0))?.expect_constant()?;
-				let decay = self.evaluate_sexpr(list.remove(0))?.expect_constant()?;
-				let sustain = self.evaluate_sexpr(list.remove(0))?.expect_constant()?;
-				let release = self.evaluate_sexpr(list.remove(0))?.expect_constant()?;
-				let gate = self.evaluate_sexpr(list.remove(0))?.to_input()?;
-				Ok(self.synth.new_env_adsr(attack, decay, sustain, release, gate).into())
+				if condition != 0.0 {
+					self.evaluate_sexpr(list.remove(0))
+				} else {
+					self.evaluate_sexpr(list.remove(1))
+				}
 			}
 
-			"clamp" => {
-				ensure_args!(func_name, list == 2);
-				let input = self.evaluate_sexpr(list.remove(0))?.to_input()?;
-				let lb = self.evaluate_sexpr(list.remove(0))?.to_input()?;
-				let ub = self.evaluate_sexpr(list.remove(0))?.to_input()?;
-				Ok(self.synth.new_clamp(input, lb, ub).into())
-			}
+			"cond" => {
+				ensure_args!(func_name, list >= 1);
 
-			"sequencer" => {
-				ensure_args!(func_name, list >= 2);
-				let sequence = self.evaluate_sexpr(list.remove(0))?.expect_array()?;
-				let advance = self.evaluate_sexpr(list.remove(0))?.to_input()?;
-				let reset = if list.len() > 0 {
-					self.evaluate_sexpr(list.remove(0))?.to_input()?
-				} else { 1.0.into() };
-
-				let buf = self.synth.new_buffer(sequence);
-				Ok(self.synth.new_sequencer(buf, advance, reset).into())
+				for clause in list {
+					let mut clause = match clause {
+						SExpression::List(clause, _) => clause,
+						sexpr => bail!("Expected (condition value) clause in 'cond', got: {:?}", sexpr),
+					};
+
+					ensure!(clause.len() == 2,
+						"'cond' clauses require a condition and a value, {} items received", clause.len());
+
+					let is_else = match clause[0] {
+						SExpression::Identifier("else", _) => true,
+						_ => false,
+					};
+
+					if is_else || self.evaluate_sexpr(clause.remove(0))?.expect_constant()? != 0.0 {
+						return self.evaluate_sexpr(clause.pop().unwrap());
+					}
+				}
+
+				bail!("No 'cond' clause matched, consider adding an 'else' clause")
 			}
 
 			"bake" => {
 				ensure_args!(func_name, list >= 2);
-				let sample_rate = self.synth_context.get_sample_rate();
-				let samples = self.evaluate_sexpr(list.remove(0))?.expect_constant()? * sample_rate;
-				let samples = samples as usize;
+				let sample_rate = self.sample_rate;
+				let seconds = self.evaluate_sexpr(list.remove(0))?.expect_constant()?;
 
+				ensure!(seconds <= MAX_BAKE_SECONDS, "You can't bake more than {} seconds of a synth", MAX_BAKE_SECONDS);
+
+				let samples = (seconds * sample_rate) as usize;
 				ensure!(samples > 0, "You can't bake a synth to a zero length buffer");
 
-				let (mut synth, _) = evaluate_top_level(self.synth_context, list)?;
-				let mut eval_ctx = SynthEvaluationContext::new(sample_rate);
-				let mut eval_buffer = SynthBuffer::new(samples);
+				let mut graph = evaluate_top_level(sample_rate, &self.dir, list)?;
+				optimise(&mut graph);
 
-				synth.evaluate_into_buffer(&mut eval_buffer, &mut eval_ctx);
-				let buffer_id = self.synth.new_buffer(eval_buffer.data);
+				let buffer_id = self.graph.new_buffer(lower::evaluate(&graph, sample_rate, samples)?);
 
-				Ok(self.synth.new_sampler(buffer_id, 0.0).into())
+				Ok(self.graph.new_sampler(buffer_id).into())
 			}
 
-			"key-freq" => {
-				ensure_args!(func_name, list == 0);
-
-				if let KeyInput::Mono{freq, ..} = &mut self.key_input {
-					if let Some(param) = *freq {
-						Ok(param.into())
-					} else {
-						let param = self.synth.new_parameter();
-						*freq = Some(param);
-						Ok(param.into())
-					}
+			"polyphonic" => {
+				ensure_args!(func_name, list >= 2);
+				bail!("(polyphonic) Unimplemented")
+			}
 
-				} else {
-					let param = self.synth.new_parameter();
+			_ => {
+				if let Some(builtin) = builtins::lookup(func_name) {
+					let args = self.evaluate_args(list)?;
+					return builtin.call(self, args);
+				}
 
-					self.key_input = KeyInput::Mono {
-						freq: Some(param),
-						vel: None,
-					};
+				let function = match self.let_bindings.get(func_name) {
+					Some(EvalResult::Function(function)) => function.clone(),
+					Some(_) => bail!("'{}' is not a function", func_name),
+					None => bail!("Unknown function: '{}'", func_name),
+				};
 
-					Ok(param.into())
-				}
+				let args = self.evaluate_args(list)?;
+				self.call_function(&function, args)
 			}
+		}
+	}
 
-			"key-vel" => {
-				ensure_args!(func_name, list == 0);
+	fn new_function(&mut self, name: Option<&'a str>, mut list: Vec<SExpression<'a>>) -> LispResult<EvalResult<'a>> {
+		let params = match list.remove(0) {
+			SExpression::List(params, _) => params.into_iter()
+				.map(SExpression::expect_ident)
+				.collect::<LispResult<_>>()?,
 
-				if let KeyInput::Mono{vel, ..} = &mut self.key_input {
-					if let Some(param) = *vel {
-						Ok(param.into())
-					} else {
-						let param = self.synth.new_parameter();
-						*vel = Some(param);
-						Ok(param.into())
-					}
+			sexpr => bail!("Expected parameter list, got: {:?}", sexpr),
+		};
 
-				} else {
-					let param = self.synth.new_parameter();
+		let function = Function {
+			name,
+			params,
+			body: list.remove(0),
+			captures: self.let_bindings.clone(),
+		};
 
-					self.key_input = KeyInput::Mono {
-						vel: Some(param),
-						freq: None,
-					};
+		Ok(EvalResult::Function(Rc::new(function)))
+	}
 
-					Ok(param.into())
-				}
+	pub(super) fn call_function(&mut self, function: &Function<'a>, args: Vec<EvalResult<'a>>) -> LispResult<EvalResult<'a>> {
+		if function.params.len() != args.len() {
+			match function.name {
+				Some(name) => bail!("'{}' function requires {} arguments, {} received",
+					name, function.params.len(), args.len()),
+				None => bail!("Anonymous function requires {} arguments, {} received",
+					function.params.len(), args.len()),
 			}
+		}
 
-			"polyphonic" => {
-				ensure_args!(func_name, list >= 2);
-				bail!("(polyphonic) Unimplemented")
-			}
+		let mut scope = function.captures.clone();
+		scope.extend(function.params.iter().cloned().zip(args));
 
-			_ => bail!("Unknown function: '{}'", func_name),
-		}
+		let outer_scope = std::mem::replace(&mut self.let_bindings, scope);
+		let result = self.evaluate_sexpr(function.body.clone());
+		self.let_bindings = outer_scope;
+
+		result
 	}
 
-	fn evaluate_sexpr(&mut self, sexpr: SExpression<'a>) -> LispResult<EvalResult> {
+	fn evaluate_args(&mut self, list: Vec<SExpression<'a>>) -> LispResult<Vec<EvalResult<'a>>> {
+		list.into_iter()
+			.map(|sexpr| self.evaluate_sexpr(sexpr))
+			.collect()
+	}
+
+	fn evaluate_sexpr(&mut self, sexpr: SExpression<'a>) -> LispResult<EvalResult<'a>> {
 		use self::SExpression::*;
 
 		match sexpr {
-			List(v) => self.execute_function(v),
-			Number(n) => Ok(EvalResult::Constant(n)),
+			List(v, span) => {
+				let outer_span = self.graph.set_span(Some(span));
+				let result = self.execute_function(v);
+				self.graph.set_span(outer_span);
+				result
+			}
 
-			Identifier(i) => {
+			Number(n, _) => Ok(EvalResult::Constant(n)),
+			Str(s, _) => Ok(EvalResult::Str(s)),
+
+			Identifier(i, _) => {
 				self.let_bindings.get(&i)
 					.cloned()
 					.ok_or_else(|| format_err!("Unknown identifier: '{}'", i))
 			}
 
-			Array(v) => {
+			Array(v, _) => {
 				let mut rs = Vec::with_capacity(v.len());
 
 				for sexpr in v {
-					let result = self.evaluate_sexpr(sexpr)?;
-					rs.push(result.expect_constant()?);
+					rs.push(self.evaluate_sexpr(sexpr)?);
 				}
 
 				Ok(EvalResult::Array(rs))
 			}
 		}
 	}
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::lisp::parser::ExprReader;
+
+	// Evaluates a snippet, returning its value if it folded to a constant
+	fn evaluate(source: &str) -> LispResult<Option<f32>> {
+		let mut ctx = EvaluationContext::new(44100.0);
+		let mut result = None;
+
+		for sexpr in ExprReader::new(source).parse_toplevel()? {
+			result = ctx.evaluate_top_level_form(sexpr)?.or(result);
+		}
+
+		match result {
+			Some(EvalResult::Constant(f)) => Ok(Some(f)),
+			Some(_) => Ok(None),
+			None => bail!("Nothing was evaluated"),
+		}
+	}
+
+	fn assert_folds_to(source: &str, expected: f32) {
+		match evaluate(source) {
+			Ok(Some(f)) => assert_eq!(f, expected, "{}", source),
+			Ok(None) => panic!("{} didn't fold to a constant", source),
+			Err(e) => panic!("{} failed to evaluate: {}", source, e),
+		}
+	}
+
+	#[test]
+	fn arithmetic_folds() {
+		assert_folds_to("(+ 1 2)", 3.0);
+		assert_folds_to("(+ 1 2 3 4)", 10.0);
+		assert_folds_to("(- 5 2)", 3.0);
+		assert_folds_to("(- 10 2 3)", 5.0);
+		assert_folds_to("(* 2 3 4)", 24.0);
+		assert_folds_to("(/ 3 2)", 1.5);
+	}
+
+	#[test]
+	fn nested_expressions_fold() {
+		assert_folds_to("(+ (* 2 3) (- 10 4))", 12.0);
+		assert_folds_to("(mix 0 (* 5 2) 0.25)", 2.5);
+		assert_folds_to("(clamp (+ 1 1) 0 1)", 1.0);
+		assert_folds_to("(if (< 1 2) (+ 1 1) 0)", 2.0);
+	}
+
+	#[test]
+	fn bound_constants_fold() {
+		assert_folds_to("(let f 110) (* f 2)", 220.0);
+		assert_folds_to("(let f 110) (let g (+ f 10)) (- g f)", 10.0);
+		assert_folds_to("(defn double (x) (* x 2)) (double 4)", 8.0);
+	}
+
+	#[test]
+	fn spliced_arrays_fold() {
+		assert_folds_to("(+ [1 2] 3)", 6.0);
+		assert_folds_to("(+ (repeat 4 (fn (i) i)))", 6.0);
+	}
+
+	#[test]
+	fn functions_bind_their_arguments() {
+		assert_folds_to("(let sub (fn (x y) (- x y))) (sub 2 5)", -3.0);
+		assert_folds_to("(defn sub (x y) (- x y)) (sub 5 2)", 3.0);
+	}
+
+	#[test]
+	fn functions_capture_their_definition_scope() {
+		assert_folds_to("(let f 2) (defn scale (x) (* x f)) (let f 3) (scale 4)", 8.0);
+		assert_folds_to("(defn adder (n) (fn (x) (+ x n))) (let add2 (adder 2)) (add2 5)", 7.0);
+		assert!(evaluate("(defn f (x) y) (let y 1) (f 0)").is_err());
+	}
+
+	#[test]
+	fn arity_errors_name_the_function() {
+		let error = evaluate("(defn double (x) (* x 2)) (double 1 2)").unwrap_err().to_string();
+		assert!(error.contains("'double'"), "{}", error);
+
+		let error = evaluate("(let f (fn (x) x)) (f)").unwrap_err().to_string();
+		assert!(error.contains("requires 1 arguments, 0 received"), "{}", error);
+	}
+
+	#[test]
+	fn if_picks_a_branch() {
+		assert_folds_to("(if 1 2 3)", 2.0);
+		assert_folds_to("(if 0 2 3)", 3.0);
+		assert_folds_to("(if -0.5 2 3)", 2.0);
+
+		// The branch not taken isn't evaluated
+		assert_folds_to("(if 1 2 (/ 1 0))", 2.0);
+		assert!(evaluate("(if (key-freq) 2 3)").is_err());
+	}
+
+	#[test]
+	fn cond_picks_the_first_true_clause() {
+		assert_folds_to("(cond (0 1) (1 2) (1 3))", 2.0);
+		assert_folds_to("(cond (0 1) (else 4))", 4.0);
+		assert_folds_to("(cond (1 1) ((/ 1 0) 2))", 1.0);
+		assert!(evaluate("(cond (0 1))").is_err());
+		assert!(evaluate("(cond (1 2 3))").is_err());
+	}
+
+	#[test]
+	fn comparisons_fold_to_one_or_zero() {
+		assert_folds_to("(< 1 2)", 1.0);
+		assert_folds_to("(< 2 1)", 0.0);
+		assert_folds_to("(> 2 1)", 1.0);
+		assert_folds_to("(> 1 1)", 0.0);
+		assert_folds_to("(<= 1 1)", 1.0);
+		assert_folds_to("(<= 2 1)", 0.0);
+		assert_folds_to("(>= 1 1)", 1.0);
+		assert_folds_to("(>= 1 2)", 0.0);
+		assert_folds_to("(= 1 1)", 1.0);
+		assert_folds_to("(= 1 2)", 0.0);
+	}
+
+	#[test]
+	fn repeat_and_map_build_arrays() {
+		assert_folds_to("(+ (repeat 3 (fn (i) (* i 10))))", 30.0);
+		assert_folds_to("(+ (map (fn (x) (* x x)) [1 2 3]))", 14.0);
+		assert_folds_to("(defn inc (x) (+ x 1)) (+ (map inc (repeat 3 (fn (i) i))))", 6.0);
+		assert!(evaluate("(repeat 2 (fn (a b) a))").is_err());
+		assert!(evaluate("(map 1 [1 2])").is_err());
+	}
+
+	#[test]
+	fn signals_dont_fold() {
+		assert_eq!(evaluate("(+ (key-freq) (* 2 3))").unwrap(), None);
+	}
+
+	#[test]
+	fn division_by_zero_is_an_error() {
+		assert!(evaluate("(/ 1 0)").is_err());
+		assert!(evaluate("(/ (key-freq) 0)").is_err());
+	}
+}
@@ -0,0 +1,161 @@
+use super::ir::{Graph, Input, Op, PortKind};
+use super::span::Span;
+
+use serde_json::{json, Value};
+use std::fmt::Write;
+
+
+/// Renders a graph as a Graphviz digraph, with each node labelled by its type, its
+/// literal inputs and where in `source` it came from
+pub fn to_dot(graph: &Graph, source: &str) -> String {
+	let mut dot = String::new();
+
+	writeln!(dot, "digraph synth {{").unwrap();
+	writeln!(dot, "\trankdir=LR;").unwrap();
+	writeln!(dot, "\tnode [shape=box, fontname=monospace];").unwrap();
+
+	for (index, parameter) in graph.parameters.iter().enumerate() {
+		writeln!(dot, "\tp{} [label=\"{}\", shape=ellipse];", index, escape(&parameter.name)).unwrap();
+	}
+
+	for (index, store) in graph.stores.iter().enumerate() {
+		writeln!(dot, "\ts{} [label=\"{}\", shape=cylinder];", index, escape(&store.name)).unwrap();
+	}
+
+	for (index, node) in graph.nodes.iter().enumerate() {
+		let mut label = node.op.name().to_string();
+
+		for (input, port) in node.inputs.iter().zip(node.op.ports()) {
+			if let Input::Literal(f) = *input {
+				write!(label, "\\n{} = {}", port.name, f).unwrap();
+			}
+		}
+
+		if let Some(span) = node.span {
+			write!(label, "\\n@ {}", span.location(source)).unwrap();
+		}
+
+		let tooltip = node.span.map(|span| escape(snippet(span, source))).unwrap_or_default();
+		writeln!(dot, "\tn{} [label=\"{}\", tooltip=\"{}\"];", index, label, tooltip).unwrap();
+
+		if let Op::StoreWrite(store) = node.op {
+			writeln!(dot, "\tn{} -> s{} [style=dashed];", index, store.0).unwrap();
+		}
+	}
+
+	for edge in graph.edges() {
+		let from = match edge.from {
+			Input::Node(n) => format!("n{}", n.0),
+			Input::Store(s) => format!("s{}", s.0),
+			Input::Parameter(p) => format!("p{}", p.0),
+			Input::Literal(_) => continue,
+		};
+
+		let port = graph.node(edge.to).op.ports()[edge.port].name;
+		writeln!(dot, "\t{} -> n{} [label=\"{}\"];", from, edge.to.0, port).unwrap();
+	}
+
+	if let Some(output) = graph.output {
+		let gain = graph.gain.map(|g| format!("\\ngain = {}", g)).unwrap_or_default();
+		writeln!(dot, "\toutput [label=\"output{}\", shape=doublecircle];", gain).unwrap();
+		writeln!(dot, "\tn{} -> output;", output.0).unwrap();
+	}
+
+	writeln!(dot, "}}").unwrap();
+	dot
+}
+
+
+/// Serialises a graph as JSON, with each node annotated with its source span
+pub fn to_json(graph: &Graph, source: &str) -> Value {
+	let nodes = graph.nodes.iter()
+		.enumerate()
+		.map(|(index, node)| {
+			let inputs = node.inputs.iter()
+				.zip(node.op.ports())
+				.map(|(input, port)| {
+					let kind = match port.kind {
+						PortKind::Constant => "constant",
+						PortKind::Signal => "signal",
+					};
+
+					let mut value = json!({ "port": port.name, "kind": kind });
+
+					let (key, from) = match *input {
+						Input::Literal(f) => ("literal", json!(f)),
+						Input::Node(n) => ("node", json!(n.0)),
+						Input::Store(s) => ("store", json!(s.0)),
+						Input::Parameter(p) => ("parameter", json!(p.0)),
+					};
+
+					value[key] = from;
+					value
+				})
+				.collect::<Vec<_>>();
+
+			let mut value = json!({
+				"id": index,
+				"op": node.op.name(),
+				"inputs": inputs,
+				"span": node.span.map(|span| span_to_json(span, source)),
+			});
+
+			if let Some(buffer) = node.op.buffer() {
+				value["buffer"] = json!(buffer.0);
+			}
+
+			if let Op::StoreWrite(store) = node.op {
+				value["store"] = json!(store.0);
+			}
+
+			value
+		})
+		.collect::<Vec<_>>();
+
+	let parameters = graph.parameters.iter()
+		.enumerate()
+		.map(|(index, p)| json!({ "id": index, "name": p.name }))
+		.collect::<Vec<_>>();
+
+	let stores = graph.stores.iter()
+		.enumerate()
+		.map(|(index, s)| json!({ "id": index, "name": s.name }))
+		.collect::<Vec<_>>();
+
+	let buffers = graph.buffers.iter()
+		.enumerate()
+		.map(|(index, b)| json!({ "id": index, "length": b.len() }))
+		.collect::<Vec<_>>();
+
+	json!({
+		"nodes": nodes,
+		"parameters": parameters,
+		"stores": stores,
+		"buffers": buffers,
+		"output": graph.output.map(|n| n.0),
+		"gain": graph.gain,
+	})
+}
+
+fn span_to_json(span: Span, source: &str) -> Value {
+	let location = span.location(source);
+
+	json!({
+		"start": span.start,
+		"end": span.end,
+		"line": location.line,
+		"column": location.column,
+		"source": snippet(span, source),
+	})
+}
+
+
+fn snippet(span: Span, source: &str) -> &str {
+	source.get(span.start..span.end).unwrap_or("")
+}
+
+fn escape(s: &str) -> String {
+	s.replace('\\', "\\\\")
+		.replace('"', "\\\"")
+		.replace('\n', "\\n")
+}
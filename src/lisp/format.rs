@@ -0,0 +1,399 @@
+//! Prints patches back out in a standard layout, keeping comments
+
+use super::LispResult;
+use super::parser::ExprReader;
+use voi_synth::failure::{bail, ensure};
+
+const WIDTH: usize = 80;
+const INDENT: usize = 2;
+
+
+/// Reformats a patch. Lists are kept on one line when they fit, and otherwise broken
+/// with their arguments indented under the function name. Runs of `let` bindings have
+/// their values aligned, and numbers are written the shortest way that reads back the same
+pub fn format(source: &str) -> LispResult<String> {
+	let original = ExprReader::new(source).parse_toplevel()?;
+	let items = Reader {source, pos: 0}.items(None)?;
+
+	let mut out = String::new();
+	let alignments = let_alignments(&items);
+
+	for (item, alignment) in items.iter().zip(alignments) {
+		match item {
+			Item::Blank => out.push('\n'),
+
+			Item::Comment {text, trailing: true} => {
+				out.pop();
+				out += " ";
+				out += text;
+				out.push('\n');
+			}
+
+			Item::Comment {text, ..} => {
+				out += text;
+				out.push('\n');
+			}
+
+			_ => {
+				match alignment {
+					Some(width) => out += &aligned_let(item, width),
+					None => out += &layout(item, 0),
+				}
+
+				out.push('\n');
+			}
+		}
+	}
+
+	// The layout should only ever change whitespace, comments and how numbers are written
+	let formatted = ExprReader::new(&out).parse_toplevel()?;
+	ensure!(original.len() == formatted.len() && original.iter().zip(&formatted).all(|(a, b)| a.same_shape(b)),
+		"Formatting changed the meaning of the patch");
+
+	Ok(out)
+}
+
+
+enum Item<'a> {
+	Word(&'a str),
+	List(char, Vec<Item<'a>>, char),
+
+	// Trailing comments follow something else on the same line
+	Comment {text: &'a str, trailing: bool},
+
+	// Blank lines are only kept between top level forms
+	Blank,
+}
+
+struct Reader<'a> {
+	source: &'a str,
+	pos: usize,
+}
+
+impl<'a> Reader<'a> {
+	fn rest(&self) -> &'a str { &self.source[self.pos..] }
+
+	// Returns the number of line breaks skipped
+	fn skip_whitespace(&mut self) -> usize {
+		let rest = self.rest();
+		let trimmed = rest.trim_start();
+		let skipped = &rest[..rest.len() - trimmed.len()];
+
+		self.pos += skipped.len();
+		skipped.matches('\n').count()
+	}
+
+	fn items(&mut self, close: Option<char>) -> LispResult<Vec<Item<'a>>> {
+		let mut items = Vec::new();
+
+		loop {
+			let line_breaks = self.skip_whitespace();
+
+			// Inside a list, the opening bracket counts as something to trail
+			let after_something = close.is_some() || !items.is_empty();
+
+			if close.is_none() && line_breaks > 1 && !items.is_empty() {
+				items.push(Item::Blank);
+			}
+
+			let next = match self.rest().chars().next() {
+				Some(c) => c,
+				None => match close {
+					Some(close) => bail!("Couldn't find the closing '{}'", close),
+					None => return Ok(items),
+				}
+			};
+
+			match next {
+				';' => {
+					let rest = self.rest();
+					let end = rest.find('\n').unwrap_or(rest.len());
+					self.pos += end;
+
+					let trailing = after_something && line_breaks == 0;
+					items.push(Item::Comment {text: rest[..end].trim_end(), trailing});
+				}
+
+				'(' | '[' => {
+					let matching = if next == '(' { ')' } else { ']' };
+					self.pos += 1;
+					items.push(Item::List(next, self.items(Some(matching))?, matching));
+				}
+
+				')' | ']' => {
+					ensure!(close == Some(next), "Unexpected '{}'", next);
+					self.pos += 1;
+					return Ok(items);
+				}
+
+				'"' => {
+					let rest = self.rest();
+					let end = match rest[1..].find('"') {
+						Some(end) => end + 2,
+						None => bail!("Couldn't find the end of the string"),
+					};
+
+					self.pos += end;
+					items.push(Item::Word(&rest[..end]));
+				}
+
+				_ => {
+					let rest = self.rest();
+					let end = rest.find(|c: char| c.is_whitespace() || "();[]\"".contains(c))
+						.unwrap_or(rest.len());
+
+					self.pos += end;
+					items.push(Item::Word(&rest[..end]));
+				}
+			}
+		}
+	}
+}
+
+
+// How many arguments stay on the same line as the function name when a list is broken
+fn header_args(func_name: &str) -> usize {
+	match func_name {
+		"defn" => 2,
+		"let" | "fn" | "def-store" | "store" | "bake" | "if" => 1,
+		_ => 0,
+	}
+}
+
+fn normalise_number(word: &str) -> String {
+	match word.parse::<f32>() {
+		Ok(f) if f.is_finite() => f.to_string(),
+		_ => word.to_owned(),
+	}
+}
+
+/// The item on a single line, unless it contains a comment
+fn flat(item: &Item) -> Option<String> {
+	match item {
+		Item::Word(word) => Some(normalise_number(word)),
+
+		Item::List(open, items, close) => {
+			let items = items.iter()
+				.map(flat)
+				.collect::<Option<Vec<_>>>()?;
+
+			Some(format!("{}{}{}", open, items.join(" "), close))
+		}
+
+		Item::Comment {..} | Item::Blank => None,
+	}
+}
+
+fn layout(item: &Item, column: usize) -> String {
+	if let Some(flat) = flat(item) {
+		if column + flat.chars().count() <= WIDTH {
+			return flat
+		}
+	}
+
+	match item {
+		Item::List(open, items, close) => broken(*open, items, *close, column),
+		Item::Word(word) => normalise_number(word),
+		Item::Comment {text, ..} => text.to_string(),
+		Item::Blank => String::new(),
+	}
+}
+
+fn broken(open: char, items: &[Item], close: char, column: usize) -> String {
+	// Lists starting with a name keep it and their header arguments on the first line, with
+	// everything else indented. Anything else, like arrays, is filled in lines aligned after
+	// the bracket
+	let (header, body_column, fill) = match items.first() {
+		Some(Item::Word(func_name)) if open == '(' => (1 + header_args(func_name), column + INDENT, false),
+		_ => (1, column + 1, true),
+	};
+
+	let mut out = open.to_string();
+	let mut placed = 0;
+	let mut after_comment = false;
+
+	for item in items {
+		match item {
+			Item::Comment {text, trailing} => {
+				if !*trailing {
+					newline(&mut out, body_column);
+				} else if !out.ends_with(open) {
+					out.push(' ');
+				}
+
+				out += text;
+				after_comment = true;
+			}
+
+			Item::Blank => {}
+
+			_ => {
+				let fits_on_line = fill && flat(item)
+					.map_or(false, |f| current_column(&out, column) + 1 + f.chars().count() < WIDTH);
+
+				if placed > 0 && !after_comment && (placed < header || fits_on_line) {
+					out.push(' ');
+				} else if placed > 0 || after_comment {
+					newline(&mut out, body_column);
+				}
+
+				after_comment = false;
+
+				let column = current_column(&out, column);
+				out += &layout(item, column);
+				placed += 1;
+			}
+		}
+	}
+
+	// Anything after a comment would be commented out
+	if let Some(Item::Comment {..}) = items.last() {
+		newline(&mut out, column);
+	}
+
+	out.push(close);
+	out
+}
+
+fn newline(out: &mut String, column: usize) {
+	out.push('\n');
+	out.extend(std::iter::repeat(' ').take(column));
+}
+
+// `out` started at `start_column`, so that's where its first line is relative to
+fn current_column(out: &str, start_column: usize) -> usize {
+	match out.rfind('\n') {
+		Some(pos) => out[pos + 1..].chars().count(),
+		None => start_column + out.chars().count(),
+	}
+}
+
+
+/// For each item, the width to pad its name to if it's part of a run of `let`s to align.
+/// Runs are broken by blank lines, comments on their own line and anything else
+fn let_alignments(items: &[Item]) -> Vec<Option<usize>> {
+	// Pairs of item index and name width
+	fn finish_run(run: &mut Vec<(usize, usize)>, alignments: &mut [Option<usize>]) {
+		if run.len() > 1 {
+			let width = run.iter().map(|&(_, w)| w).max().unwrap();
+
+			for &(index, _) in run.iter() {
+				alignments[index] = Some(width);
+			}
+		}
+
+		run.clear();
+	}
+
+	let mut alignments = vec![None; items.len()];
+	let mut run = Vec::new();
+
+	for (index, item) in items.iter().enumerate() {
+		match (item, alignable_let(item)) {
+			(_, Some(name)) => run.push((index, name.chars().count())),
+			(Item::Comment {trailing: true, ..}, _) => {}
+			_ => finish_run(&mut run, &mut alignments),
+		}
+	}
+
+	finish_run(&mut run, &mut alignments);
+	alignments
+}
+
+// The bound name of a `let` that fits on one line
+fn alignable_let<'a>(item: &Item<'a>) -> Option<&'a str> {
+	match item {
+		Item::List('(', items, _) => match items.as_slice() {
+			[Item::Word("let"), Item::Word(name), value] => {
+				let fits = flat(item).map_or(false, |f| f.chars().count() <= WIDTH);
+				flat(value).and(Some(*name)).filter(|_| fits)
+			}
+
+			_ => None,
+		},
+
+		_ => None,
+	}
+}
+
+fn aligned_let(item: &Item, width: usize) -> String {
+	match item {
+		Item::List(_, items, _) => match items.as_slice() {
+			[_, Item::Word(name), value] => {
+				let value = flat(value).unwrap();
+				format!("(let {:width$} {})", name, value, width = width)
+			}
+
+			_ => unreachable!(),
+		},
+
+		_ => unreachable!(),
+	}
+}
+
+
+#[cfg(test)]
+mod tests {
+	use super::format;
+
+	fn assert_formats(source: &str, expected: &str) {
+		let formatted = format(source).unwrap();
+		assert_eq!(formatted, expected);
+		assert_eq!(format(&formatted).unwrap(), formatted, "formatting isn't idempotent");
+	}
+
+	#[test]
+	fn normalises_whitespace_and_numbers() {
+		assert_formats("(gain   0.30)\n(output (sin\n  110.0 ))", "(gain 0.3)\n(output (sin 110))\n");
+		assert_formats("(output (* 1e3 (sqr 1.50)))", "(output (* 1000 (sqr 1.5)))\n");
+		assert_formats("(output (sample   \"my kick.wav\"0.50))", "(output (sample \"my kick.wav\" 0.5))\n");
+	}
+
+	#[test]
+	fn keeps_comments_and_single_blank_lines() {
+		assert_formats(
+			"; header\n\n\n(gain 0.3) ; quiet\n\n(output ; main\n  (sin 110))\n",
+			"; header\n\n(gain 0.3) ; quiet\n\n(output ; main\n  (sin 110))\n",
+		);
+
+		assert_formats(
+			"(output (sin 110)\n  ; the end\n  )",
+			"(output\n  (sin 110)\n  ; the end\n)\n",
+		);
+	}
+
+	#[test]
+	fn aligns_let_runs() {
+		assert_formats(
+			"(let f 220)\n(let osc (sin f)) ; oscillator\n(let env (env-ar 0.1 0.5 (key-vel)))\n\n(let x 1)",
+			"(let f   220)\n(let osc (sin f)) ; oscillator\n(let env (env-ar 0.1 0.5 (key-vel)))\n\n(let x 1)\n",
+		);
+	}
+
+	#[test]
+	fn breaks_long_lists() {
+		let source = "(defn voice (freq) (mix (lp (* freq 4) (saw freq)) (sqr (* freq 1.01)) (tri 0.5)))";
+
+		assert_formats(source, "\
+(defn voice (freq)
+  (mix (lp (* freq 4) (saw freq)) (sqr (* freq 1.01)) (tri 0.5)))
+");
+
+		let source = "(let notes [220 247.5 264 297 330 352 396 440 495 528 594 660 704 792 880 990 1056])";
+
+		assert_formats(source, "\
+(let notes
+  [220 247.5 264 297 330 352 396 440 495 528 594 660 704 792 880 990 1056])
+");
+
+		let source = "(output (sequencer [220 247.5 264 297 330 352 396 440 495 528 594 660 704 792 880 990 1056 1188] (sqr 4)))";
+
+		assert_formats(source, "\
+(output
+  (sequencer
+    [220 247.5 264 297 330 352 396 440 495 528 594 660 704 792 880 990 1056
+     1188]
+    (sqr 4)))
+");
+	}
+}
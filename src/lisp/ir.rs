@@ -0,0 +1,420 @@
+//! A typed dataflow graph produced by the evaluator. Everything between evaluation
+//! and voi-synth - optimisation, visualisation, analysis - works on this, and
+//! `lower` turns it into a `Synth`.
+
+use super::LispResult;
+use super::span::Span;
+use voi_synth::failure::{bail, ensure};
+
+use std::hash::{Hash, Hasher};
+
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct NodeRef(pub(super) usize);
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct StoreRef(pub(super) usize);
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct ParameterRef(pub(super) usize);
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct BufferRef(pub(super) usize);
+
+
+#[derive(Copy, Clone, Debug)]
+pub enum Input {
+	Literal(f32),
+	Node(NodeRef),
+	Store(StoreRef),
+	Parameter(ParameterRef),
+}
+
+// Literals compare by bit pattern so that inputs can be used as hash keys
+impl PartialEq for Input {
+	fn eq(&self, other: &Input) -> bool {
+		use self::Input::*;
+
+		match (*self, *other) {
+			(Literal(a), Literal(b)) => a.to_bits() == b.to_bits(),
+			(Node(a), Node(b)) => a == b,
+			(Store(a), Store(b)) => a == b,
+			(Parameter(a), Parameter(b)) => a == b,
+			_ => false,
+		}
+	}
+}
+
+impl Eq for Input {}
+
+impl Hash for Input {
+	fn hash<H: Hasher>(&self, state: &mut H) {
+		use self::Input::*;
+
+		std::mem::discriminant(self).hash(state);
+
+		match *self {
+			Literal(f) => f.to_bits().hash(state),
+			Node(n) => n.hash(state),
+			Store(s) => s.hash(state),
+			Parameter(p) => p.hash(state),
+		}
+	}
+}
+
+impl Input {
+	pub fn literal(&self) -> Option<f32> {
+		match *self {
+			Input::Literal(f) => Some(f),
+			_ => None,
+		}
+	}
+
+	pub fn node(&self) -> Option<NodeRef> {
+		match *self {
+			Input::Node(n) => Some(n),
+			_ => None,
+		}
+	}
+}
+
+impl From<f32> for Input { fn from(f: f32) -> Input { Input::Literal(f) } }
+impl From<NodeRef> for Input { fn from(n: NodeRef) -> Input { Input::Node(n) } }
+impl From<StoreRef> for Input { fn from(s: StoreRef) -> Input { Input::Store(s) } }
+impl From<ParameterRef> for Input { fn from(p: ParameterRef) -> Input { Input::Parameter(p) } }
+
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Op {
+	Add,
+	Sub,
+	Multiply,
+	Mix,
+
+	Sine,
+	Triangle,
+	Square,
+	Saw,
+
+	Lowpass,
+	Highpass,
+
+	EnvAR,
+	EnvADSR,
+
+	Clamp,
+
+	Sequencer(BufferRef),
+	Sampler(BufferRef),
+
+	StoreWrite(StoreRef),
+}
+
+/// Whether a node input can be driven by a signal, or must be a literal
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum PortKind {
+	Constant,
+	Signal,
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct Port {
+	pub name: &'static str,
+	pub kind: PortKind,
+}
+
+const fn signal(name: &'static str) -> Port { Port {name, kind: PortKind::Signal} }
+const fn constant(name: &'static str) -> Port { Port {name, kind: PortKind::Constant} }
+
+const BINARY_PORTS: &[Port] = &[signal("a"), signal("b")];
+const MIX_PORTS: &[Port] = &[signal("a"), signal("b"), signal("mix")];
+const OSCILLATOR_PORTS: &[Port] = &[signal("freq")];
+const FILTER_PORTS: &[Port] = &[signal("input"), signal("cutoff")];
+const ENV_AR_PORTS: &[Port] = &[constant("attack"), constant("release"), signal("gate")];
+const ENV_ADSR_PORTS: &[Port] = &[constant("attack"), constant("decay"), constant("sustain"), constant("release"), signal("gate")];
+const CLAMP_PORTS: &[Port] = &[signal("input"), signal("lower"), signal("upper")];
+const SEQUENCER_PORTS: &[Port] = &[signal("advance"), signal("reset")];
+const STORE_WRITE_PORTS: &[Port] = &[signal("value")];
+
+
+impl Op {
+	pub fn name(&self) -> &'static str {
+		match *self {
+			Op::Add => "add",
+			Op::Sub => "sub",
+			Op::Multiply => "multiply",
+			Op::Mix => "mix",
+
+			Op::Sine => "sine",
+			Op::Triangle => "triangle",
+			Op::Square => "square",
+			Op::Saw => "saw",
+
+			Op::Lowpass => "lowpass",
+			Op::Highpass => "highpass",
+
+			Op::EnvAR => "env-ar",
+			Op::EnvADSR => "env-adsr",
+
+			Op::Clamp => "clamp",
+
+			Op::Sequencer(_) => "sequencer",
+			Op::Sampler(_) => "sampler",
+
+			Op::StoreWrite(_) => "store-write",
+		}
+	}
+
+	/// The inputs this operation expects, in order
+	pub fn ports(&self) -> &'static [Port] {
+		match *self {
+			Op::Add | Op::Sub | Op::Multiply => BINARY_PORTS,
+			Op::Mix => MIX_PORTS,
+
+			Op::Sine | Op::Triangle | Op::Square | Op::Saw => OSCILLATOR_PORTS,
+
+			Op::Lowpass | Op::Highpass => FILTER_PORTS,
+
+			Op::EnvAR => ENV_AR_PORTS,
+			Op::EnvADSR => ENV_ADSR_PORTS,
+
+			Op::Clamp => CLAMP_PORTS,
+
+			Op::Sequencer(_) => SEQUENCER_PORTS,
+			Op::Sampler(_) => &[],
+
+			Op::StoreWrite(_) => STORE_WRITE_PORTS,
+		}
+	}
+
+	/// Whether the node produces a signal other nodes can use
+	pub fn has_output(&self) -> bool {
+		match *self {
+			Op::StoreWrite(_) => false,
+			_ => true,
+		}
+	}
+
+	// Whether evaluating this node affects anything other than its own output
+	pub fn has_side_effects(&self) -> bool {
+		match *self {
+			Op::StoreWrite(_) => true,
+			_ => false,
+		}
+	}
+
+	pub fn buffer(&self) -> Option<BufferRef> {
+		match *self {
+			Op::Sequencer(b) | Op::Sampler(b) => Some(b),
+			_ => None,
+		}
+	}
+
+	pub fn buffer_mut(&mut self) -> Option<&mut BufferRef> {
+		match self {
+			Op::Sequencer(b) | Op::Sampler(b) => Some(b),
+			_ => None,
+		}
+	}
+}
+
+
+#[derive(Clone, Debug)]
+pub struct Node {
+	pub op: Op,
+	pub inputs: Vec<Input>,
+
+	// The expression the node was created by
+	pub span: Option<Span>,
+}
+
+
+/// A value driven from outside the synth, e.g., by the voice allocator
+#[derive(Clone, Debug)]
+pub struct Parameter {
+	pub name: String,
+}
+
+/// A value written by one part of the synth and read by others
+#[derive(Clone, Debug)]
+pub struct Store {
+	pub name: String,
+}
+
+/// A connection into one of a node's inputs
+#[derive(Copy, Clone, Debug)]
+pub struct Edge {
+	pub from: Input,
+	pub to: NodeRef,
+	pub port: usize,
+}
+
+
+/// A synth graph. Nodes only ever refer to nodes before them, and are evaluated in order
+#[derive(Clone, Debug, Default)]
+pub struct Graph {
+	pub nodes: Vec<Node>,
+	pub buffers: Vec<Vec<f32>>,
+	pub stores: Vec<Store>,
+	pub parameters: Vec<Parameter>,
+
+	pub output: Option<NodeRef>,
+	pub gain: Option<f32>,
+
+	current_span: Option<Span>,
+}
+impl Graph {
+	pub fn new() -> Graph { Graph::default() }
+
+	pub fn node(&self, node: NodeRef) -> &Node { &self.nodes[node.0] }
+	pub fn store(&self, store: StoreRef) -> &Store { &self.stores[store.0] }
+	pub fn parameter(&self, parameter: ParameterRef) -> &Parameter { &self.parameters[parameter.0] }
+
+	pub fn find_parameter(&self, name: &str) -> Option<ParameterRef> {
+		self.parameters.iter()
+			.position(|p| p.name == name)
+			.map(ParameterRef)
+	}
+
+	/// Every connection from a node, store or parameter into a node
+	pub fn edges<'a>(&'a self) -> impl Iterator<Item=Edge> + 'a {
+		self.nodes.iter()
+			.enumerate()
+			.flat_map(|(index, node)| {
+				node.inputs.iter()
+					.enumerate()
+					.filter(|(_, input)| input.literal().is_none())
+					.map(move |(port, &from)| Edge {from, to: NodeRef(index), port})
+			})
+	}
+
+	/// Attributes nodes created from now on to `span`, returning the previous span
+	pub fn set_span(&mut self, span: Option<Span>) -> Option<Span> {
+		std::mem::replace(&mut self.current_span, span)
+	}
+
+	pub fn new_node(&mut self, op: Op, inputs: Vec<Input>) -> NodeRef {
+		let span = self.current_span;
+		self.nodes.push(Node {op, inputs, span});
+		NodeRef(self.nodes.len() - 1)
+	}
+
+	pub fn new_add<A: Into<Input>, B: Into<Input>>(&mut self, a: A, b: B) -> NodeRef {
+		self.new_node(Op::Add, vec![a.into(), b.into()])
+	}
+
+	pub fn new_sub<A: Into<Input>, B: Into<Input>>(&mut self, a: A, b: B) -> NodeRef {
+		self.new_node(Op::Sub, vec![a.into(), b.into()])
+	}
+
+	pub fn new_multiply<A: Into<Input>, B: Into<Input>>(&mut self, a: A, b: B) -> NodeRef {
+		self.new_node(Op::Multiply, vec![a.into(), b.into()])
+	}
+
+	pub fn new_mix<A: Into<Input>, B: Into<Input>, M: Into<Input>>(&mut self, a: A, b: B, mix: M) -> NodeRef {
+		self.new_node(Op::Mix, vec![a.into(), b.into(), mix.into()])
+	}
+
+	pub fn new_sine<F: Into<Input>>(&mut self, freq: F) -> NodeRef { self.new_node(Op::Sine, vec![freq.into()]) }
+	pub fn new_triangle<F: Into<Input>>(&mut self, freq: F) -> NodeRef { self.new_node(Op::Triangle, vec![freq.into()]) }
+	pub fn new_square<F: Into<Input>>(&mut self, freq: F) -> NodeRef { self.new_node(Op::Square, vec![freq.into()]) }
+	pub fn new_saw<F: Into<Input>>(&mut self, freq: F) -> NodeRef { self.new_node(Op::Saw, vec![freq.into()]) }
+
+	pub fn new_lowpass<I: Into<Input>, C: Into<Input>>(&mut self, input: I, cutoff: C) -> NodeRef {
+		self.new_node(Op::Lowpass, vec![input.into(), cutoff.into()])
+	}
+
+	pub fn new_highpass<I: Into<Input>, C: Into<Input>>(&mut self, input: I, cutoff: C) -> NodeRef {
+		self.new_node(Op::Highpass, vec![input.into(), cutoff.into()])
+	}
+
+	pub fn new_env_ar<G: Into<Input>>(&mut self, attack: f32, release: f32, gate: G) -> NodeRef {
+		self.new_node(Op::EnvAR, vec![attack.into(), release.into(), gate.into()])
+	}
+
+	pub fn new_env_adsr<G: Into<Input>>(&mut self, attack: f32, decay: f32, sustain: f32, release: f32, gate: G) -> NodeRef {
+		self.new_node(Op::EnvADSR, vec![attack.into(), decay.into(), sustain.into(), release.into(), gate.into()])
+	}
+
+	pub fn new_clamp<I: Into<Input>, L: Into<Input>, U: Into<Input>>(&mut self, input: I, lb: L, ub: U) -> NodeRef {
+		self.new_node(Op::Clamp, vec![input.into(), lb.into(), ub.into()])
+	}
+
+	pub fn new_buffer(&mut self, data: Vec<f32>) -> BufferRef {
+		self.buffers.push(data);
+		BufferRef(self.buffers.len() - 1)
+	}
+
+	pub fn new_sequencer<A: Into<Input>, R: Into<Input>>(&mut self, buffer: BufferRef, advance: A, reset: R) -> NodeRef {
+		self.new_node(Op::Sequencer(buffer), vec![advance.into(), reset.into()])
+	}
+
+	pub fn new_sampler(&mut self, buffer: BufferRef) -> NodeRef {
+		self.new_node(Op::Sampler(buffer), Vec::new())
+	}
+
+	pub fn new_parameter(&mut self, name: &str) -> ParameterRef {
+		self.parameters.push(Parameter {name: name.into()});
+		ParameterRef(self.parameters.len() - 1)
+	}
+
+	pub fn new_value_store(&mut self, name: &str) -> StoreRef {
+		self.stores.push(Store {name: name.into()});
+		StoreRef(self.stores.len() - 1)
+	}
+
+	pub fn new_store_write<V: Into<Input>>(&mut self, store: StoreRef, value: V) -> NodeRef {
+		self.new_node(Op::StoreWrite(store), vec![value.into()])
+	}
+
+	pub fn set_output(&mut self, node: NodeRef) { self.output = Some(node) }
+	pub fn set_gain(&mut self, gain: f32) { self.gain = Some(gain) }
+
+
+	/// Checks that every node has the right number and kinds of inputs, and only refers
+	/// to things that exist
+	pub fn validate(&self) -> LispResult<()> {
+		for (index, node) in self.nodes.iter().enumerate() {
+			let ports = node.op.ports();
+
+			ensure!(node.inputs.len() == ports.len(),
+				"Node {} ({}) has {} inputs, expected {}", index, node.op.name(), node.inputs.len(), ports.len());
+
+			for (input, port) in node.inputs.iter().zip(ports) {
+				match *input {
+					Input::Literal(_) => {}
+
+					_ if port.kind == PortKind::Constant =>
+						bail!("Node {} ({}) requires a literal for '{}'", index, node.op.name(), port.name),
+
+					Input::Node(n) => {
+						ensure!(n.0 < index, "Node {} ({}) refers to a later node", index, node.op.name());
+						ensure!(self.nodes[n.0].op.has_output(),
+							"Node {} ({}) refers to a node without an output", index, node.op.name());
+					}
+
+					Input::Store(s) => ensure!(s.0 < self.stores.len(),
+						"Node {} ({}) refers to a missing store", index, node.op.name()),
+
+					Input::Parameter(p) => ensure!(p.0 < self.parameters.len(),
+						"Node {} ({}) refers to a missing parameter", index, node.op.name()),
+				}
+			}
+
+			if let Some(buffer) = node.op.buffer() {
+				ensure!(buffer.0 < self.buffers.len(), "Node {} ({}) refers to a missing buffer", index, node.op.name());
+			}
+
+			if let Op::StoreWrite(store) = node.op {
+				ensure!(store.0 < self.stores.len(), "Node {} ({}) refers to a missing store", index, node.op.name());
+			}
+		}
+
+		if let Some(output) = self.output {
+			ensure!(output.0 < self.nodes.len() && self.nodes[output.0].op.has_output(),
+				"The synth output isn't a node with an output");
+		}
+
+		Ok(())
+	}
+}
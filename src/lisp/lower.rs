@@ -0,0 +1,111 @@
+use super::LispResult;
+use super::ir::{Graph, Input, Op};
+
+use voi_synth::{
+	node::Input as SynthInput,
+	Synth,
+	NodeContainer,
+	NodeID, ParameterID,
+	context::EvaluationContext as SynthEvaluationContext,
+	Buffer as SynthBuffer,
+};
+
+
+/// A graph turned into a `Synth`
+pub struct Lowered {
+	pub synth: Synth,
+
+	// The synth parameter created for each of the graph's parameters
+	pub parameters: Vec<ParameterID>,
+}
+
+impl Lowered {
+	pub fn parameter(&self, graph: &Graph, name: &str) -> Option<ParameterID> {
+		graph.find_parameter(name)
+			.map(|p| self.parameters[p.0])
+	}
+}
+
+
+pub fn lower(graph: &Graph) -> LispResult<Lowered> {
+	graph.validate()?;
+
+	let mut synth = Synth::new();
+
+	let parameters = graph.parameters.iter()
+		.map(|_| synth.new_parameter())
+		.collect::<Vec<_>>();
+
+	let stores = graph.stores.iter()
+		.map(|_| synth.new_value_store())
+		.collect::<Vec<_>>();
+
+	let buffers = graph.buffers.iter()
+		.map(|data| synth.new_buffer(data.clone()))
+		.collect::<Vec<_>>();
+
+	let mut nodes: Vec<NodeID> = Vec::with_capacity(graph.nodes.len());
+
+	for node in graph.nodes.iter() {
+		let input = |i: usize| -> SynthInput {
+			match node.inputs[i] {
+				Input::Literal(f) => f.into(),
+				Input::Node(n) => nodes[n.0].into(),
+				Input::Store(s) => stores[s.0].into(),
+				Input::Parameter(p) => parameters[p.0].into(),
+			}
+		};
+
+		// Constant ports are guaranteed to be literals by validation
+		let literal = |i: usize| node.inputs[i].literal().unwrap();
+
+		let id = match node.op {
+			Op::Add => synth.new_add(input(0), input(1)),
+			Op::Sub => synth.new_sub(input(0), input(1)),
+			Op::Multiply => synth.new_multiply(input(0), input(1)),
+			Op::Mix => synth.new_mix(input(0), input(1), input(2)),
+
+			Op::Sine => synth.new_sine(input(0)),
+			Op::Triangle => synth.new_triangle(input(0)),
+			Op::Square => synth.new_square(input(0)),
+			Op::Saw => synth.new_saw(input(0)),
+
+			Op::Lowpass => synth.new_lowpass(input(0), input(1)),
+			Op::Highpass => synth.new_highpass(input(0), input(1)),
+
+			Op::EnvAR => synth.new_env_ar(literal(0), literal(1), input(2)),
+			Op::EnvADSR => synth.new_env_adsr(literal(0), literal(1), literal(2), literal(3), input(4)),
+
+			Op::Clamp => synth.new_clamp(input(0), input(1), input(2)),
+
+			Op::Sequencer(b) => synth.new_sequencer(buffers[b.0], input(0), input(1)),
+			Op::Sampler(b) => synth.new_sampler(buffers[b.0], 0.0),
+
+			Op::StoreWrite(s) => synth.new_store_write(stores[s.0], input(0)),
+		};
+
+		nodes.push(id);
+	}
+
+	if let Some(gain) = graph.gain {
+		synth.set_gain(gain);
+	}
+
+	if let Some(output) = graph.output {
+		synth.set_output(nodes[output.0]);
+	}
+
+	Ok(Lowered {synth, parameters})
+}
+
+
+/// Lowers a graph and evaluates it straight into a buffer, without a context. Parameters
+/// are left at their defaults, so there's no key input
+pub fn evaluate(graph: &Graph, sample_rate: f32, samples: usize) -> LispResult<Vec<f32>> {
+	let mut synth = lower(graph)?.synth;
+	let mut eval_ctx = SynthEvaluationContext::new(sample_rate);
+	let mut eval_buffer = SynthBuffer::new(samples);
+
+	synth.evaluate_into_buffer(&mut eval_buffer, &mut eval_ctx);
+	Ok(eval_buffer.data)
+}
@@ -1,12 +1,36 @@
+mod span;
 mod sexpression;
 mod parser;
+mod typecheck;
+mod builtins;
 mod evaluation;
+mod optimise;
+mod lower;
+mod session;
+mod format;
+mod wav;
+mod wavetable;
+
+pub mod ir;
+pub mod export;
+pub mod analysis;
+pub mod reference;
+
+pub use self::span::{Span, Location};
+pub use self::sexpression::SExpression;
+pub use self::session::Session;
+pub use self::format::format;
+pub use self::typecheck::Diagnostic;
 
 use voi_synth::{
 	Context as SynthContext,
 	ParameterID, SynthID
 };
 
+use voi_synth::failure::bail;
+
+use std::path::Path;
+
 use crate::VstResult as LispResult;
 
 pub struct SynthInfo {
@@ -22,20 +46,110 @@ pub enum KeyInput {
 	},
 }
 
-pub fn create_synth(ctx: &mut SynthContext, input: &str) -> LispResult<(SynthID, SynthInfo)> {
-	use std::iter::once;
+/// Parses every top level form of a synth definition
+pub fn parse(input: &str) -> LispResult<Vec<SExpression>> {
+	parser::ExprReader::new(input).parse_toplevel()
+}
+
+/// Compiles a synth definition into an optimised graph. Files it loads are relative
+/// to the working directory
+pub fn compile(input: &str, sample_rate: f32) -> LispResult<ir::Graph> {
+	compile_in(input, sample_rate, Path::new(""))
+}
+
+/// Compiles a synth definition, loading files relative to `dir`, usually the directory
+/// the patch is in
+pub fn compile_in(input: &str, sample_rate: f32, dir: &Path) -> LispResult<ir::Graph> {
+	let top_level_exprs = parse(input)?;
+
+	let diagnostics = typecheck::check(&top_level_exprs);
+	if !diagnostics.is_empty() {
+		let messages = diagnostics.iter()
+			.map(|d| format!("{}: {}", d.span.location(input), d.message))
+			.collect::<Vec<_>>();
+
+		bail!("{}", messages.join("\n"));
+	}
+
+	let mut graph = evaluation::evaluate_top_level(sample_rate, dir, top_level_exprs)?;
+	optimise::optimise(&mut graph);
+
+	Ok(graph)
+}
+
+/// Compiles a synth definition and renders `samples` of it without a context, for
+/// patches that don't depend on key input
+pub fn evaluate_to_buffer(input: &str, sample_rate: f32, samples: usize) -> LispResult<Vec<f32>> {
+	let graph = compile(input, sample_rate)?;
+	lower::evaluate(&graph, sample_rate, samples)
+}
+
+pub fn create_synth(ctx: &mut SynthContext, input: &str, dir: &Path) -> LispResult<(SynthID, SynthInfo)> {
+	let graph = compile_in(input, ctx.get_sample_rate(), dir)?;
+	log::debug!("{}", export::to_dot(&graph, input));
+
+	push_graph(ctx, &graph)
+}
 
-	let comment_free_input = input.lines()
-		.map(|l| l.split(';').next().unwrap())
-		.flat_map(|l| l.chars().chain(once('\n')) )
-		.collect::<String>();
+/// Lowers a compiled graph into a synth and adds it to the context
+pub fn push_graph(ctx: &mut SynthContext, graph: &ir::Graph) -> LispResult<(SynthID, SynthInfo)> {
+	let lowered = lower::lower(graph)?;
 
-	let top_level_exprs = parser::ExprReader::new(&comment_free_input).parse_toplevel()?;
+	let key_input = match (lowered.parameter(graph, "key-freq"), lowered.parameter(graph, "key-vel")) {
+		(None, None) => KeyInput::None,
+		(freq, vel) => KeyInput::Mono {freq, vel},
+	};
 
-	let (synth, info) = evaluation::evaluate_top_level(ctx, top_level_exprs)?;
+	let info = SynthInfo {key_input};
 
-	log::info!("{:?}", synth);
+	log::info!("{:?}", lowered.synth);
 
-	ctx.push_synth(synth)
+	ctx.push_synth(lowered.synth)
 		.map(|id| (id, info))
-}
\ No newline at end of file
+}
+
+
+#[cfg(test)]
+mod tests {
+	use super::{builtins, compile, lower};
+	use proptest::prelude::*;
+
+	// Mostly real function names and plausible bindings, so that generated patches get
+	// past the parser and into the type checker and evaluator
+	fn vocabulary() -> Vec<&'static str> {
+		let mut words = vec!["x", "y", "f", "s", "voice", "'a", "'b"];
+		words.extend(builtins::SPECIAL_FORMS.iter().map(|f| f.name));
+		words.extend(builtins::BUILTINS.iter().map(|b| b.name));
+		words
+	}
+
+	fn patch() -> impl Strategy<Value = String> {
+		let leaf = prop_oneof![
+			4 => prop::sample::select(vocabulary()).prop_map(str::to_owned),
+			2 => prop_oneof![Just(0.0f32), Just(-1.0), Just(1.0e30), Just(-1.0e30), Just(f32::MIN_POSITIVE), any::<f32>()]
+				.prop_map(|f| format!("{:?}", f)),
+		];
+
+		let form = leaf.prop_recursive(5, 48, 6, |inner| {
+			(prop::collection::vec(inner, 0..6), prop::bool::weighted(0.8))
+				.prop_map(|(items, list)| if list {
+					format!("({})", items.join(" "))
+				} else {
+					format!("[{}]", items.join(" "))
+				})
+		});
+
+		prop::collection::vec(form, 1..6).prop_map(|forms| forms.join("\n"))
+	}
+
+	proptest! {
+		#![proptest_config(ProptestConfig::with_cases(512))]
+
+		#[test]
+		fn compiling_doesnt_panic(source in patch()) {
+			if let Ok(graph) = compile(&source, 44100.0) {
+				let _ = lower::lower(&graph);
+			}
+		}
+	}
+}
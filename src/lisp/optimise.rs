@@ -0,0 +1,488 @@
+use super::ir::{Graph, Node, NodeRef, BufferRef, Input, Op};
+
+use std::collections::HashMap;
+
+
+/// Simplifies a graph in place, merging identical nodes and removing any that can't
+/// affect the output
+pub fn optimise(graph: &mut Graph) {
+	let before = graph.nodes.len();
+
+	simplify(graph);
+	eliminate_common_subexpressions(graph);
+	remove_dead_nodes(graph);
+
+	// Simplifying can add nodes as well as remove them, so the count may go up
+	log::info!("Optimisation took the synth from {} to {} nodes", before, graph.nodes.len());
+}
+
+
+// Rebuilds the graph one node at a time. `rewrite` is given each node with its inputs
+// already remapped, and returns whatever should replace it
+fn rewrite<F>(graph: &mut Graph, mut rewrite: F)
+	where F: FnMut(&mut Graph, NodeRef, Node) -> Input
+{
+	let nodes = std::mem::replace(&mut graph.nodes, Vec::new());
+	let mut remap: Vec<Input> = Vec::with_capacity(nodes.len());
+
+	for (index, mut node) in nodes.into_iter().enumerate() {
+		for input in node.inputs.iter_mut() {
+			if let Input::Node(n) = *input {
+				*input = remap[n.0];
+			}
+		}
+
+		// Anything created in place of a node comes from the same expression
+		graph.set_span(node.span);
+
+		let mut replacement = rewrite(graph, NodeRef(index), node);
+
+		// The output has to be a node, even if it simplified to something else. It stays
+		// where it was, since stores can be written to after it
+		if graph.output == Some(NodeRef(index)) && replacement.node().is_none() {
+			replacement = graph.new_add(replacement, 0.0).into();
+		}
+
+		remap.push(replacement);
+	}
+
+	graph.output = graph.output.map(|output| remap[output.0].node().unwrap());
+
+	graph.set_span(None);
+}
+
+fn use_counts(graph: &Graph) -> Vec<usize> {
+	let mut uses = vec![0; graph.nodes.len()];
+
+	for node in graph.nodes.iter() {
+		for input in node.inputs.iter() {
+			if let Input::Node(n) = *input {
+				uses[n.0] += 1;
+			}
+		}
+	}
+
+	if let Some(output) = graph.output {
+		uses[output.0] += 1;
+	}
+
+	uses
+}
+
+
+// Folds literals, removes identity operations, and flattens chains of additions and
+// multiplications so that all of their literal operands are combined into one
+fn simplify(graph: &mut Graph) {
+	let uses = use_counts(graph);
+	let original = graph.nodes.clone();
+	let mut remap = Vec::with_capacity(original.len());
+
+	rewrite(graph, |graph, node_ref, node| {
+		let result = match node.op {
+			Op::Add | Op::Multiply => {
+				let mut leaves = Vec::new();
+				collect_chain(&original, &uses, node_ref, node_ref, &mut leaves);
+
+				// Operands that were nodes have to be remapped like any other input
+				let (literals, signals): (Vec<_>, Vec<_>) = leaves.into_iter()
+					.map(|leaf| match leaf {
+						Input::Node(n) => remap[n.0],
+						leaf => leaf,
+					})
+					.partition(|leaf| leaf.literal().is_some());
+
+				let literal = literals.into_iter()
+					.fold(None, |acc, lit| Some(match acc {
+						Some(acc) => combine(graph, node.op, acc, lit),
+						None => lit,
+					}));
+
+				let mut operands = signals.into_iter().chain(literal);
+				let first = operands.next().unwrap();
+
+				operands.fold(first, |acc, operand| combine(graph, node.op, acc, operand))
+			}
+
+			Op::Sub => match (node.inputs[0], node.inputs[1]) {
+				(Input::Literal(a), Input::Literal(b)) => Input::Literal(a - b),
+				(a, b) if a == b => Input::Literal(0.0),
+				(a, Input::Literal(b)) => combine(graph, Op::Add, a, Input::Literal(-b)),
+				(a, b) => graph.new_sub(a, b).into(),
+			}
+
+			Op::Mix => match (node.inputs[0], node.inputs[1], node.inputs[2]) {
+				(Input::Literal(a), Input::Literal(b), Input::Literal(mix)) => Input::Literal(a + (b - a) * mix),
+				(a, _, Input::Literal(mix)) if mix == 0.0 => a,
+				(_, b, Input::Literal(mix)) if mix == 1.0 => b,
+				(a, b, _) if a == b => a,
+				(a, b, mix) => graph.new_mix(a, b, mix).into(),
+			}
+
+			Op::Clamp => match (node.inputs[0], node.inputs[1], node.inputs[2]) {
+				(Input::Literal(x), Input::Literal(lb), Input::Literal(ub)) => Input::Literal(x.max(lb).min(ub)),
+				(x, lb, ub) => graph.new_clamp(x, lb, ub).into(),
+			}
+
+			_ => graph.new_node(node.op, node.inputs).into(),
+		};
+
+		remap.push(result);
+		result
+	});
+}
+
+// Collects the operands of a chain of the same associative operation, looking through
+// nodes that aren't used anywhere else. The chain is rebuilt where its root is, so it stops
+// at nodes that read a store written to before then
+fn collect_chain(nodes: &[Node], uses: &[usize], root: NodeRef, node: NodeRef, leaves: &mut Vec<Input>) {
+	let op = nodes[node.0].op;
+
+	for &input in nodes[node.0].inputs.iter() {
+		match input {
+			Input::Node(n) if nodes[n.0].op == op && uses[n.0] == 1 && !reads_stale_store(&nodes[n.0], &nodes[n.0 + 1..root.0]) =>
+				collect_chain(nodes, uses, root, n, leaves),
+			input => leaves.push(input),
+		}
+	}
+}
+
+// Whether node reads a store that one of the nodes after it writes to
+fn reads_stale_store(node: &Node, after: &[Node]) -> bool {
+	node.inputs.iter().any(|input| match *input {
+		Input::Store(store) => after.iter().any(|n| n.op == Op::StoreWrite(store)),
+		_ => false,
+	})
+}
+
+// Adds or multiplies two inputs, folding literals into the end of an existing chain
+fn combine(graph: &mut Graph, op: Op, a: Input, b: Input) -> Input {
+	let (apply, identity): (fn(f32, f32) -> f32, f32) = match op {
+		Op::Add => (|a, b| a + b, 0.0),
+		Op::Multiply => (|a, b| a * b, 1.0),
+		_ => unreachable!(),
+	};
+
+	// Keep literals on the right
+	let (a, b) = match (a, b) {
+		(Input::Literal(_), b) if b.literal().is_none() => (b, a),
+		ab => ab,
+	};
+
+	match (a, b) {
+		(Input::Literal(a), Input::Literal(b)) => Input::Literal(apply(a, b)),
+		(a, Input::Literal(b)) if b == identity => a,
+		(_, Input::Literal(b)) if op == Op::Multiply && b == 0.0 => Input::Literal(0.0),
+
+		(Input::Node(n), Input::Literal(b)) if graph.node(n).op == op && graph.node(n).inputs[1].literal().is_some()
+			&& !reads_stale_store(graph.node(n), &graph.nodes[n.0 + 1..]) =>
+		{
+			let inner = graph.node(n).inputs[0];
+			let lit = graph.node(n).inputs[1].literal().unwrap();
+			combine(graph, op, inner, Input::Literal(apply(lit, b)))
+		}
+
+		(a, b) => graph.new_node(op, vec![a, b]).into(),
+	}
+}
+
+
+// Merges nodes that perform the same operation on the same inputs
+fn eliminate_common_subexpressions(graph: &mut Graph) {
+	let mut seen: HashMap<(Op, Vec<Input>, usize), NodeRef> = HashMap::new();
+	let mut store_writes = 0;
+
+	rewrite(graph, |graph, _, node| {
+		let Node {op, mut inputs, ..} = node;
+
+		if op.has_side_effects() {
+			store_writes += 1;
+			return graph.new_node(op, inputs).into();
+		}
+
+		if op == Op::Add || op == Op::Multiply {
+			inputs.sort_by_key(input_order);
+		}
+
+		// A store can hold a different value either side of a write to it
+		let reads_store = inputs.iter().any(|i| if let Input::Store(_) = i { true } else { false });
+		let key = (op, inputs, if reads_store { store_writes } else { 0 });
+
+		if let Some(&existing) = seen.get(&key) {
+			return existing.into();
+		}
+
+		let node = graph.new_node(op, key.1.clone());
+		seen.insert(key, node);
+		node.into()
+	});
+}
+
+// An arbitrary but consistent order, so that commutative operations can be compared
+fn input_order(input: &Input) -> (u8, u64) {
+	match *input {
+		Input::Node(n) => (0, n.0 as u64),
+		Input::Store(s) => (1, s.0 as u64),
+		Input::Parameter(p) => (2, p.0 as u64),
+		Input::Literal(f) => (3, f.to_bits() as u64),
+	}
+}
+
+
+// Removes nodes that can't reach the output, along with any buffers only they used. Store
+// writes only reach it through the stores they write, so writes to a store nothing live reads
+// are dead too
+fn remove_dead_nodes(graph: &mut Graph) {
+	let mut live = vec![false; graph.nodes.len()];
+	let mut live_stores = vec![false; graph.stores.len()];
+
+	if let Some(output) = graph.output {
+		live[output.0] = true;
+	}
+
+	// Stores can be read before the writes to them, so this goes until no more turn up
+	loop {
+		let mut found_store = false;
+
+		for (index, node) in graph.nodes.iter().enumerate().rev() {
+			if let Op::StoreWrite(store) = node.op {
+				live[index] |= live_stores[store.0];
+			}
+
+			if !live[index] {
+				continue
+			}
+
+			for input in node.inputs.iter() {
+				match *input {
+					Input::Node(n) => live[n.0] = true,
+					Input::Store(s) if !live_stores[s.0] => {
+						live_stores[s.0] = true;
+						found_store = true;
+					}
+					_ => {}
+				}
+			}
+		}
+
+		if !found_store {
+			break
+		}
+	}
+
+	rewrite(graph, |graph, node_ref, node| {
+		if live[node_ref.0] {
+			graph.new_node(node.op, node.inputs).into()
+		} else {
+			// Never referenced by a live node
+			Input::Literal(0.0)
+		}
+	});
+
+	let mut buffers = std::mem::replace(&mut graph.buffers, Vec::new())
+		.into_iter()
+		.map(Some)
+		.collect::<Vec<_>>();
+
+	let mut remap = vec![None; buffers.len()];
+
+	for node in graph.nodes.iter_mut() {
+		if let Some(buffer) = node.op.buffer_mut() {
+			if remap[buffer.0].is_none() {
+				graph.buffers.push(buffers[buffer.0].take().unwrap());
+				remap[buffer.0] = Some(BufferRef(graph.buffers.len() - 1));
+			}
+
+			*buffer = remap[buffer.0].unwrap();
+		}
+	}
+}
+
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::lisp::builtins;
+
+	fn ops(graph: &Graph) -> Vec<Op> {
+		graph.nodes.iter().map(|n| n.op).collect()
+	}
+
+	#[test]
+	fn literal_chains_fold_together() {
+		let mut graph = Graph::new();
+		let freq = graph.new_parameter("key-freq");
+		let a = graph.new_add(freq, 1.0);
+		let b = graph.new_add(a, 2.0);
+		let c = graph.new_multiply(b, 1.0);
+		let output = graph.new_sine(c);
+		graph.set_output(output);
+
+		simplify(&mut graph);
+		remove_dead_nodes(&mut graph);
+
+		assert_eq!(ops(&graph), [Op::Add, Op::Sine]);
+		assert_eq!(graph.nodes[0].inputs, [Input::Parameter(freq), Input::Literal(3.0)]);
+		assert_eq!(graph.nodes[1].inputs, [Input::Node(NodeRef(0))]);
+	}
+
+	#[test]
+	fn identities_are_removed() {
+		let mut graph = Graph::new();
+		let freq = graph.new_parameter("key-freq");
+		let sine = graph.new_sine(freq);
+		let zero = graph.new_sub(sine, sine);
+		let mixed = graph.new_mix(sine, zero, 0.0);
+		let clamped = graph.new_clamp(2.0, 0.0, 1.0);
+		let output = graph.new_multiply(mixed, clamped);
+		graph.set_output(output);
+
+		simplify(&mut graph);
+		remove_dead_nodes(&mut graph);
+
+		assert_eq!(ops(&graph), [Op::Sine]);
+		assert_eq!(graph.output, Some(NodeRef(0)));
+	}
+
+	#[test]
+	fn identical_nodes_merge() {
+		let mut graph = Graph::new();
+		let freq = graph.new_parameter("key-freq");
+		let a = graph.new_sine(freq);
+		let b = graph.new_sine(freq);
+		let x = graph.new_add(a, 1.0);
+		let y = graph.new_add(1.0, b);
+		let output = graph.new_multiply(x, y);
+		graph.set_output(output);
+
+		eliminate_common_subexpressions(&mut graph);
+
+		assert_eq!(ops(&graph), [Op::Sine, Op::Add, Op::Multiply]);
+		assert_eq!(graph.nodes[2].inputs, [Input::Node(NodeRef(1)), Input::Node(NodeRef(1))]);
+	}
+
+	#[test]
+	fn store_reads_dont_merge_across_writes() {
+		let mut graph = Graph::new();
+		let store = graph.new_value_store("x");
+		let before = graph.new_add(store, 1.0);
+		let written = graph.new_add(store, 1.0);
+		graph.new_store_write(store, written);
+		let after = graph.new_add(store, 1.0);
+		let output = graph.new_sub(before, after);
+		graph.set_output(output);
+
+		eliminate_common_subexpressions(&mut graph);
+
+		assert_eq!(ops(&graph), [Op::Add, Op::StoreWrite(store), Op::Add, Op::Sub]);
+		assert_eq!(graph.nodes[3].inputs, [Input::Node(NodeRef(0)), Input::Node(NodeRef(2))]);
+	}
+
+	#[test]
+	fn store_reads_dont_move_past_writes() {
+		let mut graph = Graph::new();
+		let store = graph.new_value_store("s");
+		let a = graph.new_add(store, 1.0);
+		let sine = graph.new_sine(1.0);
+		graph.new_store_write(store, sine);
+		let output = graph.new_add(a, 2.0);
+		graph.set_output(output);
+
+		simplify(&mut graph);
+		remove_dead_nodes(&mut graph);
+
+		// a has to be worked out from what the store held before the write, so nothing after
+		// it reads the store
+		let write = graph.nodes.iter().position(|n| n.op == Op::StoreWrite(store)).unwrap();
+		assert!(graph.nodes[write + 1..].iter().all(|n| !n.inputs.contains(&Input::Store(store))),
+			"the store is read after it's written: {:?}", ops(&graph));
+	}
+
+	#[test]
+	fn rounding_survives() {
+		// Rounding relies on the offset being added and taken away as separate steps, so
+		// neither can be folded into the other
+		for &multiple in &[1.0, 0.25] {
+			let mut graph = Graph::new();
+			let x = graph.new_parameter("x");
+			let rounded = builtins::round(&mut graph, x.into(), multiple);
+			let output = graph.new_sine(rounded);
+			graph.set_output(output);
+
+			optimise(&mut graph);
+
+			let offset = Input::Literal(builtins::ROUNDING_OFFSET * multiple);
+			assert_eq!(ops(&graph), [Op::Sub, Op::Sub, Op::Sine]);
+			assert_eq!(graph.nodes[0].inputs, [offset, Input::Parameter(x)]);
+			assert_eq!(graph.nodes[1].inputs, [offset, Input::Node(NodeRef(0))]);
+		}
+	}
+
+	#[test]
+	fn dead_nodes_and_their_buffers_are_removed() {
+		let mut graph = Graph::new();
+		let unused = graph.new_buffer(vec![1.0]);
+		let used = graph.new_buffer(vec![2.0]);
+		let store = graph.new_value_store("x");
+
+		graph.new_sampler(unused);
+		let sampler = graph.new_sampler(used);
+		let written = graph.new_saw(110.0);
+		graph.new_store_write(store, written);
+		let output = graph.new_add(sampler, store);
+		graph.set_output(output);
+
+		remove_dead_nodes(&mut graph);
+
+		assert_eq!(ops(&graph), [Op::Sampler(BufferRef(0)), Op::Saw, Op::StoreWrite(store), Op::Add]);
+		assert_eq!(graph.buffers, [vec![2.0]]);
+		assert_eq!(graph.output, Some(NodeRef(3)));
+	}
+
+	#[test]
+	fn writes_to_unread_stores_are_removed() {
+		let mut graph = Graph::new();
+		let unread = graph.new_value_store("unread");
+		let saw = graph.new_saw(110.0);
+		graph.new_store_write(unread, saw);
+		let output = graph.new_sine(220.0);
+		graph.set_output(output);
+
+		remove_dead_nodes(&mut graph);
+
+		assert_eq!(ops(&graph), [Op::Sine]);
+	}
+
+	#[test]
+	fn stores_read_through_other_stores_are_kept() {
+		let mut graph = Graph::new();
+		let first = graph.new_value_store("first");
+		let second = graph.new_value_store("second");
+		let third = graph.new_value_store("third");
+
+		// Each store is read before the write that makes it live
+		let saw = graph.new_saw(110.0);
+		graph.new_store_write(first, saw);
+		graph.new_store_write(second, first);
+		let output = graph.new_add(third, 1.0);
+		let sine = graph.new_sine(second);
+		graph.new_store_write(third, sine);
+		graph.set_output(output);
+
+		remove_dead_nodes(&mut graph);
+
+		assert_eq!(ops(&graph), [Op::Saw, Op::StoreWrite(first), Op::StoreWrite(second), Op::Add, Op::Sine, Op::StoreWrite(third)]);
+	}
+
+	#[test]
+	fn the_output_stays_a_node() {
+		let mut graph = Graph::new();
+		let output = graph.new_add(1.0, 2.0);
+		graph.set_output(output);
+
+		optimise(&mut graph);
+
+		assert_eq!(ops(&graph), [Op::Add]);
+		assert!(graph.nodes[0].inputs.contains(&Input::Literal(3.0)));
+		assert!(graph.nodes[0].inputs.contains(&Input::Literal(0.0)));
+	}
+}
@@ -1,23 +1,35 @@
-
 use super::LispResult;
 use super::sexpression::SExpression;
+use super::span::Span;
 
 use voi_synth::failure::{format_err, bail};
 
 use self::SExpression::*;
 
+// Deeper nesting is an error rather than a stack overflow, which would take the host down with it
+const MAX_DEPTH: usize = 128;
+
 #[derive(Copy, Clone, Debug)]
 pub struct ExprReader<'a> {
+	source: &'a str,
 	input: &'a str,
+
+	// How many lists this reader is nested in
+	depth: usize,
 }
 
 impl<'a> ExprReader<'a> {
 	pub fn new(input: &str) -> ExprReader {
-		ExprReader {input}
+		ExprReader {source: input, input, depth: 0}
 	}
 
 	pub fn is_empty(&self) -> bool { self.input.is_empty() }
 
+	// Byte offset of the remaining input into the source
+	pub fn offset(&self) -> usize {
+		self.input.as_ptr() as usize - self.source.as_ptr() as usize
+	}
+
 	pub fn peek(&self) -> LispResult<char> {
 		self.input.chars()
 			.next()
@@ -38,6 +50,12 @@ impl<'a> ExprReader<'a> {
 
 	pub fn skip_whitespace(&mut self) {
 		self.input = self.input.trim_start();
+
+		// Comments run from a ';' to the end of the line
+		while self.input.starts_with(';') {
+			let line_end = self.input.find('\n').unwrap_or(self.input.len());
+			self.input = self.input[line_end..].trim_start();
+		}
 	}
 
 	pub fn parse_toplevel(&mut self) -> LispResult<Vec<SExpression<'a>>> {
@@ -54,24 +72,51 @@ impl<'a> ExprReader<'a> {
 	}
 
 	pub fn parse_sexpression(&mut self) -> LispResult<SExpression<'a>> {
+		let start = self.offset();
+
 		match self.peek()? {
 			'(' => {
 				let list = self.parse_list('(', ')')?;
-				Ok( List(list) )
+				Ok( List(list, Span::new(start, self.offset())) )
 			}
 
 			'[' => {
 				let list = self.parse_list('[', ']')?;
-				Ok( Array(list) )
+				Ok( Array(list, Span::new(start, self.offset())) )
+			}
+
+			c @ ')' | c @ ']' => bail!("Unexpected '{}' with no list to close", c),
+
+			'"' => {
+				let string = self.parse_string()?;
+				Ok( Str(string, Span::new(start, self.offset())) )
+			}
+
+			// Quoted names, like 'white, are read as strings
+			'\'' => {
+				self.input = &self.input[1..];
+
+				// The name has to follow straight on, without even a comment in between
+				let name = match self.input.chars().next() {
+					Some(c) if !c.is_whitespace() && c != ';' => self.parse_word()?,
+					_ => "",
+				};
+
+				if name.is_empty() {
+					bail!("Expected a name after the quote");
+				}
+
+				Ok( Str(name, Span::new(start, self.offset())) )
 			}
 
 			_ => {
 				let word = self.parse_word()?;
+				let span = Span::new(start, self.offset());
 
 				if let Ok(f) = word.parse() {
-					Ok( Number(f) )
+					Ok( Number(f, span) )
 				} else {
-					Ok( Identifier(word) )
+					Ok( Identifier(word, span) )
 				}
 			}
 		}
@@ -81,7 +126,7 @@ impl<'a> ExprReader<'a> {
 		self.skip_whitespace();
 
 		let word_end = self.input
-			.find(char::is_whitespace)
+			.find(|c: char| c.is_whitespace() || ";()[]\"".contains(c))
 			.unwrap_or(self.input.len());
 
 		let (word, rest) = self.input.split_at(word_end);
@@ -89,6 +134,18 @@ impl<'a> ExprReader<'a> {
 		Ok(word)
 	}
 
+	// Strings run to the next quote, and have no escapes
+	pub fn parse_string(&mut self) -> LispResult<&'a str> {
+		self.expect('"')?;
+
+		let end = self.input.find('"')
+			.ok_or_else(|| format_err!("Couldn't find the end of the string"))?;
+
+		let (string, rest) = self.input.split_at(end);
+		self.input = &rest[1..];
+		Ok(string)
+	}
+
 	pub fn parse_list(&mut self, open: char, close: char) -> LispResult<Vec<SExpression<'a>>> {
 		let mut list_parser = self.list_parser(open, close)?;
 		let mut ret = Vec::new();
@@ -99,35 +156,175 @@ impl<'a> ExprReader<'a> {
 			ret.push(list_parser.parse_sexpression()?);
 			list_parser.skip_whitespace();
 		}
-		
+
 		Ok(ret)
 	}
 
 	fn list_parser(&mut self, open: char, close: char) -> LispResult<ExprReader<'a>> {
 		self.expect(open)?;
 
-		let end = self.input
-			.char_indices()
-			.scan(1, |level, (pos, c)| {
-				match c {
-					c if (c == open) => { *level += 1 }
-					c if (c == close) => { *level -= 1 }
-					_ => {}
-				}
+		if self.depth >= MAX_DEPTH {
+			bail!("Lists are nested more than {} deep", MAX_DEPTH);
+		}
+
+		let mut level = 1;
+		let mut in_comment = false;
+		let mut in_string = false;
+		let mut end = None;
 
-				Some((*level, pos))
-			})
-			.find(|(l, _)| *l == 0);
+		for (pos, c) in self.input.char_indices() {
+			match c {
+				'"' if !in_comment => { in_string = !in_string }
+				_ if in_string => {}
+				'\n' => { in_comment = false }
+				_ if in_comment => {}
+				';' => { in_comment = true }
+				c if (c == open) => { level += 1 }
+				c if (c == close) => { level -= 1 }
+				_ => {}
+			}
 
-		if let Some((_, pos)) = end {
+			if level == 0 {
+				end = Some(pos);
+				break;
+			}
+		}
+
+		if let Some(pos) = end {
 			let (list_str, rest) = self.input.split_at(pos);
 
 			self.input = rest;
 			self.expect(close)?;
 
-			Ok(ExprReader::new(list_str))
+			Ok(ExprReader {source: self.source, input: list_str, depth: self.depth + 1})
 		} else {
 			bail!("Couldn't find end of the list");
 		}
 	}
 }
+
+
+#[cfg(test)]
+mod tests {
+	use super::ExprReader;
+	use proptest::prelude::*;
+
+	// Nested lists and arrays of identifiers and numbers, printed as source
+	fn source_tree() -> impl Strategy<Value = String> {
+		let leaf = prop_oneof![
+			"[a-z][a-z0-9-]{0,6}",
+			any::<f32>().prop_filter("finite", |f| f.is_finite()).prop_map(|f| f.to_string()),
+		];
+
+		leaf.prop_recursive(6, 64, 8, |inner| {
+			(prop::collection::vec(inner, 0..8), any::<bool>(), "[ \n]{1,3}")
+				.prop_map(|(items, array, gap)| {
+					let (open, close) = if array { ('[', ']') } else { ('(', ')') };
+					format!("{}{}{}", open, items.join(&gap), close)
+				})
+		})
+	}
+
+	proptest! {
+		#[test]
+		fn arbitrary_input_doesnt_panic(source in any::<String>()) {
+			let _ = ExprReader::new(&source).parse_toplevel();
+		}
+
+		#[test]
+		fn bracket_soup_doesnt_panic(source in "[()\\[\\]; \na1.é\"]{0,64}") {
+			let _ = ExprReader::new(&source).parse_toplevel();
+		}
+
+		#[test]
+		fn balanced_trees_round_trip(trees in prop::collection::vec(source_tree(), 1..4)) {
+			let source = trees.join("\n; comment\n");
+			let parsed = ExprReader::new(&source).parse_toplevel().unwrap();
+
+			// Printing the parsed trees back out gives the same thing again
+			let printed = parsed.iter()
+				.map(|e| super::super::format(&source[e.span().start..e.span().end]).unwrap())
+				.collect::<String>();
+
+			let reparsed = ExprReader::new(&printed).parse_toplevel().unwrap();
+
+			prop_assert_eq!(parsed.len(), reparsed.len());
+			for (a, b) in parsed.iter().zip(&reparsed) {
+				prop_assert!(a.same_shape(b), "{:?} != {:?}", a, b);
+			}
+		}
+
+		#[test]
+		fn quotes_must_touch_their_names(gap in "[ \t\n]{1,3}|;[a-z ]{0,8}\n", name in "[a-z][a-z0-9-]{0,6}") {
+			let source = format!("(noise '{}{})", gap, name);
+			prop_assert!(ExprReader::new(&source).parse_toplevel().is_err(), "{:?} parsed", source);
+		}
+
+		#[test]
+		fn unbalanced_input_is_an_error(tree in source_tree(), index in any::<prop::sample::Index>()) {
+			let brackets = tree.match_indices(|c| "()[]".contains(c))
+				.map(|(i, _)| i)
+				.collect::<Vec<_>>();
+
+			prop_assume!(!brackets.is_empty());
+
+			let mut source = tree.clone();
+			source.remove(brackets[index.index(brackets.len())]);
+
+			prop_assert!(ExprReader::new(&source).parse_toplevel().is_err(), "{:?} parsed", source);
+		}
+	}
+
+	#[test]
+	fn deep_nesting_is_an_error() {
+		let source = format!("{}{}", "(".repeat(100_000), ")".repeat(100_000));
+		assert!(ExprReader::new(&source).parse_toplevel().is_err());
+	}
+
+	#[test]
+	fn stray_closing_brackets_are_errors() {
+		assert!(ExprReader::new(")").parse_toplevel().is_err());
+		assert!(ExprReader::new("(a])").parse_toplevel().is_err());
+		assert!(ExprReader::new("a(b").parse_toplevel().is_err());
+	}
+
+	#[test]
+	fn spans_cover_each_expression() {
+		let source = "(sin 440)\n  [1 (key-freq)] ; trailing\n-2.5";
+		let parsed = ExprReader::new(source).parse_toplevel().unwrap();
+
+		let text = |e: &super::SExpression| &source[e.span().start..e.span().end];
+		assert_eq!(parsed.iter().map(text).collect::<Vec<_>>(), ["(sin 440)", "[1 (key-freq)]", "-2.5"]);
+
+		match &parsed[1] {
+			super::Array(items, _) => assert_eq!(text(&items[1]), "(key-freq)"),
+			e => panic!("expected an array, got {:?}", e),
+		}
+	}
+
+	#[test]
+	fn comments_are_skipped() {
+		let source = "; header\n(output ; the output\n  (sin 440)) ; done\n; (unclosed";
+		let parsed = ExprReader::new(source).parse_toplevel().unwrap();
+		let expected = ExprReader::new("(output (sin 440))").parse_toplevel().unwrap();
+
+		assert_eq!(parsed.len(), 1);
+		assert!(parsed[0].same_shape(&expected[0]));
+
+		// Brackets in comments don't open or close lists, and comments end words
+		let parsed = ExprReader::new("(a ; )\n b;c\n)").parse_toplevel().unwrap();
+		let expected = ExprReader::new("(a b)").parse_toplevel().unwrap();
+		assert!(parsed[0].same_shape(&expected[0]));
+	}
+
+	#[test]
+	fn quoted_names_are_strings() {
+		let parsed = ExprReader::new("(noise 'pink)").parse_toplevel().unwrap();
+		let expected = ExprReader::new("(noise \"pink\")").parse_toplevel().unwrap();
+		assert!(parsed[0].same_shape(&expected[0]));
+
+		assert!(ExprReader::new("(noise ' pink)").parse_toplevel().is_err());
+		assert!(ExprReader::new("(noise ')").parse_toplevel().is_err());
+		assert!(ExprReader::new("';x\nfoo").parse_toplevel().is_err());
+	}
+}
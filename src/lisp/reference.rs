@@ -0,0 +1,117 @@
+//! The language reference, generated from the builtin and special form tables
+
+use super::builtins::{BUILTINS, SPECIAL_FORMS};
+
+use std::fmt::Write;
+
+
+/// A file-name-safe name for each special form and builtin, paired with its example
+pub fn examples() -> Vec<(String, &'static str)> {
+	let forms = SPECIAL_FORMS.iter().map(|f| (slug(f.name), f.example));
+	let builtins = BUILTINS.iter().map(|b| (slug(b.name), b.example));
+
+	forms.chain(builtins).collect()
+}
+
+/// The reference as Markdown. When `audio_dir` is given, each example links to
+/// `<audio_dir>/<slug>.wav`, as written by `vst-lisp docs --audio`
+pub fn markdown(audio_dir: Option<&str>) -> String {
+	let mut out = String::new();
+
+	out += "# Patch reference\n\n";
+	out += "Generated by `vst-lisp docs`. Arguments of kind signal also accept constants.\n\n";
+
+	out += "## Special forms\n\n";
+
+	for form in SPECIAL_FORMS {
+		writeln!(out, "### `{}`\n", form.name).unwrap();
+		writeln!(out, "```lisp\n{}\n```\n", form.usage).unwrap();
+		writeln!(out, "{}\n", form.doc).unwrap();
+		example(&mut out, form.name, form.example, audio_dir);
+	}
+
+	out += "## Builtins\n\n";
+
+	for builtin in BUILTINS {
+		writeln!(out, "### `{}`\n", builtin.name).unwrap();
+
+		if !builtin.aliases.is_empty() {
+			let aliases = builtin.aliases.iter()
+				.map(|a| format!("`{}`", a))
+				.collect::<Vec<_>>();
+
+			writeln!(out, "Also called {}.\n", aliases.join(", ")).unwrap();
+		}
+
+		for sig in builtin.signatures {
+			writeln!(out, "```lisp\n{}\n```\n", sig.usage(builtin.name)).unwrap();
+
+			if !sig.params.is_empty() {
+				out += "| Argument | Kind | Default |\n";
+				out += "|----------|------|---------|\n";
+
+				for param in sig.params {
+					let default = param.default.map(|d| d.to_string()).unwrap_or_default();
+					writeln!(out, "| `{}` | {} | {} |", param.name, param.kind.name(), default).unwrap();
+				}
+
+				out += "\n";
+			}
+
+			writeln!(out, "Returns {}.\n", sig.returns).unwrap();
+		}
+
+		writeln!(out, "{}\n", builtin.doc).unwrap();
+		example(&mut out, builtin.name, builtin.example, audio_dir);
+	}
+
+	out
+}
+
+fn example(out: &mut String, name: &str, example: &str, audio_dir: Option<&str>) {
+	writeln!(out, "Example:\n\n```lisp\n{}\n```\n", example).unwrap();
+
+	if let Some(dir) = audio_dir {
+		writeln!(out, "[Listen]({}/{}.wav)\n", dir, slug(name)).unwrap();
+	}
+}
+
+fn slug(name: &str) -> String {
+	let slug = match name {
+		"+" => "add",
+		"-" => "subtract",
+		"*" => "multiply",
+		"/" => "divide",
+		"<" => "less",
+		">" => "greater",
+		"<=" => "less-equal",
+		">=" => "greater-equal",
+		"=" => "equal",
+		name => name,
+	};
+
+	slug.to_owned()
+}
+
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn examples_compile() {
+		for (slug, example) in examples() {
+			if let Err(err) = crate::lisp::compile(example, 44100.0) {
+				panic!("The example for {} doesn't compile: {}", slug, err);
+			}
+		}
+	}
+
+	#[test]
+	fn slugs_are_unique() {
+		let mut slugs = examples().into_iter().map(|(s, _)| s).collect::<Vec<_>>();
+		slugs.sort();
+		slugs.dedup();
+		assert_eq!(slugs.len(), examples().len());
+	}
+}
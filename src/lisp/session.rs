@@ -0,0 +1,157 @@
+use super::LispResult;
+use super::parser::ExprReader;
+use super::typecheck::Checker;
+use super::evaluation::{EvaluationContext, EvalResult};
+use super::ir::{Graph, Input};
+use super::optimise::optimise;
+use voi_synth::failure::bail;
+
+
+/// Evaluates input a piece at a time against the same bindings and graph, for
+/// interactive use
+pub struct Session {
+	// Everything evaluated so far, which spans in the graph refer to
+	source: String,
+
+	// Bindings borrow from the inputs they came from, which are leaked so they last as long
+	// as anything could refer to them. A session only sees as much input as someone types
+	checker: Checker<'static>,
+	ctx: EvaluationContext<'static>,
+}
+
+impl Session {
+	pub fn new(sample_rate: f32) -> Session {
+		Session {
+			source: String::new(),
+			checker: Checker::new(),
+			ctx: EvaluationContext::new(sample_rate),
+		}
+	}
+
+	pub fn source(&self) -> &str { &self.source }
+
+	/// Evaluates every form in `input`, returning a description of each value produced.
+	/// If any form fails, the session is left as it was before
+	pub fn evaluate(&mut self, input: &str) -> LispResult<Vec<String>> {
+		let checkpoint = (self.checker.clone(), self.ctx.clone(), self.source.len());
+		let input: &'static str = Box::leak(input.into());
+
+		let result = self.evaluate_input(input);
+
+		if result.is_err() {
+			let (checker, ctx, source_len) = checkpoint;
+			self.checker = checker;
+			self.ctx = ctx;
+			self.source.truncate(source_len);
+		}
+
+		result
+	}
+
+	fn evaluate_input(&mut self, input: &'static str) -> LispResult<Vec<String>> {
+		let offset = self.source.len();
+
+		let mut exprs = ExprReader::new(input).parse_toplevel()?;
+
+		for sexpr in exprs.iter_mut() {
+			sexpr.offset_spans(offset);
+		}
+
+		self.source.push_str(input);
+		self.source.push('\n');
+
+		for sexpr in exprs.iter() {
+			self.checker.check_top_level(sexpr);
+		}
+
+		let diagnostics = self.checker.take_diagnostics();
+		if !diagnostics.is_empty() {
+			let messages = diagnostics.iter()
+				.map(|d| format!("{}: {}", d.span.location(&self.source), d.message))
+				.collect::<Vec<_>>();
+
+			bail!("{}", messages.join("\n"));
+		}
+
+		let mut results = Vec::new();
+
+		for sexpr in exprs {
+			self.ctx.graph.set_span(Some(sexpr.span()));
+			let result = self.ctx.evaluate_top_level_form(sexpr);
+			self.ctx.graph.set_span(None);
+
+			if let Some(result) = result? {
+				results.push(self.describe(&result));
+			}
+		}
+
+		Ok(results)
+	}
+
+	/// The optimised graph of everything evaluated so far
+	pub fn graph(&self) -> Graph {
+		let mut graph = self.ctx.graph.clone();
+		self.ctx.shift_delay_lines(&mut graph);
+		optimise(&mut graph);
+		graph
+	}
+
+	fn describe(&self, result: &EvalResult) -> String {
+		let graph = &self.ctx.graph;
+
+		match result {
+			EvalResult::Constant(f) => format!("constant {}", f),
+			EvalResult::Function(_) => "function".into(),
+			EvalResult::Str(s) => format!("string \"{}\"", s),
+			EvalResult::DelayLine(_) => "delay line".into(),
+
+			EvalResult::Array(elements) => {
+				let elements = elements.iter()
+					.map(|e| self.describe(e))
+					.collect::<Vec<_>>();
+
+				format!("array [{}]", elements.join(", "))
+			}
+
+			EvalResult::SynthNode(input) => match *input {
+				Input::Literal(f) => format!("constant {}", f),
+				Input::Node(n) => format!("node {} ({})", n.0, graph.node(n).op.name()),
+				Input::Store(s) => format!("store '{}'", graph.store(s).name),
+				Input::Parameter(p) => format!("parameter '{}'", graph.parameter(p).name),
+			},
+		}
+	}
+}
+
+
+#[cfg(test)]
+mod tests {
+	use super::Session;
+
+	#[test]
+	fn bindings_outlive_their_input() {
+		let mut session = Session::new(44100.0);
+
+		let input = String::from("(let f 220)");
+		session.evaluate(&input).unwrap();
+		drop(input);
+
+		assert_eq!(session.evaluate("(* f 2)").unwrap(), ["constant 440"]);
+	}
+
+	#[test]
+	fn failed_input_changes_nothing() {
+		let mut session = Session::new(44100.0);
+		session.evaluate("(let f 220)").unwrap();
+
+		// The first form checks and evaluates, but the second fails, so neither is kept
+		assert!(session.evaluate("(let g 1) (/ f 0)").is_err());
+		assert!(session.evaluate("(+ g 1)").is_err());
+
+		// Nor are forms that only fail to check
+		assert!(session.evaluate("(let f [1]) (sin f)").is_err());
+		assert_eq!(session.evaluate("(sin f)").unwrap().len(), 1);
+
+		assert_eq!(session.source(), "(let f 220)\n(sin f)\n");
+	}
+}
@@ -1,40 +1,62 @@
 use super::LispResult;
-use voi_synth::failure::{format_err, bail};
+use super::span::Span;
+use voi_synth::failure::bail;
 
 
 #[derive(Clone, Debug)]
 pub enum SExpression<'a> {
-	Identifier(&'a str),
-	Number(f32),
-	List(Vec<SExpression<'a>>),
-	Array(Vec<SExpression<'a>>),
+	Identifier(&'a str, Span),
+	Number(f32, Span),
+	Str(&'a str, Span),
+	List(Vec<SExpression<'a>>, Span),
+	Array(Vec<SExpression<'a>>, Span),
 }
 
 use self::SExpression::*;
 
 impl<'a> SExpression<'a> {
-	pub fn expect_ident(self) -> LispResult<&'a str> {
-		match self {
-			Identifier(s) => Ok(s),
-			Number(x) => bail!("Expected identifier, got number: {}", x),
-			List(v) => bail!("Expected identifier, got list: ({:?})", v),
-			Array(v) => bail!("Expected identifier, got array: ({:?})", v),
+	pub fn span(&self) -> Span {
+		match *self {
+			Identifier(_, span) | Number(_, span) | Str(_, span) | List(_, span) | Array(_, span) => span,
 		}
 	}
-}
 
+	// Moves every span in the expression, for when its source is part of something larger
+	pub fn offset_spans(&mut self, by: usize) {
+		match self {
+			Identifier(_, span) | Number(_, span) | Str(_, span) => *span = span.offset(by),
+
+			List(list, span) | Array(list, span) => {
+				*span = span.offset(by);
 
+				for sexpr in list.iter_mut() {
+					sexpr.offset_spans(by);
+				}
+			}
+		}
+	}
 
-pub trait ExpressionListExt {
-	fn is_constant(&self) -> bool;
-}
+	/// Whether two expressions are the same, ignoring where they came from
+	pub fn same_shape(&self, other: &SExpression) -> bool {
+		match (self, other) {
+			(Identifier(a, _), Identifier(b, _)) | (Str(a, _), Str(b, _)) => a == b,
+			(Number(a, _), Number(b, _)) => a.to_bits() == b.to_bits(),
+
+			(List(a, _), List(b, _)) | (Array(a, _), Array(b, _)) =>
+				a.len() == b.len() && a.iter().zip(b).all(|(a, b)| a.same_shape(b)),
 
-impl<'a> ExpressionListExt for Vec<SExpression<'a>> {
-	fn is_constant(&self) -> bool {
-		self.iter().all(|sexpr| match *sexpr {
-			SExpression::Number(_) => true,
-			// SExpression::Identifier(_) => true, // TODO
 			_ => false,
-		})
+		}
+	}
+
+	pub fn expect_ident(self) -> LispResult<&'a str> {
+		match self {
+			Identifier(s, _) => Ok(s),
+			Number(x, _) => bail!("Expected identifier, got number: {}", x),
+			Str(s, _) => bail!("Expected identifier, got string: \"{}\"", s),
+			List(v, _) => bail!("Expected identifier, got list: ({:?})", v),
+			Array(v, _) => bail!("Expected identifier, got array: ({:?})", v),
+		}
 	}
 }
+
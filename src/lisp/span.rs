@@ -0,0 +1,41 @@
+use std::fmt;
+
+/// A byte range into the source of a patch
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct Span {
+	pub start: usize,
+	pub end: usize,
+}
+
+/// A one-based line and column, for reporting spans to humans
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Location {
+	pub line: usize,
+	pub column: usize,
+}
+
+impl Span {
+	pub fn new(start: usize, end: usize) -> Span {
+		Span {start, end}
+	}
+
+	pub fn offset(self, by: usize) -> Span {
+		Span::new(self.start + by, self.end + by)
+	}
+
+	pub fn location(&self, source: &str) -> Location {
+		let before = &source[..self.start.min(source.len())];
+		let line_start = before.rfind('\n').map(|p| p + 1).unwrap_or(0);
+
+		Location {
+			line: before.matches('\n').count() + 1,
+			column: before[line_start..].chars().count() + 1,
+		}
+	}
+}
+
+impl fmt::Display for Location {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "{}:{}", self.line, self.column)
+	}
+}
@@ -0,0 +1,517 @@
+use super::sexpression::SExpression;
+use super::span::Span;
+use super::builtins;
+
+use std::collections::HashMap;
+use std::fmt;
+
+use self::Type::*;
+
+
+/// The kind of value an expression evaluates to
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Type {
+	Constant,
+	Signal,
+	Array,
+	Store,
+	Function,
+	Str,
+	DelayLine,
+
+	// Anything we can't know without evaluating, e.g., function parameters
+	Unknown,
+}
+
+impl Type {
+	/// Whether a value of type `actual` can be passed where `self` is expected
+	pub fn accepts(self, actual: Type) -> bool {
+		match (self, actual) {
+			(Unknown, _) | (_, Unknown) => true,
+			(Signal, Constant) | (Signal, Store) => true,
+			(expected, actual) => expected == actual,
+		}
+	}
+
+	pub fn name(self) -> &'static str {
+		match self {
+			Constant => "constant",
+			Signal => "signal",
+			Array => "array",
+			Store => "store",
+			Function => "function",
+			Str => "string",
+			DelayLine => "delay line",
+			Unknown => "anything",
+		}
+	}
+
+	fn join(self, other: Type) -> Type {
+		if self == other { self } else { Unknown }
+	}
+}
+
+impl fmt::Display for Type {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		let name = match *self {
+			Constant => "a constant",
+			Signal => "a signal",
+			Array => "an array",
+			Store => "a store",
+			Function => "a function",
+			Str => "a string",
+			DelayLine => "a delay line",
+			Unknown => "an unknown value",
+		};
+
+		f.write_str(name)
+	}
+}
+
+
+#[derive(Clone, Debug)]
+pub struct Diagnostic {
+	pub span: Span,
+	pub message: String,
+}
+
+
+/// Checks the kinds of values passed to every builtin before anything is evaluated,
+/// returning every mismatch found
+pub fn check(top_level: &[SExpression]) -> Vec<Diagnostic> {
+	let mut checker = Checker::new();
+
+	for sexpr in top_level {
+		checker.check_top_level(sexpr);
+	}
+
+	checker.diagnostics
+}
+
+
+/// Checks top level forms one at a time, remembering the bindings each one introduces
+#[derive(Clone)]
+pub struct Checker<'a> {
+	scope: HashMap<&'a str, Type>,
+	diagnostics: Vec<Diagnostic>,
+}
+
+impl<'a> Checker<'a> {
+	pub fn new() -> Self {
+		Checker {
+			scope: HashMap::new(),
+			diagnostics: Vec::new(),
+		}
+	}
+
+	pub fn take_diagnostics(&mut self) -> Vec<Diagnostic> {
+		std::mem::replace(&mut self.diagnostics, Vec::new())
+	}
+
+	fn error(&mut self, span: Span, message: String) {
+		self.diagnostics.push(Diagnostic {span, message});
+	}
+
+	pub fn check_top_level(&mut self, sexpr: &SExpression<'a>) {
+		let (list, span) = match sexpr {
+			SExpression::List(list, span) => (list, *span),
+			_ => {
+				self.error(sexpr.span(), "Unexpected item at top level of synth definition".into());
+				return;
+			}
+		};
+
+		let func_name = match list.first() {
+			Some(SExpression::Identifier(func_name, _)) => *func_name,
+			_ => {
+				self.check(sexpr);
+				return;
+			}
+		};
+
+		let args = &list[1..];
+
+		match func_name {
+			"let" => if self.check_arg_count(func_name, args, 2, span) {
+				let value = self.check(&args[1]);
+
+				if let Some(ident) = self.expect_ident(&args[0]) {
+					self.scope.insert(ident, value);
+				}
+			}
+
+			"defn" => if self.check_arg_count(func_name, args, 3, span) {
+				self.check_function(&args[1], &args[2]);
+
+				if let Some(ident) = self.expect_ident(&args[0]) {
+					self.scope.insert(ident, Function);
+				}
+			}
+
+			"gain" => if self.check_arg_count(func_name, args, 1, span) {
+				self.check_arg(func_name, "gain", Constant, &args[0]);
+			}
+
+			"output" => if self.check_arg_count(func_name, args, 1, span) {
+				let ty = self.check(&args[0]);
+
+				if ty == Constant || !Signal.accepts(ty) {
+					let message = format!("'{}' expects a signal, got {}", func_name, ty);
+					self.error(args[0].span(), message);
+				}
+			}
+
+			"def-store" => if self.check_arg_count(func_name, args, 1, span) {
+				if let Some(ident) = self.expect_ident(&args[0]) {
+					self.scope.insert(ident, Store);
+				}
+			}
+
+			"store" => if self.check_arg_count(func_name, args, 2, span) {
+				self.check_arg(func_name, "store", Store, &args[0]);
+				self.check_arg(func_name, "value", Signal, &args[1]);
+			}
+
+			_ => { self.check(sexpr); }
+		}
+	}
+
+	fn check(&mut self, sexpr: &SExpression<'a>) -> Type {
+		match sexpr {
+			SExpression::Number(..) => Constant,
+			SExpression::Str(..) => Str,
+
+			SExpression::Identifier(ident, span) => {
+				if let Some(&ty) = self.scope.get(ident) {
+					ty
+				} else {
+					self.error(*span, format!("Unknown identifier: '{}'", ident));
+					Unknown
+				}
+			}
+
+			SExpression::Array(elements, _) => {
+				for element in elements {
+					self.check(element);
+				}
+
+				Array
+			}
+
+			SExpression::List(list, span) => self.check_call(list, *span),
+		}
+	}
+
+	fn check_call(&mut self, list: &[SExpression<'a>], span: Span) -> Type {
+		let func_name = match list.first() {
+			Some(SExpression::Identifier(func_name, _)) => *func_name,
+			Some(sexpr) => {
+				self.error(sexpr.span(), "Expected function name".into());
+				return Unknown;
+			}
+			None => {
+				self.error(span, "Tried to evaluate an empty list".into());
+				return Unknown;
+			}
+		};
+
+		let args = &list[1..];
+
+		match func_name {
+			"fn" => {
+				if self.check_arg_count(func_name, args, 2, span) {
+					self.check_function(&args[0], &args[1]);
+				}
+
+				Function
+			}
+
+			"if" => {
+				if !self.check_arg_count(func_name, args, 3, span) {
+					return Unknown;
+				}
+
+				self.check_arg(func_name, "condition", Constant, &args[0]);
+				let then_ty = self.check(&args[1]);
+				let else_ty = self.check(&args[2]);
+				then_ty.join(else_ty)
+			}
+
+			"cond" => {
+				let mut result = None;
+
+				for clause in args {
+					let clause = match clause {
+						SExpression::List(clause, _) if clause.len() == 2 => clause,
+						_ => {
+							let message = "Expected (condition value) clause in 'cond'".into();
+							self.error(clause.span(), message);
+							continue;
+						}
+					};
+
+					match clause[0] {
+						SExpression::Identifier("else", _) => {}
+						_ => { self.check_arg(func_name, "condition", Constant, &clause[0]); }
+					}
+
+					let ty = self.check(&clause[1]);
+					result = Some(result.map_or(ty, |r: Type| r.join(ty)));
+				}
+
+				result.unwrap_or(Unknown)
+			}
+
+			"bake" => {
+				if args.len() < 2 {
+					let message = format!("'{}' function requires at least 2 arguments, {} received",
+						func_name, args.len());
+					self.error(span, message);
+					return Signal;
+				}
+
+				self.check_arg(func_name, "duration", Constant, &args[0]);
+
+				// Baked synths are evaluated in their own scope
+				let mut checker = Checker::new();
+
+				for sexpr in &args[1..] {
+					checker.check_top_level(sexpr);
+				}
+
+				self.diagnostics.extend(checker.diagnostics);
+				Signal
+			}
+
+			"polyphonic" => Unknown,
+
+			_ => self.check_builtin(func_name, args, span),
+		}
+	}
+
+	fn check_builtin(&mut self, func_name: &str, args: &[SExpression<'a>], span: Span) -> Type {
+		let builtin = match builtins::lookup(func_name) {
+			Some(builtin) => builtin,
+			None => {
+				match self.scope.get(func_name) {
+					Some(Function) | Some(Unknown) => {}
+					Some(ty) => {
+						let message = format!("'{}' is not a function, it's {}", func_name, ty);
+						self.error(span, message);
+					}
+					None => self.error(span, format!("Unknown function: '{}'", func_name)),
+				}
+
+				for arg in args {
+					self.check(arg);
+				}
+
+				return Unknown;
+			}
+		};
+
+		let sig = match builtin.signature(args.len()) {
+			Ok(sig) => sig,
+			Err(err) => {
+				self.error(span, err.to_string());
+
+				for arg in args {
+					self.check(arg);
+				}
+
+				return Unknown;
+			}
+		};
+
+		let mut all_constant = true;
+		let mut maybe_constant = true;
+
+		for (index, arg) in args.iter().enumerate() {
+			let param = sig.param(index).unwrap();
+
+			let ty = if sig.is_spliced(index) {
+				let ty = self.check_spliced(arg);
+
+				if ty != Array && !param.kind.accepts(ty) {
+					let message = format!("'{}' expects {} or an array for '{}', got {}",
+						builtin.name, param.kind, param.name, ty);
+					self.error(arg.span(), message);
+				}
+
+				ty
+			} else {
+				self.check_arg(builtin.name, param.name, param.kind, arg)
+			};
+
+			all_constant &= ty == Constant;
+			maybe_constant &= ty == Constant || ty == Unknown || ty == Array;
+		}
+
+		match builtin.fold {
+			Some(_) if all_constant => Constant,
+
+			// Arrays that can't be seen into without evaluating them could be all constants
+			Some(_) if maybe_constant => Unknown,
+
+			_ => sig.returns,
+		}
+	}
+
+	// Spliced arrays written out in place count as constant when all their elements are, and
+	// as signals when any is, since that's what the builtin folds or builds from
+	fn check_spliced(&mut self, arg: &SExpression<'a>) -> Type {
+		let elements = match arg {
+			SExpression::Array(elements, _) => elements,
+			_ => return self.check(arg),
+		};
+
+		let types = elements.iter()
+			.map(|element| self.check(element))
+			.collect::<Vec<_>>();
+
+		if types.iter().all(|&ty| ty == Constant) {
+			Constant
+		} else if types.iter().all(|&ty| Signal.accepts(ty) && ty != Unknown) {
+			Signal
+		} else {
+			Array
+		}
+	}
+
+	fn check_function(&mut self, params: &SExpression<'a>, body: &SExpression<'a>) {
+		let params = match params {
+			SExpression::List(params, _) => params,
+			_ => {
+				self.error(params.span(), "Expected parameter list".into());
+				return;
+			}
+		};
+
+		let outer_scope = self.scope.clone();
+
+		for param in params {
+			if let Some(ident) = self.expect_ident(param) {
+				self.scope.insert(ident, Unknown);
+			}
+		}
+
+		self.check(body);
+		self.scope = outer_scope;
+	}
+
+	fn check_arg_count(&mut self, func_name: &str, args: &[SExpression<'a>], count: usize, span: Span) -> bool {
+		if args.len() != count {
+			let message = format!("'{}' function requires {} arguments, {} received",
+				func_name, count, args.len());
+			self.error(span, message);
+			return false;
+		}
+
+		true
+	}
+
+	fn check_arg(&mut self, func_name: &str, param: &str, expected: Type, arg: &SExpression<'a>) -> Type {
+		let ty = self.check(arg);
+
+		if !expected.accepts(ty) {
+			let message = format!("'{}' expects {} for '{}', got {}", func_name, expected, param, ty);
+			self.error(arg.span(), message);
+		}
+
+		ty
+	}
+
+	fn expect_ident(&mut self, sexpr: &SExpression<'a>) -> Option<&'a str> {
+		match *sexpr {
+			SExpression::Identifier(ident, _) => Some(ident),
+			_ => {
+				self.error(sexpr.span(), "Expected identifier".into());
+				None
+			}
+		}
+	}
+}
+
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::lisp::parser::ExprReader;
+
+	// The source each diagnostic points at, with its message
+	fn diagnose(source: &str) -> Vec<(&str, String)> {
+		let top_level = ExprReader::new(source).parse_toplevel().unwrap();
+
+		check(&top_level).into_iter()
+			.map(|d| (&source[d.span.start..d.span.end], d.message))
+			.collect()
+	}
+
+	#[test]
+	fn mismatches_point_at_the_argument() {
+		let diagnostics = diagnose("(let f [1 2])\n(output (sin f))");
+		assert_eq!(diagnostics.len(), 1);
+		assert_eq!(diagnostics[0].0, "f");
+
+		let diagnostics = diagnose("(output (sin (repeat (sin 1) 'i)))");
+		assert_eq!(diagnostics.iter().map(|d| d.0).collect::<Vec<_>>(), ["(sin 1)", "'i", "(repeat (sin 1) 'i)"]);
+	}
+
+	#[test]
+	fn arity_errors_point_at_the_call() {
+		let diagnostics = diagnose("(output (+ 1 (clamp (sin 1) 0)))");
+		assert_eq!(diagnostics.len(), 1);
+		assert_eq!(diagnostics[0].0, "(clamp (sin 1) 0)");
+	}
+
+	#[test]
+	fn every_mismatch_is_reported() {
+		let diagnostics = diagnose("(output (sin [1]))\n(gain (sin 1))\n(output (unknown 1))");
+		assert_eq!(diagnostics.iter().map(|d| d.0).collect::<Vec<_>>(), ["[1]", "(sin 1)", "(unknown 1)"]);
+	}
+
+	#[test]
+	fn comments_dont_shift_spans() {
+		let source = "; (output (sin [1]))\n(output ; [2]\n  (sin [3]))";
+		let diagnostics = diagnose(source);
+
+		assert_eq!(diagnostics.len(), 1);
+		assert_eq!(diagnostics[0].0, "[3]");
+
+		let top_level = ExprReader::new(source).parse_toplevel().unwrap();
+		let span = check(&top_level)[0].span;
+		assert_eq!(span.location(source).to_string(), "3:8");
+	}
+
+	#[test]
+	fn spliced_constant_arrays_fold() {
+		for &(source, valid) in &[
+			("(gain (+ [1 2] 3))", true),
+			("(let a [1 2]) (gain (+ a 3))", true),
+			("(gain (+ [1 (sin 2)] 3))", false),
+		] {
+			assert_eq!(diagnose(source).is_empty(), valid, "{}", source);
+			assert_eq!(crate::lisp::compile(&format!("{} (output (sin 1))", source), 44100.0).is_ok(), valid, "{}", source);
+		}
+	}
+
+	#[test]
+	fn delay_lines_are_only_for_tapping() {
+		for &(source, valid) in &[
+			("(let line (delay-line 0.01 (sin 1))) (output (delay-tap line 0.005))", true),
+			("(let line (delay-line 0.01 (sin 1))) (output (mix line))", false),
+			("(output (delay-tap [1 2] 0))", false),
+		] {
+			assert_eq!(diagnose(source).is_empty(), valid, "{}", source);
+			assert_eq!(crate::lisp::compile(source, 44100.0).is_ok(), valid, "{}", source);
+		}
+	}
+
+	#[test]
+	fn checker_and_evaluator_agree_on_arity() {
+		for &(source, valid) in &[("(output (clamp (sin 1) 0 1))", true), ("(output (clamp (sin 1) 0))", false)] {
+			assert_eq!(diagnose(source).is_empty(), valid, "{}", source);
+			assert_eq!(crate::lisp::compile(source, 44100.0).is_ok(), valid, "{}", source);
+		}
+	}
+}
@@ -0,0 +1,140 @@
+//! Reads WAV files into buffers for playback
+
+use super::LispResult;
+use voi_synth::failure::{format_err, bail};
+
+use std::path::Path;
+use std::f32::consts::PI;
+
+// How many zero crossings of the sinc are used either side of each output sample
+const SINC_ZERO_CROSSINGS: f32 = 16.0;
+
+
+/// The samples of a WAV file, mixed down to mono
+pub struct Wav {
+	pub samples: Vec<f32>,
+	pub sample_rate: f32,
+}
+
+/// Reads an integer or 32 bit float WAV file. Files with more than one channel have
+/// their channels averaged
+pub fn read(path: &Path) -> LispResult<Wav> {
+	let reader = hound::WavReader::open(path)
+		.map_err(|err| format_err!("Couldn't open '{}': {}", path.display(), err))?;
+
+	let spec = reader.spec();
+
+	let interleaved = match (spec.sample_format, spec.bits_per_sample) {
+		(hound::SampleFormat::Float, 32) => reader.into_samples::<f32>()
+			.collect::<Result<Vec<_>, _>>(),
+
+		(hound::SampleFormat::Int, bits) if bits <= 32 => {
+			let scale = 1.0 / (1u64 << (bits - 1)) as f32;
+
+			reader.into_samples::<i32>()
+				.map(|s| s.map(|s| s as f32 * scale))
+				.collect::<Result<Vec<_>, _>>()
+		}
+
+		(format, bits) => bail!("'{}' is {} bit {:?}, which isn't supported", path.display(), bits, format),
+	};
+
+	let interleaved = interleaved
+		.map_err(|err| format_err!("Couldn't read '{}': {}", path.display(), err))?;
+
+	let channels = spec.channels as usize;
+	let samples = interleaved.chunks(channels)
+		.map(|frame| frame.iter().sum::<f32>() / channels as f32)
+		.collect();
+
+	Ok(Wav {samples, sample_rate: spec.sample_rate as f32})
+}
+
+
+/// Converts between sample rates with a windowed sinc, filtering out anything above
+/// the lower of the two nyquist frequencies
+pub fn resample(samples: &[f32], from_rate: f32, to_rate: f32) -> Vec<f32> {
+	if from_rate == to_rate {
+		return samples.to_vec()
+	}
+
+	let ratio = to_rate / from_rate;
+	let cutoff = ratio.min(1.0);
+
+	// In input samples
+	let radius = SINC_ZERO_CROSSINGS / cutoff;
+
+	let output_len = (samples.len() as f32 * ratio).ceil() as usize;
+
+	(0..output_len)
+		.map(|index| {
+			let position = index as f32 / ratio;
+			let first = (position - radius).ceil().max(0.0) as usize;
+			let last = ((position + radius).floor() as usize).min(samples.len().saturating_sub(1));
+
+			(first..=last)
+				.map(|i| {
+					let x = position - i as f32;
+					let window = 0.5 + 0.5 * (PI * x / radius).cos();
+					samples[i] * cutoff * sinc(cutoff * x) * window
+				})
+				.sum()
+		})
+		.collect()
+}
+
+fn sinc(x: f32) -> f32 {
+	if x.abs() < 1.0e-6 {
+		1.0
+	} else {
+		(PI * x).sin() / (PI * x)
+	}
+}
+
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn reads_stereo_24_bit_as_mono() {
+		let path = std::env::temp_dir().join("vst-lisp-wav-test.wav");
+
+		let spec = hound::WavSpec {
+			channels: 2,
+			sample_rate: 22050,
+			bits_per_sample: 24,
+			sample_format: hound::SampleFormat::Int,
+		};
+
+		let mut writer = hound::WavWriter::create(&path, spec).unwrap();
+		for &(left, right) in &[(4_194_304, 0), (-8_388_608, 0), (1000, -1000)] {
+			writer.write_sample(left).unwrap();
+			writer.write_sample(right).unwrap();
+		}
+		writer.finalize().unwrap();
+
+		let wav = read(&path).unwrap();
+		assert_eq!(wav.sample_rate, 22050.0);
+		assert_eq!(wav.samples, vec![0.25, -0.5, 0.0]);
+	}
+
+	#[test]
+	fn resampling_keeps_length_and_pitch() {
+		let sine = (0..4410)
+			.map(|i| (i as f32 * 2.0 * PI * 441.0 / 44100.0).sin())
+			.collect::<Vec<_>>();
+
+		for &rate in &[22050.0, 48000.0, 96000.0] {
+			let resampled = resample(&sine, 44100.0, rate);
+			assert_eq!(resampled.len(), (rate / 10.0) as usize);
+
+			// Away from the edges, it should be the same sine at the new rate
+			let middle = resampled.len() / 2;
+			for i in middle..middle + 100 {
+				let expected = (i as f32 * 2.0 * PI * 441.0 / rate).sin();
+				assert!((resampled[i] - expected).abs() < 0.01, "{} at {}hz: {} != {}", i, rate, resampled[i], expected);
+			}
+		}
+	}
+}
@@ -0,0 +1,169 @@
+//! Splits wavetables into cycles and finds the harmonics they're played back from
+
+use std::f64::consts::PI;
+
+// The frame size Serum and most other wavetable synths use
+pub const FRAME_SIZE: usize = 2048;
+
+const MAX_HARMONIC: usize = FRAME_SIZE / 2 - 1;
+
+
+/// Splits samples into single cycle frames. Anything shorter than a frame is one cycle,
+/// and a partial frame at the end is dropped
+pub fn split_frames(samples: &[f32]) -> Vec<Vec<f32>> {
+	if samples.len() <= FRAME_SIZE {
+		return vec![samples.to_vec()]
+	}
+
+	samples.chunks_exact(FRAME_SIZE)
+		.map(<[f32]>::to_vec)
+		.collect()
+}
+
+/// Resamples the harmonics of a table's frames to at most `count` frames, evenly spaced and
+/// including the first and last. Frames that fall between two others are crossfaded from
+/// them, which works on harmonics the same as on samples, even for cycles of different lengths
+pub fn resample_frames(frames: Vec<Vec<(f32, f32)>>, count: usize) -> Vec<Vec<(f32, f32)>> {
+	if frames.len() <= count {
+		return frames
+	}
+
+	let last = (frames.len() - 1) as f32;
+
+	(0..count)
+		.map(|i| i as f32 * last / (count - 1) as f32)
+		.map(|position| {
+			let before = (position.floor() as usize).min(frames.len() - 2);
+			let mix = position - before as f32;
+			let (a, b) = (&frames[before], &frames[before + 1]);
+			let harmonic = |frame: &[(f32, f32)], k| frame.get(k).cloned().unwrap_or((0.0, 0.0));
+
+			(0..a.len().max(b.len()))
+				.map(|k| {
+					let ((a_cosine, a_sine), (b_cosine, b_sine)) = (harmonic(a, k), harmonic(b, k));
+					(a_cosine + (b_cosine - a_cosine) * mix, a_sine + (b_sine - a_sine) * mix)
+				})
+				.collect()
+		})
+		.collect()
+}
+
+/// The DC offset of a cycle, followed by the cosine and sine amplitudes of each of its
+/// harmonics, up to half its length or the highest a frame can hold
+pub fn harmonics(frame: &[f32]) -> Vec<(f32, f32)> {
+	let len = frame.len();
+	if len == 0 {
+		return Vec::new()
+	}
+
+	let count = (len / 2).min(MAX_HARMONIC);
+
+	let bins = if len.is_power_of_two() {
+		let mut bins = frame.iter().map(|&x| (x as f64, 0.0)).collect::<Vec<_>>();
+		fft(&mut bins, -1.0);
+		bins
+	} else {
+		(0..=count)
+			.map(|k| frame.iter().enumerate().fold((0.0, 0.0), |(re, im), (n, &x)| {
+				let phase = 2.0 * PI * (k * n % len) as f64 / len as f64;
+				(re + x as f64 * phase.cos(), im - x as f64 * phase.sin())
+			}))
+			.collect()
+	};
+
+	(0..=count)
+		.map(|k| {
+			// DC and the cycle's nyquist don't have a mirror image to share with
+			let scale = if k == 0 || 2 * k == len { 1.0 } else { 2.0 } / len as f64;
+			((bins[k].0 * scale) as f32, (-bins[k].1 * scale) as f32)
+		})
+		.collect()
+}
+
+// A radix 2 FFT, in place and unnormalised. The sign of the exponent is `sign`
+fn fft(bins: &mut [(f64, f64)], sign: f64) {
+	let len = bins.len();
+	if len < 2 {
+		return
+	}
+
+	let bits = len.trailing_zeros();
+	for i in 0..len {
+		let j = i.reverse_bits() >> (usize::BITS - bits);
+		if i < j {
+			bins.swap(i, j);
+		}
+	}
+
+	let mut size = 2;
+	while size <= len {
+		let half = size / 2;
+
+		for start in (0..len).step_by(size) {
+			for k in 0..half {
+				let (sin, cos) = (sign * 2.0 * PI * k as f64 / size as f64).sin_cos();
+				let (even, (re, im)) = (bins[start + k], bins[start + k + half]);
+				let odd = (re * cos - im * sin, re * sin + im * cos);
+
+				bins[start + k] = (even.0 + odd.0, even.1 + odd.1);
+				bins[start + k + half] = (even.0 - odd.0, even.1 - odd.1);
+			}
+		}
+
+		size *= 2;
+	}
+}
+
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn saw(len: usize) -> Vec<f32> {
+		(0..len).map(|n| 1.0 - 2.0 * n as f32 / len as f32).collect()
+	}
+
+	#[test]
+	fn harmonics_of_a_saw() {
+		// Power of two lengths go through the FFT, and others don't
+		for &len in &[256, 250] {
+			let harmonics = harmonics(&saw(len));
+			assert_eq!(harmonics.len(), len / 2 + 1);
+
+			for (k, &(cosine, sine)) in harmonics.iter().enumerate().skip(1).take(8) {
+				let expected = 2.0 / (std::f32::consts::PI * k as f32);
+				assert!((sine - expected).abs() < 0.01, "harmonic {} was {}, expected {}", k, sine, expected);
+				assert!(cosine.abs() < 0.01, "harmonic {} has a cosine of {}", k, cosine);
+			}
+		}
+	}
+
+	#[test]
+	fn harmonics_of_a_short_cycle() {
+		// A square four samples long is a sine and cosine at the fundamental, with nothing
+		// left over at its nyquist
+		let harmonics = harmonics(&[1.0, 1.0, -1.0, -1.0]);
+		let expected = [(0.0, 0.0), (1.0, 1.0), (0.0, 0.0)];
+		assert_eq!(harmonics.len(), expected.len());
+
+		for (k, (&(cosine, sine), &(expected_cosine, expected_sine))) in harmonics.iter().zip(&expected).enumerate() {
+			assert!((cosine - expected_cosine).abs() < 1.0e-5 && (sine - expected_sine).abs() < 1.0e-5,
+				"harmonic {} was {}, {}", k, cosine, sine);
+		}
+	}
+
+	#[test]
+	fn frames() {
+		assert_eq!(split_frames(&[0.0; 100]).len(), 1);
+		assert_eq!(split_frames(&[0.0; FRAME_SIZE * 3 + 5]).len(), 3);
+
+		let frames = (0..10).map(|i| vec![(i as f32, 0.0)]).collect();
+		let picked = resample_frames(frames, 4);
+		assert_eq!(picked, vec![vec![(0.0, 0.0)], vec![(3.0, 0.0)], vec![(6.0, 0.0)], vec![(9.0, 0.0)]]);
+
+		// Halfway between two frames, with the shorter padded out to the longer
+		let frames = vec![vec![(0.0, 0.0)], vec![(1.0, 2.0), (4.0, 0.0)], vec![(2.0, 0.0)], vec![(3.0, 0.0)]];
+		let resampled = resample_frames(frames, 3);
+		assert_eq!(resampled[1], vec![(1.5, 1.0), (2.0, 0.0)]);
+	}
+}
@@ -3,22 +3,26 @@ use voi_synth::{Synth, SynthID, ParameterID, Context as SynthContext};
 use crate::VstResult;
 use crate::voice_allocator::VoiceAllocator;
 
+use std::path::PathBuf;
+
 pub struct Model {
     pub synth_id: SynthID,
     pub voice_allocator: VoiceAllocator,
 
     pub source: String,
+    pub dir: PathBuf,
 }
 
 impl Model {
-    pub fn from_string(synth_ctx: &mut SynthContext, src: String) -> VstResult<Model> {
-        let (synth_id, synth_info) = crate::lisp::create_synth(synth_ctx, &src)?;
+    pub fn from_string(synth_ctx: &mut SynthContext, src: String, dir: PathBuf) -> VstResult<Model> {
+        let (synth_id, synth_info) = crate::lisp::create_synth(synth_ctx, &src, &dir)?;
 
         Ok(Model{
             synth_id,
             voice_allocator: VoiceAllocator::new(synth_info.key_input),
 
             source: src,
+            dir,
         })
     }
 }
\ No newline at end of file
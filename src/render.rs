@@ -0,0 +1,258 @@
+//! Rendering patches offline, without a host
+
+use voi_synth::Context as SynthContext;
+use voi_synth::failure::{bail, ensure, format_err};
+
+use crate::VstResult;
+use crate::lisp::{self, ir::Graph};
+use crate::voice_allocator::VoiceAllocator;
+
+use std::path::Path;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum NoteEventKind {
+    On { key: u8, velocity: f32 },
+    Off { key: u8 },
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct NoteEvent {
+    // In seconds from the start of the render
+    pub time: f32,
+    pub kind: NoteEventKind,
+}
+
+pub struct RenderOptions {
+    pub sample_rate: f32,
+    pub block_size: usize,
+
+    // In seconds
+    pub length: f32,
+}
+
+impl Default for RenderOptions {
+    fn default() -> Self {
+        RenderOptions {
+            sample_rate: 44100.0,
+            block_size: 64,
+            length: 1.0,
+        }
+    }
+}
+
+
+/// Renders a patch to mono samples, playing `events` through a voice allocator.
+/// Events are applied at block boundaries, so their timing is quantised to the block size
+pub fn render(patch: &str, events: &[NoteEvent], options: &RenderOptions) -> VstResult<Vec<f32>> {
+    let graph = lisp::compile(patch, options.sample_rate)?;
+    render_graph(&graph, events, options)
+}
+
+/// Renders an already compiled patch, like `render`
+pub fn render_graph(graph: &Graph, events: &[NoteEvent], options: &RenderOptions) -> VstResult<Vec<f32>> {
+    ensure!(options.block_size > 0, "Block size must be greater than zero");
+    ensure!(options.sample_rate > 0.0 && options.sample_rate.is_finite(), "Sample rate must be greater than zero");
+    ensure!(options.length > 0.0 && options.length.is_finite(), "Length must be a positive number of seconds, not {}", options.length);
+
+    if let Some(event) = events.iter().find(|e| !(e.time >= 0.0 && e.time.is_finite())) {
+        bail!("Event times must be a finite number of seconds from the start, not {}", event.time);
+    }
+
+    let mut synth_ctx = SynthContext::new(1, options.block_size)?;
+    synth_ctx.set_sample_rate(options.sample_rate);
+    synth_ctx.set_buffer_size(options.block_size);
+
+    let (synth_id, synth_info) = lisp::push_graph(&mut synth_ctx, graph)?;
+    let mut voice_allocator = VoiceAllocator::new(synth_info.key_input);
+
+    let mut events = events.to_vec();
+    events.sort_by(|a, b| a.time.partial_cmp(&b.time).unwrap());
+    let mut events = events.into_iter().peekable();
+
+    let length = (options.length * options.sample_rate).ceil() as usize;
+    let mut output = Vec::with_capacity(length + options.block_size);
+
+    while output.len() < length {
+        let block_end = (output.len() + options.block_size) as f32 / options.sample_rate;
+
+        while let Some(event) = events.peek().cloned() {
+            if event.time >= block_end {
+                break
+            }
+
+            match event.kind {
+                NoteEventKind::On { key, velocity } => voice_allocator.note_on(&mut synth_ctx, key, velocity),
+                NoteEventKind::Off { key } => voice_allocator.note_off(&mut synth_ctx, key),
+            }
+
+            events.next();
+        }
+
+        let buf = synth_ctx.get_ready_buffer()?;
+        output.extend_from_slice(&buf.data);
+        synth_ctx.queue_empty_buffer(buf)?;
+    }
+
+    synth_ctx.remove_synth(synth_id);
+
+    output.truncate(length);
+    Ok(output)
+}
+
+
+/// Writes mono samples to a WAV file. 32 bit files are floating point, anything else
+/// is integer and clipped
+pub fn write_wav(path: &Path, samples: &[f32], sample_rate: u32, bits: u16) -> VstResult<()> {
+    ensure!(bits == 16 || bits == 24 || bits == 32, "Unsupported bit depth {}, expected 16, 24 or 32", bits);
+
+    let spec = hound::WavSpec {
+        channels: 1,
+        sample_rate,
+        bits_per_sample: bits,
+        sample_format: if bits == 32 { hound::SampleFormat::Float } else { hound::SampleFormat::Int },
+    };
+
+    let mut writer = hound::WavWriter::create(path, spec)?;
+
+    for &sample in samples {
+        match bits {
+            16 => writer.write_sample((sample.max(-1.0).min(1.0) * i16::max_value() as f32) as i16)?,
+            24 => writer.write_sample((sample.max(-1.0).min(1.0) * 8_388_607.0) as i32)?,
+            _ => writer.write_sample(sample)?,
+        }
+    }
+
+    writer.finalize()?;
+    Ok(())
+}
+
+
+/// Parses a list of note events, one per line, like:
+///
+/// ```text
+/// # time  event  key  velocity
+/// 0.0     on     60   100
+/// 0.5     off    60
+/// ```
+///
+/// Times are in seconds, and velocities go from 0 to 127, defaulting to 100
+pub fn parse_event_list(source: &str) -> VstResult<Vec<NoteEvent>> {
+    let mut events = Vec::new();
+
+    for (line_number, line) in source.lines().enumerate() {
+        let line = line.split('#').next().unwrap().trim();
+        if line.is_empty() {
+            continue
+        }
+
+        let parse_event = || -> VstResult<NoteEvent> {
+            let words = line.split_whitespace().collect::<Vec<_>>();
+            ensure!(words.len() >= 3, "Expected a time, an event and a key");
+
+            let time = words[0].parse::<f32>()
+                .map_err(|_| format_err!("Invalid time '{}'", words[0]))?;
+
+            let key = words[2].parse::<u8>()
+                .map_err(|_| format_err!("Invalid key '{}'", words[2]))?;
+
+            ensure!(time.is_finite(), "Invalid time '{}'", words[0]);
+            ensure!(time >= 0.0, "Events can't happen before the start of the render");
+            ensure!(key < 128, "Key {} is out of range", key);
+
+            let kind = match words[1] {
+                "on" => {
+                    let velocity = match words.get(3) {
+                        Some(v) => v.parse::<u8>().map_err(|_| format_err!("Invalid velocity '{}'", v))?,
+                        None => 100,
+                    };
+
+                    ensure!(words.len() <= 4, "Too many fields");
+                    ensure!(velocity < 128, "Velocity {} is out of range", velocity);
+
+                    NoteEventKind::On { key, velocity: velocity as f32 / 127.0 }
+                }
+
+                "off" => {
+                    ensure!(words.len() == 3, "Too many fields");
+                    NoteEventKind::Off { key }
+                }
+
+                other => bail!("Unknown event '{}', expected 'on' or 'off'", other),
+            };
+
+            Ok(NoteEvent { time, kind })
+        };
+
+        let event = parse_event()
+            .map_err(|e| format_err!("line {}: {}", line_number + 1, e))?;
+
+        events.push(event);
+    }
+
+    Ok(events)
+}
+
+
+/// Reads the note events from every track and channel of a Standard MIDI File
+pub fn read_midi_file(data: &[u8]) -> VstResult<Vec<NoteEvent>> {
+    use midly::{Smf, Timing, TrackEventKind, MidiMessage, MetaMessage};
+
+    let smf = Smf::parse(data)
+        .map_err(|e| format_err!("Couldn't parse MIDI file: {}", e))?;
+
+    // Tempo changes in any track apply to all of them, so merge everything into one
+    // timeline before converting ticks to seconds
+    let mut timeline = Vec::new();
+
+    for track in smf.tracks.iter() {
+        let mut tick = 0u64;
+
+        for event in track.iter() {
+            tick += event.delta.as_int() as u64;
+            timeline.push((tick, event.kind));
+        }
+    }
+
+    timeline.sort_by_key(|&(tick, _)| tick);
+
+    let mut events = Vec::new();
+    let mut seconds_per_tick = match smf.header.timing {
+        // Until told otherwise, tempo is 120bpm
+        Timing::Metrical(ticks_per_beat) => 0.5 / ticks_per_beat.as_int() as f64,
+        Timing::Timecode(fps, subframes) => 1.0 / (fps.as_f32() as f64 * subframes as f64),
+    };
+
+    let mut time = 0.0f64;
+    let mut last_tick = 0;
+
+    for (tick, kind) in timeline {
+        time += (tick - last_tick) as f64 * seconds_per_tick;
+        last_tick = tick;
+
+        match kind {
+            TrackEventKind::Meta(MetaMessage::Tempo(micros_per_beat)) => {
+                if let Timing::Metrical(ticks_per_beat) = smf.header.timing {
+                    seconds_per_tick = micros_per_beat.as_int() as f64 / 1_000_000.0 / ticks_per_beat.as_int() as f64;
+                }
+            }
+
+            TrackEventKind::Midi { message, .. } => {
+                let kind = match message {
+                    MidiMessage::NoteOn { key, vel } if vel.as_int() > 0 =>
+                        NoteEventKind::On { key: key.as_int(), velocity: vel.as_int() as f32 / 127.0 },
+
+                    MidiMessage::NoteOn { key, .. } | MidiMessage::NoteOff { key, .. } =>
+                        NoteEventKind::Off { key: key.as_int() },
+
+                    _ => continue,
+                };
+
+                events.push(NoteEvent { time: time as f32, kind });
+            }
+
+            _ => {}
+        }
+    }
+
+    Ok(events)
+}
@@ -80,8 +80,9 @@ impl Window {
                     self.file_hovered = true;
                 }
                 Event::WindowEvent{ event: WindowEvent::DroppedFile(filename), .. } => {
-                    if let Ok(src) = std::fs::read_to_string(filename) {
-                        self.command_tx.send(AudioCommand::SetModel(src)).unwrap();
+                    if let Ok(src) = std::fs::read_to_string(&filename) {
+                        let dir = filename.parent().map(PathBuf::from).unwrap_or_default();
+                        self.command_tx.send(AudioCommand::SetModel(src, dir)).unwrap();
                     }
 
                     self.file_hovered = false;
@@ -158,7 +159,7 @@ impl Window {
 
         if load_default_button.was_clicked() {
             let source = include_str!("../assets/default.lisp").into();
-            self.command_tx.send(AudioCommand::SetModel(source)).unwrap();
+            self.command_tx.send(AudioCommand::SetModel(source, PathBuf::new())).unwrap();
         }
 
         let load_sound_test_button = Button::new()
@@ -168,7 +169,7 @@ impl Window {
 
         if load_sound_test_button.was_clicked() {
             let source = include_str!("../assets/sound_test.lisp").into();
-            self.command_tx.send(AudioCommand::SetModel(source)).unwrap();
+            self.command_tx.send(AudioCommand::SetModel(source, PathBuf::new())).unwrap();
         }
 
         if self.file_hovered {
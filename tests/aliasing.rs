@@ -0,0 +1,62 @@
+//! Checks that the band limited oscillators alias much less than the naive ones
+
+mod common;
+
+use common::{SAMPLE_RATE, render_settled, runtime_value, bin_energy, node_count};
+
+// A whole number of cycles fits in the analysed window, so every harmonic lands exactly
+// on a bin, and anything between them is aliasing
+const FREQ: f32 = 1230.0;
+const WINDOW: usize = 4410;
+
+/// Energy in the top half of the spectrum that isn't at a harmonic of FREQ
+fn alias_energy(patch: &str) -> f64 {
+    let window = render_settled(patch, WINDOW);
+    let bin_width = SAMPLE_RATE / WINDOW as f32;
+
+    (WINDOW / 4..WINDOW / 2)
+        .filter(|&bin| {
+            let harmonic = bin as f32 * bin_width / FREQ;
+            (harmonic - harmonic.round()).abs() > 0.01
+        })
+        .map(|bin| bin_energy(&window, bin))
+        .sum()
+}
+
+fn assert_less_aliasing(naive: &str, band_limited: &str) {
+    let naive_energy = alias_energy(naive);
+    let band_limited_energy = alias_energy(band_limited);
+
+    assert!(naive_energy > 0.0, "{} didn't alias at all", naive);
+
+    // At least 10dB less
+    assert!(band_limited_energy * 10.0 < naive_energy,
+        "{} has alias energy {}, compared to {} for {}", band_limited, band_limited_energy, naive_energy, naive);
+}
+
+#[test]
+fn saw_bl_aliases_less_than_saw() {
+    assert_less_aliasing(&format!("(output (saw {}))", FREQ), &format!("(output (saw-bl {}))", FREQ));
+}
+
+#[test]
+fn sqr_bl_aliases_less_than_sqr() {
+    assert_less_aliasing(&format!("(output (sqr {}))", FREQ), &format!("(output (sqr-bl {}))", FREQ));
+}
+
+#[test]
+fn sqr_bl_tracks_signal_frequencies() {
+    let band_limited = format!("{} (output (sqr-bl freq 0.3))", runtime_value("freq", FREQ));
+    assert_less_aliasing(&format!("(output (sqr {}))", FREQ), &band_limited);
+}
+
+#[test]
+fn band_limited_oscillators_stay_cheap() {
+    // Every voice pays for these, so their documented costs are upper bounds
+    let signal = runtime_value("freq", FREQ);
+
+    assert!(node_count("(output (saw-bl 1230))") <= 15);
+    assert!(node_count("(output (sqr-bl 1230 0.3))") <= 29);
+    assert!(node_count(&format!("{} (output (saw-bl freq))", signal)) <= 1 + 15 + 9);
+    assert!(node_count(&format!("{} (output (sqr-bl freq))", signal)) <= 1 + 29 + 9);
+}
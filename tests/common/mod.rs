@@ -0,0 +1,72 @@
+//! Rendering and measuring helpers shared by the integration tests
+
+// Each test crate only uses some of these
+#![allow(dead_code)]
+
+use vstlisp::lisp;
+
+use std::f64::consts::PI;
+
+pub const SAMPLE_RATE: f32 = 44100.0;
+
+pub fn render(patch: &str, samples: usize) -> Vec<f32> {
+    lisp::evaluate_to_buffer(patch, SAMPLE_RATE, samples).unwrap()
+}
+
+/// Renders twice `window` samples and keeps the second half, after oscillators, filters
+/// and delays have settled
+pub fn render_settled(patch: &str, window: usize) -> Vec<f32> {
+    render(patch, 2 * window)[window..].to_vec()
+}
+
+/// Definitions that hold `value` in a store called `name`, so that it can't be folded
+/// and the patch has to treat it as a signal
+pub fn runtime_value(name: &str, value: f32) -> String {
+    format!("(def-store {0}) (store {0} {1})", name, value)
+}
+
+pub fn peak(samples: &[f32]) -> f32 {
+    samples.iter().fold(0.0, |peak, x| peak.max(x.abs()))
+}
+
+pub fn energy(samples: &[f32]) -> f32 {
+    samples.iter().map(|x| x * x).sum()
+}
+
+pub fn max_difference(a: &[f32], b: &[f32]) -> f32 {
+    a.iter().zip(b).fold(0.0, |max, (a, b)| max.max((a - b).abs()))
+}
+
+/// The energy in one bin of the samples' discrete Fourier transform
+pub fn bin_energy(samples: &[f32], bin: usize) -> f64 {
+    let (mut re, mut im) = (0.0, 0.0);
+
+    for (i, &x) in samples.iter().enumerate() {
+        let phase = 2.0 * PI * ((bin * i) % samples.len()) as f64 / samples.len() as f64;
+        re += x as f64 * phase.cos();
+        im -= x as f64 * phase.sin();
+    }
+
+    re * re + im * im
+}
+
+/// The amplitude of the sine at freq, which has to fit a whole number of cycles in the samples
+pub fn amplitude(samples: &[f32], freq: f32) -> f64 {
+    let bin = (freq * samples.len() as f32 / SAMPLE_RATE).round() as usize;
+    2.0 * bin_energy(samples, bin).sqrt() / samples.len() as f64
+}
+
+/// Checks that a stereo effect's sides aren't copies of each other. Arrays are spliced
+/// into '+', so adding up the sides gives twice the mono effect if they are
+pub fn assert_stereo_sides_differ(stereo: &str, mono: &str, samples: usize) {
+    let both = render(&format!("(output (+ 0 {}))", stereo), samples);
+    let doubled = render(&format!("(output (* 2 {}))", mono), samples);
+
+    assert!(peak(&both) > 0.0, "{} is silent", stereo);
+    assert_ne!(both, doubled, "{}'s sides are the same", stereo);
+}
+
+/// How many nodes the patch compiles to, after optimisation
+pub fn node_count(patch: &str) -> usize {
+    lisp::compile(patch, SAMPLE_RATE).unwrap().nodes.len()
+}
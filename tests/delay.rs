@@ -0,0 +1,86 @@
+//! Checks that delay lines delay by the right amount, whether or not their time is known
+
+mod common;
+
+use vstlisp::lisp;
+
+use common::{SAMPLE_RATE, render, runtime_value, peak, max_difference, node_count};
+
+#[test]
+fn whole_sample_delays_shift_the_input() {
+    let dry = render("(output (sin 100))", 2000);
+    let wet = render("(output (delay 0.01 0 (sin 100)))", 2000);
+
+    assert!(wet[..441].iter().all(|&x| x == 0.0));
+    assert_eq!(max_difference(&wet[441..], &dry), 0.0);
+}
+
+#[test]
+fn fractional_delays_interpolate() {
+    // 4.41 samples
+    let dry = render("(output (sin 100))", 100);
+    let expected = (5..100).map(|i| dry[i - 4] * 0.59 + dry[i - 5] * 0.41).collect::<Vec<_>>();
+
+    let constant = render("(output (delay 0.0001 0 (sin 100)))", 100);
+    assert!(max_difference(&constant[5..], &expected) < 1.0e-5);
+
+    let modulated = render(&format!("{} (output (delay time 0 (sin 100) 0.001))", runtime_value("time", 0.0001)), 100);
+    assert!(max_difference(&modulated[5..], &expected) < 1.0e-5);
+}
+
+#[test]
+fn feedback_repeats_the_delayed_signal() {
+    // The delay is exactly one cycle, so each repeat lines up with the input
+    let wet = render("(output (delay 0.01 0.5 (sin 100)))", 44100);
+    let peak = peak(&wet[40000..]);
+
+    assert!((peak - 2.0).abs() < 0.01, "feedback built up to {}, expected 2", peak);
+}
+
+#[test]
+fn taps_read_the_same_as_delays() {
+    let tapped = render("(let line (delay-line 0.01 (sin 100))) (output (- (delay-tap line 0.005) (delay 0.005 0 (sin 100))))", 2000);
+    assert!(tapped.iter().all(|&x| x == 0.0));
+
+    let undelayed = render("(let line (delay-line 0.01 (sin 100))) (output (- (delay-tap line 0) (sin 100)))", 200);
+    assert!(undelayed.iter().all(|&x| x == 0.0));
+}
+
+#[test]
+fn delays_are_limited() {
+    assert!(lisp::compile("(output (delay 2 0 (sin 100)))", SAMPLE_RATE).is_err());
+    assert!(lisp::compile("(output (delay (sin 1) 0 (sin 100)))", SAMPLE_RATE).is_err());
+    assert!(lisp::compile("(output (delay 0.03 0 (sin 100)))", SAMPLE_RATE).is_err());
+    assert!(lisp::compile("(output (mix (repeat 5 (fn (i) (delay 0.01 0 (sin 100))))))", SAMPLE_RATE).is_err());
+
+    assert!(lisp::compile("(output (delay 0.015 0 (sin 100) 0.01))", SAMPLE_RATE).is_err());
+    assert!(lisp::compile("(let line (delay-line 0.01 (sin 100))) (output (delay-tap line 0.02))", SAMPLE_RATE).is_err());
+}
+
+#[test]
+fn delays_cost_a_node_a_sample() {
+    // The longest delay a patch can have, in every voice
+    let count = node_count("(output (delay 0.02 0.5 (sin 100)))");
+    assert!(count <= 882 + 10, "a 20ms delay costs {} nodes", count);
+}
+
+#[test]
+fn feedback_dies_away() {
+    let wet = render("(output (delay 0.01 2 (sin 100)))", 44100);
+    let peak = peak(&wet);
+
+    assert!(peak.is_finite() && peak <= 100.0, "feedback built up to {}", peak);
+}
+
+#[test]
+fn modulated_reads_only_interpolate_near_the_end() {
+    // A line 20ms long, but only its last 2ms are tapped
+    let time = runtime_value("time", 0.019);
+    let fixed = node_count("(output (delay 0.02 0 (sin 100)))");
+    let modulated = node_count(&format!("{} (output (delay time 0 (sin 100) 0.02))", time));
+    assert!(modulated - fixed <= 5 * 90 + 10, "modulating the delay took {} more nodes", modulated - fixed);
+
+    let fixed = node_count("(let line (delay-line 0.02 (sin 100))) (output (delay-tap line 0.02))");
+    let modulated = node_count(&format!("{} (let line (delay-line 0.02 (sin 100))) (output (delay-tap line time))", time));
+    assert!(modulated - fixed <= 5 * 90 + 10, "modulating the tap took {} more nodes", modulated - fixed);
+}
@@ -0,0 +1,95 @@
+//! Checks the waveshapers' curves, and that crushing and decimating step the input
+
+mod common;
+
+use common::{amplitude, render, render_settled, runtime_value, node_count};
+
+/// The shaper's output for a steady input, once its oversampling filters have settled
+fn shaped(shaper: &str, input: f32) -> f32 {
+    let patch = format!("{} (output ({} x))", runtime_value("x", input), shaper);
+    *render(&patch, 1000).last().unwrap()
+}
+
+#[test]
+fn waveshapers_follow_their_curves() {
+    let curves: [(&str, fn(f32) -> f32); 5] = [
+        ("tanh", f32::tanh),
+        ("softclip 2", |x| { let c = (2.0 * x).max(-1.5).min(1.5); c - 4.0 * c.powi(3) / 27.0 }),
+        ("fold", |x| { let phase = (x + 1.0).rem_euclid(4.0); if phase < 2.0 { phase - 1.0 } else { 3.0 - phase } }),
+        ("crush 3", |x| ((x.max(-1.0).min(1.0) + 1.0) * 3.5 + 0.5).floor() / 3.5 - 1.0),
+        ("shaper [1 0 1]", |x| x.abs().min(1.0)),
+    ];
+
+    for &(shaper, curve) in &curves {
+        for &input in &[-10.0, -2.5, -0.7, 0.0, 0.3, 1.2, 3.0, 6.4] {
+            let output = shaped(shaper, input);
+            let expected = curve(input);
+            assert!((output - expected).abs() < 2.0e-3, "({} {}) is {}, expected {}", shaper, input, output, expected);
+        }
+    }
+}
+
+#[test]
+fn constants_fold_to_the_same_curve() {
+    for &input in &[-10.0, -2.5, -0.7, 0.3, 1.2, 3.0] {
+        // Scaled by a runtime one, since output wants a signal rather than the folded constant
+        let patch = format!("{} (output (* one (tanh {})))", runtime_value("one", 1.0), input);
+        let folded = *render(&patch, 4).last().unwrap();
+        let shaped = shaped("tanh", input);
+        assert!((folded - shaped).abs() < 1.0e-5, "(tanh {}) folds to {}, but the nodes give {}", input, folded, shaped);
+    }
+}
+
+#[test]
+fn crush_rounds_to_steps() {
+    let mut levels = render("(output (crush 2 (sin 100)))", 441);
+    levels.sort_by(|a, b| a.partial_cmp(b).unwrap());
+    levels.dedup();
+
+    let expected = [-1.0, -1.0 / 3.0, 1.0 / 3.0, 1.0];
+    assert_eq!(levels.len(), expected.len(), "crushed to {:?}", levels);
+    for (level, expected) in levels.iter().zip(&expected) {
+        assert!((level - expected).abs() < 1.0e-6, "crushed to {:?}", levels);
+    }
+}
+
+#[test]
+fn decimate_holds_between_samples() {
+    let mut held = render("(output (decimate 1000 (saw 110)))", 44100);
+    held.dedup();
+
+    assert!((held.len() as i32 - 1000).abs() <= 2, "decimate took {} samples in a second, expected 1000", held.len());
+}
+
+#[test]
+fn shapers_stay_cheap() {
+    // Oversampling takes 82 nodes, and then the shape runs twice
+    assert!(node_count("(output (tanh (sin 100)))") <= 1 + 82 + 2 * 26);
+    assert!(node_count("(output (fold (sin 100)))") <= 1 + 82 + 2 * 7);
+    assert!(node_count("(output (crush 8 (sin 100)))") <= 1 + 7);
+}
+
+#[test]
+fn oversampling_passes_the_audio_band() {
+    // Quiet enough that softclip's cubic is about 1e-5 of the output, so it's the filters' gain
+    for &freq in &[100.0, 5000.0, 15000.0, 19000.0] {
+        let samples = render_settled(&format!("(output (softclip 1 (* 0.01 (sin {}))))", freq), 4410);
+        let gain = amplitude(&samples, freq) / 0.01;
+        assert!((gain - 1.0).abs() < 1.0e-3, "{}hz comes out with a gain of {}", freq, gain);
+    }
+}
+
+#[test]
+fn oversampling_stops_aliases() {
+    // Below its clip, softclip 2 is 2x - 32x^3/27, so a 9khz sine gets a third harmonic at
+    // 27khz, which would fold back to 17.1khz at the plain sample rate
+    let samples = render_settled("(output (softclip 2 (* 0.25 (sin 9000))))", 4410);
+
+    let fundamental = 0.5 - 0.75 * 32.0 / 27.0 * 0.25f64.powi(3);
+    let third = 0.25 * 32.0 / 27.0 * 0.25f64.powi(3);
+    assert!((amplitude(&samples, 9000.0) - fundamental).abs() < 1.0e-3 * fundamental);
+
+    // At least 60dB under the harmonic it comes from
+    let alias = amplitude(&samples, 17100.0);
+    assert!(alias < 1.0e-3 * third, "the alias at 17.1khz has amplitude {}, against {} for the harmonic", alias, third);
+}
@@ -0,0 +1,65 @@
+//! Checks the resonant filters' responses, and that they stay stable under modulation
+
+mod common;
+
+use common::{render, render_settled, peak};
+
+const WINDOW: usize = 4410;
+
+/// How much a filter scales a sine at freq. The sine is quiet enough not to saturate anything
+fn gain(filter: &str, freq: f32) -> f32 {
+    let samples = render_settled(&format!("(output ({} (* 0.1 (sin {}))))", filter, freq), WINDOW);
+    let rms = (samples.iter().map(|x| x * x).sum::<f32>() / samples.len() as f32).sqrt();
+    rms * 2.0f32.sqrt() / 0.1
+}
+
+fn assert_gains(filter: &str, expected: &[(f32, f32)]) {
+    for &(freq, expected) in expected {
+        let gain = gain(filter, freq);
+        assert!((gain - expected).abs() < 0.05, "{} has gain {} at {}hz, expected {}", filter, gain, freq, expected);
+    }
+}
+
+#[test]
+fn svf_modes() {
+    // With a q of 2, the peak at the cutoff is twice the input
+    assert_gains("svf 'lp 1000 2", &[(100.0, 1.0), (1000.0, 2.0), (10000.0, 0.0)]);
+    assert_gains("svf 'hp 1000 2", &[(100.0, 0.0), (1000.0, 2.0), (10000.0, 1.0)]);
+    assert_gains("svf 'bp 1000 2", &[(100.0, 0.05), (1000.0, 1.0), (10000.0, 0.04)]);
+    assert_gains("svf 'notch 1000 2", &[(100.0, 1.0), (1000.0, 0.0), (10000.0, 1.0)]);
+    assert_gains("svf 'peak 1000 2", &[(100.0, 1.0), (1000.0, 4.0), (10000.0, 1.0)]);
+}
+
+#[test]
+fn ladder_rolls_off_at_24db_per_octave() {
+    // Each of the four poles is 3dB down at the cutoff
+    assert_gains("ladder 1000 0 1", &[(100.0, 1.0), (1000.0, 0.25), (8000.0, 0.0)]);
+}
+
+#[test]
+fn ladder_self_oscillates_at_any_cutoff() {
+    // Faint noise, and long enough for the oscillation to build up from it
+    let tail = |patch: String| render(&patch, 10 * WINDOW)[9 * WINDOW..].to_vec();
+
+    for &cutoff in &[200.0, 1000.0, 5000.0] {
+        let quiet = tail(format!("(output (ladder {} 0.85 1 (* 0.001 (noise 'white 1))))", cutoff));
+        let ringing = tail(format!("(output (ladder {} 1 1 (* 0.001 (noise 'white 1))))", cutoff));
+
+        assert!(peak(&quiet) < 0.01, "ladder at {}hz rings with 0.85 resonance", cutoff);
+        assert!(peak(&ringing) > 0.1, "ladder at {}hz doesn't ring with full resonance", cutoff);
+    }
+}
+
+#[test]
+fn filters_stay_stable_when_swept_at_audio_rate() {
+    let patches = [
+        "(output (svf 'lp (+ 10000 (* 10000 (sqr 3000))) 100 (saw 110)))",
+        "(output (svf 'bp (+ 10000 (* 10000 (sin 5000))) (+ 50 (* 49.5 (sqr 700))) (noise 'white)))",
+        "(output (ladder (+ 10000 (* 10000 (sqr 3000))) 1 10 (saw 110)))",
+    ];
+
+    for patch in &patches {
+        let samples = render_settled(patch, WINDOW);
+        assert!(samples.iter().all(|x| x.is_finite()) && peak(&samples) < 10.0, "{} blew up", patch);
+    }
+}
@@ -0,0 +1,74 @@
+//! Checks phase modulated oscillators and the FM operators built on them
+
+mod common;
+
+use vstlisp::render::{self, NoteEvent, NoteEventKind, RenderOptions};
+
+use common::{SAMPLE_RATE, amplitude, node_count};
+
+const WINDOW: usize = 4410;
+
+/// Plays key 64, which is 440hz, through the patch
+fn play(patch: &str) -> Vec<f32> {
+    let events = [NoteEvent { time: 0.0, kind: NoteEventKind::On { key: 64, velocity: 1.0 } }];
+    let options = RenderOptions { length: 2.0 * WINDOW as f32 / SAMPLE_RATE, ..RenderOptions::default() };
+    render::render(patch, &events, &options).unwrap()
+}
+
+#[test]
+fn phase_offsets_shift_the_oscillator() {
+    // The phase has moved on a sample by the first output
+    let phase = |i: usize| ((i + 1) as f32 / 100.0 + 0.25).fract();
+
+    let sine = common::render("(output (sin 441 0.25))", 200);
+    for (i, &x) in sine.iter().enumerate() {
+        let expected = (2.0 * std::f32::consts::PI * phase(i)).sin();
+        assert!((x - expected).abs() < 1.0e-4, "sample {} is {}, expected {}", i, x, expected);
+    }
+
+    let triangle = common::render("(output (tri 441 0.25))", 200);
+    for (i, &x) in triangle.iter().enumerate() {
+        let t = phase(i);
+        let expected = if t < 0.25 { 4.0 * t } else if t < 0.75 { 2.0 - 4.0 * t } else { 4.0 * t - 4.0 };
+        assert!((x - expected).abs() < 1.0e-4, "sample {} is {}, expected {}", i, x, expected);
+    }
+}
+
+#[test]
+fn sidebands_follow_bessel_functions() {
+    // A modulation index of 1 at three times the carrier, so the sidebands don't overlap
+    let samples = play("(output (fm-op 1 1 1 (fm-op 3 0 1)))");
+    let window = &samples[WINDOW..];
+
+    let expected = [(440.0, 0.7652), (1320.0, 0.0), (880.0, 0.4401), (1760.0, 0.4401), (2200.0, 0.1149), (3080.0, 0.1149)];
+    for &(freq, bessel) in &expected {
+        let measured = amplitude(window, freq);
+        assert!((measured - bessel).abs() < 0.01, "{}hz has amplitude {}, expected {}", freq, measured, bessel);
+    }
+}
+
+#[test]
+fn templates_match_nested_operators() {
+    let stack = play("(output (- (fm-stack [1 3 5] [1 2] (key-vel)) \
+        (fm-op 1 1 (key-vel) (fm-op 3 2 (key-vel) (fm-op 5 0 (key-vel))))))");
+    assert!(stack.iter().all(|&x| x == 0.0));
+
+    let parallel = play("(output (- (fm-parallel [1 2] 1.5 1 (sin 100)) \
+        (mix [(fm-op 1 1.5 1 (sin 100)) (fm-op 2 1.5 1 (sin 100))])))");
+    assert!(parallel.iter().all(|&x| x == 0.0));
+}
+
+#[test]
+fn feedback_stays_within_its_level() {
+    let samples = play("(output (fm-feedback 1 3 0.5))");
+    assert!(samples.iter().any(|&x| x != 0.0));
+    assert!(samples.iter().all(|x| x.abs() <= 0.5));
+}
+
+#[test]
+fn phase_modulation_stays_cheap() {
+    // An accumulator and a quarter cycle polynomial for the sine, while the operators of a
+    // stack share the key's phase between them
+    assert!(node_count("(output (sin 220 0.1))") <= 19);
+    assert!(node_count("(output (fm-stack [1 2 3 7] [2 1 0.5] (key-vel)))") <= 71);
+}
@@ -0,0 +1,113 @@
+//! Renders a short snippet for each builtin, with key 64 held, and compares it with a
+//! reference buffer in tests/golden, so changes to how things sound don't go unnoticed.
+//!
+//! `cargo test --test golden -- --bless` regenerates the references
+
+mod common;
+
+use vstlisp::VstResult;
+use vstlisp::render::{self, NoteEvent, NoteEventKind, RenderOptions};
+
+use common::SAMPLE_RATE;
+
+use failure::{ensure, format_err};
+
+use std::path::Path;
+use std::process;
+
+const LENGTH: f32 = 0.25;
+const TOLERANCE: f32 = 1.0e-4;
+
+const CASES: &[(&str, &str)] = &[
+    ("sin", "(output (sin 220))"),
+    ("tri", "(output (tri 220))"),
+    ("sqr", "(output (sqr 220))"),
+    ("saw", "(output (saw 220))"),
+    ("lp", "(output (lp 800 (saw 110)))"),
+    ("hp", "(output (hp 800 (saw 110)))"),
+    ("env-ar", "(output (* (env-ar 0.01 0.05 (sqr 6)) (sin 440)))"),
+    ("env-adsr", "(output (* (env-adsr 0.01 0.02 0.5 0.05 (sqr 6)) (sin 440)))"),
+    ("sequencer", "(output (sin (sequencer [220 330 440 550] (sqr 16))))"),
+    ("bake", "(output (bake 0.1 (output (* (env-ar 0.01 0.05 1) (saw 110)))))"),
+    ("mix", "(output (mix (sin 220) (sqr 330) (tri 2)))"),
+    ("clamp", "(output (clamp (* 2 (sin 220)) -0.5 0.5))"),
+    ("saw-bl", "(output (saw-bl 220))"),
+    ("sqr-bl", "(output (sqr-bl 220 (+ 0.5 (* 0.4 (sin 3)))))"),
+    ("wavetable", "(output (wavetable [[0 0.7 1 0.7 0 -0.7 -1 -0.7] [1 1 1 1 -1 -1 -1 -1]] 220 (* 0.5 (+ 1 (sin 4)))))"),
+    ("fm-op", "(output (fm-op 1 3 (env-ar 0.001 0.2 (key-vel)) (fm-op 3.5 0 (key-vel))))"),
+    ("noise", "(output (mix (noise 'white 1) (noise 'pink 2) (noise 'brown 3)))"),
+    ("random-step", "(output (sin (+ 440 (* 200 (random-step 16 1)))))"),
+    ("svf", "(output (svf 'bp (+ 1200 (* 900 (sin 4))) 8 (saw 110)))"),
+    ("ladder", "(output (ladder (+ 800 (* 600 (sin 4))) 0.8 1.5 (saw 110)))"),
+    ("delay", "(output (delay 0.01 0.5 (* (env-ar 0.001 0.02 (sqr 2)) (saw 220))))"),
+    ("chorus", "(output (chorus 0.8 0.7 0.5 (saw 220)))"),
+    ("flanger", "(output (flanger 2 0.8 0.7 (saw 220)))"),
+    ("phaser", "(output (phaser 6 2 0.8 (saw 220)))"),
+    ("tanh", "(output (tanh (* 4 (saw 220))))"),
+    ("softclip", "(output (softclip 3 (saw 220)))"),
+    ("fold", "(output (fold (* 3 (sin 220))))"),
+    ("crush", "(output (crush 3 (sin 220)))"),
+    ("decimate", "(output (decimate 3000 (saw 220)))"),
+    ("shaper", "(output (shaper [-1 -0.2 0 0.2 1] (sin 220)))"),
+    ("sample", "(output (sample \"{assets}/kick.wav\"))"),
+];
+
+fn main() {
+    let bless = std::env::args().any(|a| a == "--bless");
+    let manifest_dir = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let reference_dir = manifest_dir.join("tests/golden");
+    let assets_dir = manifest_dir.join("assets");
+
+    let mut failures = Vec::new();
+
+    for &(name, patch) in CASES {
+        let path = reference_dir.join(format!("{}.wav", name));
+        let patch = patch.replace("{assets}", &assets_dir.display().to_string());
+
+        match check(&patch, &path, bless) {
+            Ok(()) => println!("golden {} ... ok", name),
+            Err(err) => {
+                println!("golden {} ... FAILED", name);
+                failures.push(format!("{}: {}", name, err));
+            }
+        }
+    }
+
+    if !failures.is_empty() {
+        eprintln!("\nfailures:");
+        for failure in failures.iter() {
+            eprintln!("    {}", failure);
+        }
+
+        process::exit(1);
+    }
+}
+
+fn check(patch: &str, path: &Path, bless: bool) -> VstResult<()> {
+    let events = [NoteEvent { time: 0.0, kind: NoteEventKind::On { key: 64, velocity: 1.0 } }];
+    let options = RenderOptions { sample_rate: SAMPLE_RATE, length: LENGTH, ..RenderOptions::default() };
+    let samples = render::render(patch, &events, &options)?;
+
+    if bless {
+        std::fs::create_dir_all(path.parent().unwrap())?;
+        return render::write_wav(path, &samples, SAMPLE_RATE as u32, 32)
+    }
+
+    let reference = hound::WavReader::open(path)
+        .map_err(|e| format_err!("Couldn't read {}: {}, run with --bless to create it", path.display(), e))?
+        .into_samples::<f32>()
+        .collect::<Result<Vec<_>, _>>()?;
+
+    ensure!(reference.len() == samples.len(),
+        "Rendered {} samples, but the reference has {}", samples.len(), reference.len());
+
+    let (index, difference) = samples.iter().zip(reference.iter())
+        .map(|(a, b)| (a - b).abs())
+        .enumerate()
+        .fold((0, 0.0), |worst, (i, d)| if d > worst.1 { (i, d) } else { worst });
+
+    ensure!(difference <= TOLERANCE,
+        "Sample {} differs from the reference by {}", index, difference);
+
+    Ok(())
+}
@@ -0,0 +1,59 @@
+//! Checks the modulation effects' delays and notches, and that their stereo sides differ
+
+mod common;
+
+use common::{peak, max_difference, assert_stereo_sides_differ, node_count};
+
+const WINDOW: usize = 4410;
+
+fn render(patch: &str) -> Vec<f32> {
+    common::render_settled(patch, WINDOW)
+}
+
+#[test]
+fn chorus_without_depth_is_a_plain_delay() {
+    let chorus = render("(output (chorus 1 0 1 (sin 100)))");
+    let delayed = render("(output (delay 0.0035 0 (sin 100)))");
+
+    let difference = max_difference(&chorus, &delayed);
+    assert!(difference < 1.0e-5, "chorus differs from a delay by {}", difference);
+}
+
+#[test]
+fn phaser_notches_where_the_stages_turn_half_a_cycle() {
+    // Without depth, two stages sit at 200hz, where they turn the phase by half a cycle
+    let notched = peak(&render("(output (phaser 2 1 0 (sin 200)))"));
+    let passed = peak(&render("(output (phaser 2 1 0 (sin 10000)))"));
+
+    assert!(notched < 0.01, "phaser passes {} at its notch", notched);
+    assert!(passed > 0.9, "phaser passes only {} far from its notch", passed);
+}
+
+#[test]
+fn flanger_feedback_stays_stable() {
+    for &feedback in &[-1.0, 1.0] {
+        let samples = render(&format!("(output (flanger 5 1 {} (noise 'white 1)))", feedback));
+        assert!(samples.iter().all(|x| x.is_finite()) && peak(&samples) < 20.0, "flanger blew up with {} feedback", feedback);
+    }
+}
+
+#[test]
+fn stereo_sides_differ() {
+    let effects = [("chorus", "2 1 1"), ("flanger", "2 1 0.5"), ("phaser", "4 2 1")];
+
+    for &(effect, args) in &effects {
+        let stereo = format!("({}-stereo {} (saw 110))", effect, args);
+        let mono = format!("({} {} (saw 110))", effect, args);
+
+        assert_stereo_sides_differ(&stereo, &mono, 2 * WINDOW);
+    }
+}
+
+#[test]
+fn delay_effects_stay_cheap() {
+    let chorus = node_count("(output (chorus 1 1 0.5 (saw 110)))");
+    let flanger = node_count("(output (flanger 1 1 0.5 (saw 110)))");
+
+    assert!(chorus <= 700, "chorus costs {} nodes", chorus);
+    assert!(flanger <= 600, "flanger costs {} nodes", flanger);
+}
@@ -0,0 +1,70 @@
+//! Checks that noise is reproducible and has the right spectrum for its colour
+
+mod common;
+
+use common::{SAMPLE_RATE, render, render_settled, bin_energy, energy};
+
+const WINDOW: usize = 8192;
+
+/// Average energy per bin between two frequencies, in dB
+fn band_level(samples: &[f32], low: f32, high: f32) -> f64 {
+    let bin_width = SAMPLE_RATE / samples.len() as f32;
+    let bins = (low / bin_width) as usize..(high / bin_width) as usize;
+    let count = bins.len() as f64;
+
+    let energy: f64 = bins.map(|bin| bin_energy(samples, bin)).sum();
+
+    10.0 * (energy / count).log10()
+}
+
+/// How much quieter each octave is than the one below, from 100hz up to 12.8khz
+fn slope_per_octave(colour: &str) -> f64 {
+    let window = render_settled(&format!("(output (noise '{} 1))", colour), WINDOW);
+    (band_level(&window, 100.0, 200.0) - band_level(&window, 6400.0, 12800.0)) / 6.0
+}
+
+#[test]
+fn seeded_noise_is_reproducible() {
+    assert_eq!(render("(output (noise 'white 3))", WINDOW), render("(output (noise 'white 3))", WINDOW));
+    assert_ne!(render("(output (noise 'white 3))", WINDOW), render("(output (noise 'white 4))", WINDOW));
+
+    // Unseeded sources differ from each other, but not between renders
+    let unseeded = "(output (- (noise 'pink) (noise 'pink)))";
+    assert!(render(unseeded, WINDOW).iter().any(|&x| x != 0.0));
+    assert_eq!(render(unseeded, WINDOW), render(unseeded, WINDOW));
+
+    // Nor do they share a seed with any seeded source
+    assert_ne!(render("(output (noise 'white))", WINDOW), render("(output (noise 'white 0))", WINDOW));
+}
+
+#[test]
+fn white_noise_is_uncorrelated() {
+    let samples = render("(output (noise 'white 1))", 4 * WINDOW);
+
+    let lagged: f32 = samples.windows(2).map(|pair| pair[0] * pair[1]).sum();
+    let correlation = lagged / energy(&samples);
+    assert!(correlation.abs() < 0.03, "neighbouring samples have a correlation of {}", correlation);
+
+    // It doesn't repeat after either of its tables
+    let samples = render("(output (noise 'white 1))", 65536 + WINDOW);
+    assert_ne!(&samples[65521..65521 + WINDOW], &samples[..WINDOW]);
+    assert_ne!(&samples[65536..], &samples[..WINDOW]);
+}
+
+#[test]
+fn noise_colours_have_the_right_slope() {
+    for &(colour, expected) in &[("white", 0.0), ("pink", 3.0), ("brown", 6.0)] {
+        let slope = slope_per_octave(colour);
+        assert!((slope - expected).abs() < 1.0, "{} noise falls {}dB per octave, expected {}", colour, slope, expected);
+    }
+}
+
+#[test]
+fn random_step_holds_between_steps() {
+    // Steps at 0, 4410, 8820 and 13230
+    let mut values = render("(output (random-step 10 2))", 2 * WINDOW);
+    values.dedup();
+
+    assert_eq!(values.len(), 4);
+    assert!(values.iter().all(|x| x.abs() <= 1.0));
+}
@@ -0,0 +1,122 @@
+//! Checks where sample playback starts, how fast it goes, and how it loops
+
+mod common;
+
+use vstlisp::lisp;
+use vstlisp::render::{self, RenderOptions};
+
+use common::{SAMPLE_RATE, render};
+
+use std::path::PathBuf;
+use std::sync::Once;
+
+const RAMP_LENGTH: usize = 4410;
+
+/// A ramp that goes up by 1/1000 each sample, at the synth's sample rate so it isn't resampled
+fn ramp_file() -> PathBuf {
+    static WRITE: Once = Once::new();
+
+    let dir = std::env::temp_dir().join("vst-lisp-sample-test");
+    let path = dir.join("ramp.wav");
+
+    WRITE.call_once(|| {
+        std::fs::create_dir_all(&dir).unwrap();
+        let ramp = (0..RAMP_LENGTH).map(|i| i as f32 / 1000.0).collect::<Vec<_>>();
+        render::write_wav(&path, &ramp, SAMPLE_RATE as u32, 32).unwrap();
+    });
+
+    path
+}
+
+fn play(args: &str, samples: usize) -> Vec<f32> {
+    render(&format!("(output (sample \"{}\" {}))", ramp_file().display(), args), samples)
+}
+
+fn assert_ramp(samples: &[f32], expected: impl Fn(usize) -> f32) {
+    for (i, &x) in samples.iter().enumerate() {
+        assert!((x - expected(i)).abs() < 1.0e-5, "sample {} is {}, expected {}", i, x, expected(i));
+    }
+}
+
+#[test]
+fn playback_begins_at_start_and_ends_in_silence() {
+    let samples = play("0.05", RAMP_LENGTH);
+
+    assert_ramp(&samples[..RAMP_LENGTH - 2205], |i| (i + 2205) as f32 / 1000.0);
+    assert!(samples[RAMP_LENGTH - 2205..].iter().all(|&x| x == 0.0));
+
+    assert!(lisp::compile(&format!("(output (sample \"{}\" 0.1))", ramp_file().display()), SAMPLE_RATE).is_err());
+}
+
+#[test]
+fn rate_scales_the_speed() {
+    // Resampling smooths the corners at the ends of the ramp, so only the middle is exact
+    let fast = play("0 2", 2000);
+    for (i, &x) in fast.iter().enumerate().take(1900).skip(100) {
+        assert!((x - (2 * i) as f32 / 1000.0).abs() < 1.0e-3, "sample {} is {}", i, x);
+    }
+
+    let slow = play("0 0.25", 2000);
+    for (i, &x) in slow.iter().enumerate().skip(100) {
+        assert!((x - i as f32 / 4000.0).abs() < 1.0e-3, "sample {} is {}", i, x);
+    }
+
+    assert!(lisp::compile(&format!("(output (sample \"{}\" 0 0))", ramp_file().display()), SAMPLE_RATE).is_err());
+    assert!(lisp::compile(&format!("(output (sample \"{}\" 0 100))", ramp_file().display()), SAMPLE_RATE).is_err());
+}
+
+#[test]
+fn rate_is_fixed_when_the_patch_loads() {
+    let patch = format!("(output (sample \"{}\" 0 (/ (key-freq) 440)))", ramp_file().display());
+    assert!(lisp::compile(&patch, SAMPLE_RATE).is_err());
+}
+
+#[test]
+fn loops_repeat_until_the_end() {
+    // The loop runs from 1000 to 1500, and is played from 1200
+    let samples = play("0.0272109 1 0.0226757 0.0340136", 10 * RAMP_LENGTH);
+
+    assert_ramp(&samples, |i| {
+        let position = 1200 + i;
+        let position = if position < 1500 { position } else { 1000 + (position - 1500) % 500 };
+        position as f32 / 1000.0
+    });
+}
+
+#[test]
+fn loops_are_reached_from_before_them() {
+    let samples = play("0 1 0.0226757 0.0340136", 4000);
+
+    assert_ramp(&samples, |i| {
+        let position = if i < 1500 { i } else { 1000 + (i - 1500) % 500 };
+        position as f32 / 1000.0
+    });
+}
+
+#[test]
+fn loops_keep_every_sample() {
+    // 497 samples, which doesn't divide into anything
+    let samples = play("0 1 0 0.0112698", 2000);
+    assert_ramp(&samples, |i| (i % 497) as f32 / 1000.0);
+}
+
+#[test]
+fn loops_stop_after_ten_seconds() {
+    let samples = play("0 1 0 0.0112698", 10 * 44100 + 100);
+
+    assert!(samples[10 * 44100 - 100..10 * 44100].iter().any(|&x| x != 0.0));
+    assert!(samples[10 * 44100 + 1..].iter().all(|&x| x == 0.0));
+}
+
+#[test]
+fn paths_are_relative_to_the_patch() {
+    let path = ramp_file();
+    let patch = "(output (sample \"ramp.wav\"))";
+
+    let graph = lisp::compile_in(patch, SAMPLE_RATE, path.parent().unwrap()).unwrap();
+    let options = RenderOptions { length: 0.01, ..RenderOptions::default() };
+    let samples = render::render_graph(&graph, &[], &options).unwrap();
+    assert_ramp(&samples, |i| i as f32 / 1000.0);
+
+    assert!(lisp::compile(patch, SAMPLE_RATE).is_err());
+}
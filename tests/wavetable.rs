@@ -0,0 +1,116 @@
+//! Checks that wavetables keep their harmonics at low pitches and don't alias at high ones
+
+mod common;
+
+use vstlisp::lisp;
+
+use common::{SAMPLE_RATE, amplitude, max_difference, node_count, render, render_settled};
+
+use std::f64::consts::PI;
+
+const WINDOW: usize = 4410;
+
+/// A falling saw, a whole frame long so that none of its harmonics are lost
+const SAW: &str = "(repeat 2048 (fn (i) (- 1 (/ i 1024))))";
+
+fn saw_amplitude(harmonic: usize) -> f64 {
+    2.0 / (PI * harmonic as f64)
+}
+
+#[test]
+fn low_notes_keep_their_harmonics() {
+    let samples = render_settled(&format!("(output (wavetable {} 50))", SAW), WINDOW);
+
+    for &harmonic in &[1, 2, 10, 40, 60, 64] {
+        let measured = amplitude(&samples, 50.0 * harmonic as f32);
+        let expected = saw_amplitude(harmonic);
+        assert!((measured - expected).abs() < 0.05 * expected,
+            "harmonic {} has amplitude {}, expected {}", harmonic, measured, expected);
+    }
+
+    // Past the last harmonic a table keeps
+    assert!(amplitude(&samples, 50.0 * 65.0) < 1.0e-3);
+}
+
+#[test]
+fn high_notes_dont_alias() {
+    let samples = render_settled(&format!("(output (wavetable {} 5000))", SAW), WINDOW);
+
+    assert!((amplitude(&samples, 5000.0) - saw_amplitude(1)).abs() < 0.01);
+
+    // Where the fifth, sixth and seventh harmonics would fold back to
+    for &alias in &[19100.0, 14100.0, 9100.0] {
+        let measured = amplitude(&samples, alias);
+        assert!(measured < 1.0e-3, "{}hz has amplitude {}", alias, measured);
+    }
+}
+
+#[test]
+fn position_crossfades_frames() {
+    let sine = "[0 1 0 -1]";
+    let inverted = "[0 -1 0 1]";
+    let table = format!("[{} {}]", sine, inverted);
+
+    let first = render(&format!("(output (wavetable {} 441 0))", table), 400);
+    let last = render(&format!("(output (wavetable {} 441 1))", table), 400);
+    let middle = render(&format!("(output (wavetable {} 441 0.5))", table), 400);
+
+    for (i, &x) in first.iter().enumerate() {
+        let expected = (2.0 * PI * (i + 1) as f64 / 100.0).sin() as f32;
+        assert!((x - expected).abs() < 1.0e-3, "sample {} is {}, expected {}", i, x, expected);
+    }
+
+    assert!(max_difference(&first, &last.iter().map(|x| -x).collect::<Vec<_>>()) < 1.0e-3);
+    assert!(middle.iter().all(|x| x.abs() < 1.0e-3));
+}
+
+#[test]
+fn phase_mode_follows_the_phase() {
+    // A quarter of the way through a sine's cycle, however many cycles it's wrapped by
+    for &phase in &[0.25, 1.25, -0.75] {
+        let samples = render(&format!("(output (wavetable [0 1 0 -1] \"phase\" {} 0))", phase), 100);
+        assert!(samples.iter().all(|x| (x - 1.0).abs() < 1.0e-3), "phase {} gave {:?}", phase, &samples[..4]);
+    }
+
+    // A phase ramping by 1/128 a sample plays the same as the matching frequency, down to
+    // the band limiting. The step is exact in f32, so the ramp doesn't drift
+    let ramp = format!("(def-store p) (let next (+ p 0.0078125)) (store p next) \
+        (output (wavetable {} \"phase\" next 0))", SAW);
+    let by_phase = render(&ramp, 1000);
+    let by_freq = render(&format!("(output (wavetable {} {}))", SAW, SAMPLE_RATE / 128.0), 1000);
+    assert!(max_difference(&by_phase, &by_freq) < 1.0e-3);
+
+    assert!(lisp::compile(&format!("(output (wavetable {} \"speed\" 1 0))", SAW), SAMPLE_RATE).is_err());
+}
+
+#[test]
+fn long_tables_are_resampled() {
+    // Nine cycles rising evenly from silence to full level, which resample to eight that
+    // still rise evenly. Picking the nearest cycles instead would give 3/8 for the fourth
+    let frames = (0..9)
+        .map(|i| format!("(map (fn (x) (* {} x)) [0 1 0 -1])", i as f32 / 8.0))
+        .collect::<Vec<_>>()
+        .join(" ");
+
+    for &(position, level) in &[(0.0, 0.0), (3.0 / 7.0, 3.0 / 7.0), (1.0, 1.0)] {
+        let samples = render(&format!("(output (wavetable [{}] 441 {}))", frames, position), 100);
+        assert!((common::peak(&samples) - level).abs() < 1.0e-3, "position {} peaks at {}, expected {}",
+            position, common::peak(&samples), level);
+    }
+}
+
+#[test]
+fn tables_stay_cheap() {
+    let sine = node_count("(output (wavetable [0 1 0 -1] (key-freq)))");
+    let saw = node_count(&format!("(output (wavetable {} (key-freq)))", SAW));
+    // Saws at different levels, so that their frames can't be merged
+    let frames = (0..8)
+        .map(|i| format!("(repeat 2048 (fn (i) (* {} (- 1 (/ i 1024)))))", 1.0 - i as f32 / 8.0))
+        .collect::<Vec<_>>()
+        .join(" ");
+    let table = node_count(&format!("(output (wavetable [{}] (key-freq) (key-vel)))", frames));
+
+    assert!(sine <= 40, "a sine costs {} nodes", sine);
+    assert!(saw <= 600, "a saw costs {} nodes", saw);
+    assert!(table <= 2500, "eight saws cost {} nodes", table);
+}